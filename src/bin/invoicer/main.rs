@@ -1,88 +1,445 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use invoicer::invoicer::{Invoicer, Config};
+use invoicer::invoicer::{Invoicer, Config, HasDirectories};
 use invoicer::worklog::Worklog;
+use invoicer::error::InvoicerError;
+use invoicer::schema::SchemaKind;
 use invoicer::helpers::*;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum StdinFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl std::fmt::Display for StdinFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StdinFormat::Csv => f.write_str("CSV"),
+            StdinFormat::Json => f.write_str("JSON"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SchemaFile {
+    Config,
+    Recipient,
+    Locale,
+}
+
+impl From<SchemaFile> for SchemaKind {
+    fn from(file: SchemaFile) -> Self {
+        match file {
+            SchemaFile::Config => SchemaKind::Config,
+            SchemaFile::Recipient => SchemaKind::Recipient,
+            SchemaFile::Locale => SchemaKind::Locale,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the invoice fingerprints
+    Fingerprints {
+        #[command(subcommand)]
+        command: FingerprintsCommand,
+    },
+    /// Load and list all locales of the locale directory
+    Locales {
+        /// Also list the translation keys missing compared to this locale
+        #[arg(long, num_args = 0..=1, default_missing_value = "en")]
+        diff: Option<String>,
+    },
+    /// List the generated invoices of a recipient with the running total
+    Ledger {
+        /// Name of the recipient
+        recipient: String,
+    },
+    /// Write a commented recipient TOML <NAME>.toml into the tag directory
+    NewRecipient {
+        /// Name of the recipient, also its tag in worklogs
+        name: String,
+        /// Overwrite an existing recipient TOML
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print what the worklogs would total at a rate, treating each tag as a recipient, without writing files
+    Estimate {
+        /// Hourly rate applied to all billable hours
+        #[arg(long)]
+        rate: f32,
+        /// Worklog CSV file or glob pattern, in addition to the worklogs given before the command
+        #[arg(short, long)]
+        worklog: Vec<String>,
+    },
+    /// Print a commented example TOML with all fields and their defaults
+    Schema {
+        /// Kind of TOML file
+        file: SchemaFile,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FingerprintsCommand {
+    /// Rebuild fingerprints.toml from the tex files in the invoice directory
+    Rebuild,
+}
 
 #[derive(Parser, Debug)]
 #[command(author="Michael Winkelmann", version, about="Invoicer")]
 struct Arguments{
-    /// Worklog CSV file
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Worklog CSV file or glob pattern like 'worklogs/2024-*.csv'
     #[arg(short, long)]
     worklog: Vec<String>,
 
+    /// Directory whose CSV files are all loaded as worklogs, e.g. one file per day
+    #[arg(long)]
+    worklog_dir: Vec<String>,
+
     /// Recipient TOML file (optional)
     #[arg(short, long)]
     recipient_toml: Vec<String>,
 
-    /// Optional latex output file
+    /// Only generate the invoice of the recipient with this name, can be given several times
+    #[arg(long)]
+    only: Vec<String>,
+
+    /// Directory for the generated invoices, overrides the configured invoice directory.
+    /// Placeholders like ${YEAR} are replaced as in the config.
     #[arg(short = 'o', long)]
     output_dir: Option<String>,
 
-    /// Optional config file. 
-    #[arg(short, long, default_value = "invoicer.toml")]
-    config: String,
+    /// Optional config file, merged over ~/invoicer.toml and ./invoicer.toml
+    #[arg(short, long)]
+    config: Option<String>,
 
     /// Optional counter for the invoice to generate an invoice number
     #[arg(short = 'n', long)]
     counter: Option<u32>,
 
+    /// Optional rate for all recipients, overrides the configured default rates
+    #[arg(long)]
+    rate: Option<f32>,
+
+    /// Optional deposit already paid, as gross amount
+    #[arg(long)]
+    deposit: Option<f32>,
+
+    /// Optional days for payment of all invoices, overrides the recipients and the config
+    #[arg(long)]
+    days_for_payment: Option<u32>,
+
+    /// Optional purchase order number of all invoices, overrides the recipients
+    #[arg(long = "po")]
+    po_number: Option<String>,
+
+    /// Optional template for all invoices, relative to the template directory
+    #[arg(long)]
+    template: Option<String>,
+
+    /// Locale for all invoices, e.g. de, overrides the locales of the recipients and the config
+    #[arg(long)]
+    locale: Option<String>,
+
+    /// Name of a promotion of the [[promotions]] list to apply as discount to all invoices
+    #[arg(long)]
+    promo: Option<String>,
+
+    /// Directory searched for templates before the configured ones, can be given several times
+    #[arg(long)]
+    template_path: Vec<String>,
+
+    /// File attached to all invoices, listed by its name in the invoice, can be given several times
+    #[arg(long)]
+    attachment: Vec<String>,
+
+    /// Combine the PDFs of all generated invoices into this file
+    #[arg(long)]
+    combine: Option<String>,
+
+    /// Write a report comparing the worklog hours with the billed hours to this TOML file
+    #[arg(long)]
+    reconcile: Option<String>,
+
     /// Optional invoice date in format %Y-m%-%d. If no date is given, current date is used.
     #[arg(short = 'd', long)]
     date: Option<String>,
 
+    /// Mark the invoices as paid
+    #[clap(long, action)]
+    paid: bool,
+
+    /// Optional currency to convert the invoice totals into, e.g. EUR
+    #[arg(long)]
+    report_currency: Option<String>,
+
+    /// Print the tex output of a single invoice to stdout instead of writing files
+    #[clap(long, action)]
+    print: bool,
+
+    /// Fail if a template contains unknown tokens or lacks required ones like %$INVOICE_POSITIONS
+    #[clap(long, action)]
+    strict_template: bool,
+
+    /// Exit with an error if any invoice was skipped, e.g. a recipient without positions
+    #[clap(long, action)]
+    strict: bool,
+
+    /// Print the effective config as TOML, with absolute directories and all defaults filled in, and exit
+    #[clap(long, action)]
+    print_config: bool,
+
+    /// Print the fingerprint of each invoice next to its number
+    #[clap(short, long, action)]
+    verbose: bool,
+
+    /// Regenerate the invoices whenever a worklog, recipient, config, template, tag or locale file changes.
+    /// Invoices are written to a scratch directory unless --output-dir is given, no fingerprints are stored.
+    #[clap(long, action, conflicts_with_all = ["stdin", "print"])]
+    watch: bool,
+
     /// Read from stdin
     #[clap(long, action)]
     stdin: bool,
+
+    /// Format of the worklog read from stdin
+    #[arg(long, value_enum, default_value_t = StdinFormat::Csv)]
+    stdin_format: StdinFormat,
+
+    /// Optional delimiter for worklog CSV files, e.g. ';'
+    #[arg(long)]
+    csv_delimiter: Option<char>,
 }
 
 
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Arguments::parse();
+/// Directory for the invoices generated by `--watch` if no output directory is given.
+fn watch_dir() -> PathBuf {
+    std::env::temp_dir().join("invoicer-watch")
+}
+
+/// Read the config files and apply the overrides given as arguments, without validating the config.
+fn load_config(args: &Arguments) -> Result<Config, InvoicerError> {
+    let mut config = Config::from_toml_files(args.config.as_ref().map(PathBuf::from))?;
 
-    let mut config = Config::from_toml_files(Some(PathBuf::from(args.config)))?;
-    
-    if let Some(output_dir) = args.output_dir {
+    if let Some(output_dir) = &args.output_dir {
         config.set_invoice_dir(PathBuf::from(output_dir));
+    } else if args.watch {
+        config.set_invoice_dir(watch_dir());
     }
 
-    let date = match args.date {
+    if let Some(csv_delimiter) = args.csv_delimiter {
+        config.set_csv_delimiter(csv_delimiter);
+    }
+
+    Ok(config)
+}
+
+fn validate_config(config: &Config) -> Result<(), InvoicerError> {
+    let issues = config.validate();
+    if !issues.is_empty() {
+        for issue in &issues {
+            eprintln!("Config error: {issue}");
+        }
+        return Err(InvoicerError::InvalidConfig(format!("{} issue(s) found", issues.len())));
+    }
+    Ok(())
+}
+
+fn create_invoicer(args: &Arguments) -> Result<Invoicer, InvoicerError> {
+    let config = load_config(args)?;
+    validate_config(&config)?;
+
+    let date = match &args.date {
         Some(date_str) => {
-            DateTime::parse_from_str((date_str + " 00:00").as_str(), "%Y-%d-%m %H:%M").unwrap()
+            DateTime::parse_from_str((date_str.clone() + " 00:00").as_str(), "%Y-%d-%m %H:%M").unwrap()
         },
         None => now()
     };
 
-    let mut invoicer = Invoicer::new(config, Some(date), args.counter);
+    Ok(Invoicer::new(config, Some(date), args.counter))
+}
+
+/// Append the worklog CSV files, patterns like worklogs/2024-*.csv are expanded.
+fn append_worklogs(invoicer: &mut Invoicer, patterns: &[String]) -> Result<(), InvoicerError> {
+    for pattern in patterns {
+        let worklog_csvs = expand_glob(pattern)?;
+        for worklog_csv in &worklog_csvs {
+            invoicer.append_worklog_from_csv_file(&worklog_csv.to_string())?;
+        }
+        if worklog_csvs != [PathBuf::from(pattern)] {
+            eprintln!("{pattern}: {} worklog files loaded", worklog_csvs.len());
+        }
+    }
+    Ok(())
+}
+
+fn load_invoicer(args: &Arguments) -> Result<Invoicer, InvoicerError> {
+    let mut invoicer = create_invoicer(args)?;
+
+    if let Some(rate) = args.rate {
+        invoicer.set_rate(rate);
+    }
+
+    if let Some(deposit) = args.deposit {
+        invoicer.set_deposit(deposit);
+    }
+
+    if let Some(days) = args.days_for_payment {
+        invoicer.set_days_for_payment(days);
+    }
+
+    if let Some(po_number) = &args.po_number {
+        invoicer.set_po_number(po_number);
+    }
+
+    if let Some(template) = &args.template {
+        invoicer.set_template(template);
+    }
+
+    if let Some(locale) = &args.locale {
+        invoicer.set_locale(locale)?;
+    }
+
+    if let Some(promo) = &args.promo {
+        invoicer.set_promotion(promo)?;
+    }
+
+    invoicer.set_template_path(args.template_path.iter().map(PathBuf::from).collect());
+    invoicer.set_paid(args.paid);
+    invoicer.set_strict_template(args.strict_template);
+    invoicer.set_strict(args.strict);
+    invoicer.set_preview(args.watch);
+    invoicer.set_verbose(args.verbose);
+    invoicer.set_only(args.only.clone());
+    invoicer.set_attachments(args.attachment.clone());
+
+    if let Some(combine) = &args.combine {
+        invoicer.set_combine(combine.into());
+    }
+
+    if let Some(reconcile) = &args.reconcile {
+        invoicer.set_reconcile(reconcile.into());
+    }
+
+    if let Some(report_currency) = &args.report_currency {
+        invoicer.set_report_currency(report_currency.clone().into());
+    }
 
     // Create a merged worklog from all input worklogs
     // 1) Try to read worklog from stdin    
     if args.stdin {
-        match Worklog::from_csv(std::io::stdin()) {
+        let worklog = match args.stdin_format {
+            StdinFormat::Csv => Worklog::from_csv_with_config(std::io::stdin(), invoicer.config().worklog()),
+            StdinFormat::Json => Worklog::from_json(std::io::stdin()),
+        };
+        match worklog {
             Ok(worklog) => invoicer.append_worklog(&worklog),
-            Err(e) => eprintln!("Could not read worklog CSV from stdin: {e}"),
+            Err(e) => eprintln!("Could not read worklog {} from stdin: {e}", args.stdin_format),
         }
     }
 
     // 2) Try to read worklog from given commandline arguments
-    let worklog_csvs = args.worklog;
-    for worklog_csv in worklog_csvs {
-        invoicer.append_worklog_from_csv_file(&worklog_csv)?;
-    } 
+    append_worklogs(&mut invoicer, &args.worklog)?;
+    for worklog_dir in &args.worklog_dir {
+        invoicer.append_worklogs_from_dir(&PathBuf::from(worklog_dir))?;
+    }
 
     // 3) Create list of recipients from toml files
-    for recipient_toml in args.recipient_toml {
+    for recipient_toml in &args.recipient_toml {
         invoicer.add_recipient_from_toml_file::<PathBuf>(recipient_toml.into())?;
     }
 
-    // 4) Try to fetch recipients from worklogs
+    // 4) Try to fetch recipients from worklogs, using inline recipients of the config or tag files
     if !invoicer.has_recipients() {
         // If no recipient is given as command-line argument, try to fetch recipients from worklog
         invoicer.add_recipients_from_worklog();
     }
 
+    Ok(invoicer)
+}
+
+/// Files and directories watched by `--watch`.
+/// Glob patterns are expanded again before each run, so new worklog files are picked up.
+fn watched_paths(args: &Arguments) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("invoicer.toml")];
+    paths.extend(home::home_dir().map(|home_dir| home_dir.join("invoicer.toml")));
+    paths.extend(args.config.iter().map(PathBuf::from));
+    for pattern in &args.worklog {
+        paths.extend(expand_glob(pattern).unwrap_or_default());
+    }
+    paths.extend(args.worklog_dir.iter().map(PathBuf::from));
+    paths.extend(args.recipient_toml.iter().map(PathBuf::from));
+
+    if let Ok(mut invoicer) = create_invoicer(args) {
+        invoicer.set_template_path(args.template_path.iter().map(PathBuf::from).collect());
+        paths.extend(invoicer.template_search_path());
+        paths.extend([invoicer.tag_dir(), invoicer.locale_dir()]);
+    }
+    paths
+}
+
+fn watch(args: &Arguments) -> ! {
+    loop {
+        match load_invoicer(args).and_then(|invoicer| invoicer.generate()) {
+            Ok(()) => println!("Invoices generated in {}", args.output_dir.clone().map(PathBuf::from).unwrap_or(watch_dir()).display()),
+            Err(e) => eprintln!("Error: {e}"),
+        }
+        println!("Watching for changes...");
+        wait_for_changes(&watched_paths(args), Duration::from_millis(500), Duration::from_millis(300));
+    }
+}
+
+fn main() -> Result<(), InvoicerError> {
+    let args = Arguments::parse();
+
+    if let Some(command) = &args.command {
+        let invoicer = || create_invoicer(&args);
+        return match command {
+            Command::Fingerprints { command: FingerprintsCommand::Rebuild } => invoicer()?.rebuild_fingerprints(),
+            Command::Locales { diff } => invoicer()?.check_locales(diff.as_deref()),
+            Command::Ledger { recipient } => invoicer()?.print_ledger(recipient, &mut std::io::stdout()),
+            Command::NewRecipient { name, force } => invoicer()?.new_recipient(name, *force)
+                .map(|file| println!("{file:?} written")),
+            Command::Estimate { rate, worklog } => {
+                let mut invoicer = invoicer()?;
+                append_worklogs(&mut invoicer, &args.worklog)?;
+                append_worklogs(&mut invoicer, worklog)?;
+                print!("{}", invoicer.estimate(*rate));
+                Ok(())
+            }
+            // No config is needed for the example files
+            Command::Schema { file } => {
+                print!("{}", SchemaKind::from(*file).example_toml());
+                Ok(())
+            }
+        };
+    }
+
+    if args.print_config {
+        // Print the config before validating it, it helps finding out why it is invalid
+        let config = load_config(&args)?;
+        print!("{}", config.effective_toml()?);
+        return validate_config(&config);
+    }
+
+    if args.watch {
+        watch(&args);
+    }
+
+    let invoicer = load_invoicer(&args)?;
+
+    if args.print {
+        return invoicer.print(&mut std::io::stdout());
+    }
+
     invoicer.generate()
 }
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+/// Errors of the invoicer library.
+#[derive(Debug, thiserror::Error)]
+pub enum InvoicerError {
+    /// Reading or writing a file failed, e.g. because it does not exist
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    /// A TOML file could not be parsed
+    #[error("{0}")]
+    Toml(#[from] toml::de::Error),
+    /// A file could not be written as TOML
+    #[error("{0}")]
+    TomlWrite(#[from] toml::ser::Error),
+    /// A worklog CSV could not be parsed
+    #[error("{0}")]
+    Csv(#[from] csv::Error),
+    /// A worklog JSON could not be parsed
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+    /// The config contains invalid values
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    /// The worklog contains invalid records, e.g. wrong tag weights or missing rates
+    #[error("Invalid worklog: {0}")]
+    InvalidWorklog(String),
+    /// No recipient was given
+    #[error("No recipient given!")]
+    NoRecipients,
+    /// None of the recipients has an invoice with positions
+    #[error("No invoice with positions!")]
+    NoInvoices,
+    /// Recipients selected by name were not found
+    #[error("Unknown recipients: {0}")]
+    UnknownRecipients(String),
+    /// Several invoices of one run got the same invoice number
+    #[error("Invoice numbers assigned to several recipients: {0}")]
+    DuplicateInvoiceNumbers(String),
+    /// The payment QR code could not be created
+    #[error("Cannot create payment QR code: {0}")]
+    PaymentQr(String),
+    /// The PDFs of the invoices could not be combined
+    #[error("Cannot combine PDFs: {0}")]
+    CombinePdf(String),
+    /// Invoices of a strict run were skipped, e.g. recipients without positions or existing files
+    #[error("Invoices skipped: {0}")]
+    SkippedInvoices(String),
+    /// A directory cannot be created or written to.
+    /// `setting` is the configured directory, `path` the path after substituting variables like `${HOME}`.
+    #[error("Directory '{setting}' ({}) is not writable: {err}", .path.display())]
+    DirectoryNotWritable { setting: String, path: PathBuf, #[source] err: std::io::Error },
+}
@@ -12,6 +12,67 @@ pub fn generate_tex_command<'a>(mut w: &'a mut dyn Write, commandname: &str, con
     Ok(())
 }
 
+/// Generate a command for each entry of a map, sorted by key, e.g. `\legalcourt` for the key `court` and prefix `legal`.
+/// Characters other than ASCII letters are removed from the key, since TeX command names consist of letters only.
+pub fn generate_tex_commands_for_map(w: &mut dyn Write, prefix: &str, map: &HashMap<String, String>) -> std::io::Result<()> {
+    let mut keys = map.keys().collect::<Vec<_>>();
+    keys.sort();
+
+    for key in keys {
+        let name = key.chars().filter(|c| c.is_ascii_alphabetic()).collect::<String>();
+        generate_tex_command(w, format!("{prefix}{name}").as_str(), &map[key])?;
+    }
+
+    Ok(())
+}
+
+/// Truncate TeX text to `max_len` characters and append `\ldots{}` if it is longer.
+/// A control sequence like `\&` or `\textbf` counts as one character and is never cut,
+/// groups opened with `{` before the cut are closed again.
+pub fn truncate_tex(text: &str, max_len: usize) -> String {
+    // Split into tokens, which are control sequences or single characters
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((begin, c)) = chars.next() {
+        let mut end = begin + c.len_utf8();
+        if c == '\\' {
+            match chars.next() {
+                Some((i, next)) if next.is_ascii_alphabetic() => {
+                    end = i + 1;
+                    while let Some((i, _)) = chars.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+                        end = i + 1;
+                    }
+                }
+                Some((i, next)) => end = i + next.len_utf8(),
+                None => {}
+            }
+        }
+        tokens.push(&text[begin..end]);
+    }
+
+    let visible = tokens.iter().filter(|token| !matches!(**token, "{" | "}")).count();
+    if visible <= max_len {
+        return text.to_string();
+    }
+
+    let mut result = String::new();
+    let mut len = 0;
+    let mut depth = 0_usize;
+    for token in tokens {
+        match token {
+            "{" => depth += 1,
+            "}" => depth = depth.saturating_sub(1),
+            _ if len + 1 >= max_len => break,
+            _ => len += 1,
+        }
+        result += token;
+    }
+
+    result.truncate(result.trim_end().len());
+    result += &"}".repeat(depth);
+    result + "\\ldots{}"
+}
+
 pub trait GenerateTexCommands : Iterable {
     fn generate_tex_commands<'a>(&self, w: &'a mut dyn Write, prefix: &str) -> std::io::Result<()> {
         for (field_name, field_value) in self.iter() {
@@ -50,40 +111,255 @@ pub trait GenerateTex {
     fn template_dir(&self) -> PathBuf { PathBuf::from(".") }
 }
 
+/// Templates shipped with invoicer.
+/// They are used when a template or `\\input` file does not exist in the template directory.
+pub fn embedded_template(filename: &str) -> Option<&'static str> {
+    match filename {
+        "invoice.tex" => Some(include_str!("../templates/invoice.tex")),
+        "timesheet.tex" => Some(include_str!("../templates/timesheet.tex")),
+        "common.tex" => Some(include_str!("../templates/common.tex")),
+        _ => None
+    }
+}
+
+/// First existing file with the given name in the directories of the search path.
+pub fn find_in_search_path(search_path: &[PathBuf], filename: &str) -> Option<PathBuf> {
+    search_path.iter()
+        .map(|dir| dir.join(filename))
+        .find(|path| path.is_file())
+}
+
+/// Text of a template from the first directory of the search path containing it or from the embedded templates.
+pub fn template_text(search_path: &[PathBuf], filename: &str) -> Option<String> {
+    match find_in_search_path(search_path, filename) {
+        Some(path) => std::fs::read_to_string(path).ok(),
+        None => embedded_template(filename).map(String::from),
+    }
+}
+
+/// `\\input` files of a template text which can not be included, because they exist
+/// neither in the search path nor as embedded template.
+/// Like on generation, only `\\input` commands at the beginning of a line are considered.
+pub fn missing_inputs(text: &str, search_path: &[PathBuf]) -> Vec<String> {
+    text.lines()
+        .filter(|line| line.starts_with("\\input{"))
+        .map(|line| format!("{}.tex", line.replace("\\input{", "").replace("}", "")))
+        .filter(|filename| find_in_search_path(search_path, filename).is_none()
+            && !std::path::Path::new(filename).is_file()
+            && embedded_template(filename).is_none())
+        .collect()
+}
+
+/// Source of a template, either a file read on generation or an in-memory text.
+enum TemplateSource {
+    File(PathBuf),
+    Text(String),
+}
+
 pub struct TexTemplate<'a> {
-    filename: PathBuf,
-    tokens: std::collections::HashMap<String, Box<dyn Fn(&mut dyn Write) -> Result<(), std::io::Error> + 'a>>
+    source: TemplateSource,
+    tokens: std::collections::HashMap<String, Box<dyn Fn(&mut dyn Write) -> Result<(), std::io::Error> + 'a>>,
+    required: Vec<String>,
+    flags: HashMap<String, bool>,
+    strict: bool,
+    search_path: Vec<PathBuf>,
 }
 
 impl<'a> TexTemplate<'a> {
+    /// Create a template from a file, `\\input` files are searched in the directory of the file.
     pub fn new(filename: PathBuf) -> Self {
+        let dir = std::path::Path::parent(&filename).map(|dir| dir.to_path_buf()).unwrap_or_default();
+        Self {
+            source: TemplateSource::File(filename),
+            tokens: HashMap::new(),
+            required: Vec::new(),
+            flags: HashMap::new(),
+            strict: false,
+            search_path: vec![dir],
+        }
+    }
+
+    /// Create a template from an in-memory text, e.g. a template embedded with `include_str!`.
+    pub fn from_string(text: impl Into<String>) -> Self {
         Self {
-            filename: filename,
-            tokens: HashMap::new()
+            source: TemplateSource::Text(text.into()),
+            tokens: HashMap::new(),
+            required: Vec::new(),
+            flags: HashMap::new(),
+            strict: false,
+            search_path: Vec::new(),
         }
     }
 
+    /// Create a template from the first file with the given name in the directories of the search path.
+    /// If no directory contains the file, the embedded template with this name is used.
+    /// `\\input` files are looked up in the same search path.
+    pub fn from_search_path(search_path: &[PathBuf], filename: &str) -> Self {
+        let mut template = match find_in_search_path(search_path, filename) {
+            Some(path) => {
+                eprintln!("{filename}: Using template {:?}", path);
+                Self::new(path)
+            }
+            None => match embedded_template(filename) {
+                Some(text) => {
+                    eprintln!("{filename}: Template not found in {:?}, using embedded template.", search_path);
+                    Self::from_string(text)
+                }
+                None => {
+                    eprintln!("{filename}: Template not found in {:?}!", search_path);
+                    Self::new(search_path.first().cloned().unwrap_or_default().join(filename))
+                }
+            }
+        };
+        template.search_path = search_path.to_vec();
+        template
+    }
+
+    /// Create a template from a file.
+    /// If the file does not exist, the embedded template with the same file name is used.
+    pub fn from_file_or_embedded(filename: PathBuf) -> Self {
+        if !filename.exists() {
+            if let Some(text) = embedded_template(&filename.file_name()) {
+                eprintln!("{:?}: Template file does not exist, using embedded template.", filename);
+                return Self::from_string(text);
+            }
+        }
+        Self::new(filename)
+    }
+
+    /// Create a template by reading it completely from a reader.
+    pub fn from_reader(mut reader: impl std::io::BufRead) -> std::io::Result<Self> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok(Self::from_string(text))
+    }
+
     pub fn token(&mut self, name: &str, tag: impl Fn(&mut dyn Write) -> Result<(), std::io::Error> + 'a) -> &mut Self {
         self.tokens.insert(name.to_string(), Box::new(tag));
         self
     }
 
+    /// Register a token whose handler writes essential content, e.g. the invoice positions.
+    /// A template without this token is reported by `check_tokens`.
+    pub fn required_token(&mut self, name: &str, tag: impl Fn(&mut dyn Write) -> Result<(), std::io::Error> + 'a) -> &mut Self {
+        self.required.push(name.to_string());
+        self.token(name, tag)
+    }
+
+    /// Register a flag for conditional sections.
+    /// Lines between `%$IF:<name>` and `%$ENDIF` are only written if the flag is true, sections can be nested.
+    pub fn flag(&mut self, name: &str, value: bool) -> &mut Self {
+        self.flags.insert(name.to_string(), value);
+        self
+    }
+
+    /// In strict mode, `generate` fails if `check_tokens` finds problems instead of only printing warnings.
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Compare the tokens of the template with the registered handlers.
+    /// Returns the tokens without handler, e.g. misspelled ones or conditions on unknown flags,
+    /// and the required tokens missing in the template.
+    pub fn check_tokens(&self, text: &str) -> (Vec<String>, Vec<String>) {
+        let used = text.lines()
+            .filter_map(|line| Self::token_name_from_line(&line.to_string()))
+            .collect::<Vec<_>>();
+
+        let unknown = used.iter()
+            .filter(|token| match token.strip_prefix("IF:") {
+                Some(flag) => !self.flags.contains_key(flag.trim()),
+                None => token.as_str() != "ENDIF" && !self.tokens.contains_key(token.as_str()),
+            })
+            .cloned()
+            .collect();
+        let missing = self.required.iter()
+            .filter(|token| !used.contains(token))
+            .cloned()
+            .collect();
+        (unknown, missing)
+    }
+
     pub fn generate(&self, w: &mut dyn Write) -> std::io::Result<()> {
-        if let Ok(lines) = crate::helpers::read_lines(&self.filename) {
-            // Consumes the iterator, returns an (Optional) String
-            for line in lines {
-                if let Ok(line) = line {
-                    if line.starts_with("\\input{") {
-                        let filename = line.replace("\\input{", "").replace("}", "");
-                        self.inline_input(&filename, w)?;
+        let (name, text) = match &self.source {
+            TemplateSource::File(filename) => match std::fs::read_to_string(filename) {
+                Ok(text) => (format!("{:?}", filename), text),
+                Err(_) => return Ok(()),
+            }
+            TemplateSource::Text(text) => ("Embedded template".to_string(), text.clone()),
+        };
+
+        let (unknown, missing) = self.check_tokens(&text);
+        let mut problems = Vec::new();
+        if !unknown.is_empty() {
+            problems.push(format!("unknown tokens {}", unknown.join(", ")));
+        }
+        if !missing.is_empty() {
+            problems.push(format!("missing tokens {}", missing.join(", ")));
+        }
+        if !Self::balanced_conditions(&text) {
+            problems.push("unbalanced %$IF and %$ENDIF".to_string());
+        }
+        if !problems.is_empty() {
+            let message = format!("{name}: {}", problems.join("; "));
+            if self.strict {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+            }
+            eprintln!("{message}");
+        }
+
+        self.generate_from_reader(text.as_bytes(), w)
+    }
+
+    /// Every `%$IF` is closed by a later `%$ENDIF` and no `%$ENDIF` comes before its `%$IF`.
+    fn balanced_conditions(text: &str) -> bool {
+        let mut depth = 0_usize;
+        for token in text.lines().filter_map(|line| Self::token_name_from_line(&line.to_string())) {
+            if token.starts_with("IF:") {
+                depth += 1;
+            } else if token == "ENDIF" {
+                match depth.checked_sub(1) {
+                    Some(d) => depth = d,
+                    None => return false,
+                }
+            }
+        }
+        depth == 0
+    }
+
+    fn generate_from_reader(&self, reader: impl std::io::BufRead, w: &mut dyn Write) -> std::io::Result<()> {
+        // Values of the enclosing conditional sections, lines are skipped if any of them is false
+        let mut conditions: Vec<bool> = Vec::new();
+
+        // Consumes the iterator, returns an (Optional) String
+        for line in reader.lines() {
+            if let Ok(line) = line {
+                match Self::token_name_from_line(&line) {
+                    Some(token) if token.starts_with("IF:") => {
+                        conditions.push(self.flags.get(token["IF:".len()..].trim()).copied().unwrap_or(false));
                         continue;
                     }
-                    writeln!(w, "{}", line)?;                    
+                    Some(token) if token == "ENDIF" => {
+                        conditions.pop();
+                        continue;
+                    }
+                    _ => {}
+                }
+                if conditions.contains(&false) {
+                    continue;
+                }
 
-                    if let Some(line_template) =  Self::token_name_from_line(&line) {
-                        if let Some(handler) = self.tokens.get(line_template.as_str()) {
-                            handler(w)?;
-                        }
+                if line.starts_with("\\input{") {
+                    let filename = line.replace("\\input{", "").replace("}", "");
+                    self.inline_input(&filename, w)?;
+                    continue;
+                }
+                writeln!(w, "{}", line)?;                    
+
+                if let Some(line_template) =  Self::token_name_from_line(&line) {
+                    if let Some(handler) = self.tokens.get(line_template.as_str()) {
+                        handler(w)?;
                     }
                 }
             }
@@ -93,14 +369,23 @@ impl<'a> TexTemplate<'a> {
 
 
     fn inline_input(&self, filename: &str, w: &'a mut dyn Write) -> std::io::Result<()> {
-        let filename = format!("templates/{}.tex", filename);
-        match crate::helpers::read_lines(&filename) {
+        let filename = format!("{}.tex", filename);
+        let embedded = embedded_template(&filename);
+        let path = find_in_search_path(&self.search_path, &filename).unwrap_or(PathBuf::from(&filename));
+        match crate::helpers::read_lines(&path) {
             Ok(lines) => 
                 for line in lines {
                     writeln!(w, "{}", line.unwrap())?;
                 }
             Err(err) => {
-                eprintln!("Could not include {}: {}", filename, err);
+                match embedded {
+                    Some(text) => {
+                        for line in text.lines() {
+                            writeln!(w, "{}", line)?;
+                        }
+                    }
+                    None => eprintln!("Could not include {}: {}", filename, err),
+                }
             }
         }
         
@@ -116,3 +401,170 @@ impl<'a> TexTemplate<'a> {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::{TexTemplate, truncate_tex};
+
+    fn generate(template: &TexTemplate) -> String {
+        let mut out = Vec::new();
+        template.generate(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn token_name_from_line() {
+        assert_eq!(TexTemplate::token_name_from_line(&"%$INVOICE_SUM".to_string()), Some("INVOICE_SUM".to_string()));
+        assert_eq!(TexTemplate::token_name_from_line(&"  %$ TIMESHEET ".to_string()), Some("TIMESHEET".to_string()));
+        assert_eq!(TexTemplate::token_name_from_line(&"% comment".to_string()), None);
+        assert_eq!(TexTemplate::token_name_from_line(&"\\begin{document}".to_string()), None);
+    }
+
+    #[test]
+    fn token_substitution() {
+        let mut template = TexTemplate::from_string("\\documentclass{article}\n%$GREETING\n%$UNKNOWN\n\\greeting\n");
+        template
+            .token("GREETING", |w| writeln!(w, "\\newcommand{{\\greeting}}{{Hello}}"))
+            .token("UNUSED", |w| writeln!(w, "never written"));
+
+        let out = generate(&template);
+        assert_eq!(out, "\\documentclass{article}\n%$GREETING\n\\newcommand{\\greeting}{Hello}\n%$UNKNOWN\n\\greeting\n");
+    }
+
+    #[test]
+    fn input_is_inlined() {
+        let template = TexTemplate::from_string("\\input{common}\n\\input{does_not_exist}\n\\begin{document}\n");
+
+        let out = generate(&template);
+        let common = std::fs::read_to_string("templates/common.tex").unwrap();
+        let common = common.lines().map(|line| format!("{line}\n")).collect::<String>();
+        assert_eq!(out, format!("{common}\\begin{{document}}\n"));
+    }
+
+    #[test]
+    fn search_path() {
+        use super::{find_in_search_path, missing_inputs, template_text};
+        use std::path::PathBuf;
+
+        let dir = std::env::temp_dir().join(format!("invoicer_search_path_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("custom.tex"), "\\input{common}\n\\input{partial}\ncustom\n").unwrap();
+        std::fs::write(dir.join("partial.tex"), "partial\n").unwrap();
+
+        let search_path = [dir.clone(), PathBuf::from("templates")];
+        assert_eq!(find_in_search_path(&search_path, "custom.tex"), Some(dir.join("custom.tex")));
+        assert_eq!(find_in_search_path(&search_path, "common.tex"), Some(PathBuf::from("templates/common.tex")));
+        assert_eq!(find_in_search_path(&search_path, "missing.tex"), None);
+
+        // The template is found in the first directory, its inputs in the others
+        let common = std::fs::read_to_string("templates/common.tex").unwrap();
+        let common = common.lines().map(|line| format!("{line}\n")).collect::<String>();
+        assert_eq!(generate(&TexTemplate::from_search_path(&search_path, "custom.tex")), format!("{common}partial\ncustom\n"));
+
+        // Embedded templates are the final fallback
+        let embedded = generate(&TexTemplate::from_search_path(std::slice::from_ref(&dir), "timesheet.tex"));
+        assert_eq!(embedded, generate(&TexTemplate::new("templates/timesheet.tex".into())));
+
+        // Inputs are resolved against the search path and the embedded templates
+        let text = template_text(&search_path, "custom.tex").unwrap();
+        assert!(missing_inputs(&text, &search_path).is_empty());
+        assert_eq!(missing_inputs(&text, std::slice::from_ref(&dir)), Vec::<String>::new());
+        assert_eq!(missing_inputs("\\input{partial}\n\\input{nonexistent}\n", &[]), vec!["partial.tex", "nonexistent.tex"]);
+        assert!(template_text(&search_path, "missing.tex").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn from_reader_and_file() {
+        let template = TexTemplate::from_reader("line 1\nline 2".as_bytes()).unwrap();
+        assert_eq!(generate(&template), "line 1\nline 2\n");
+
+        let from_file = generate(&TexTemplate::new("templates/timesheet.tex".into()));
+        let from_reader = generate(&TexTemplate::from_reader(std::io::BufReader::new(std::fs::File::open("templates/timesheet.tex").unwrap())).unwrap());
+        assert_eq!(from_file, from_reader);
+
+        assert_eq!(generate(&TexTemplate::new("templates/does_not_exist.tex".into())), "");
+    }
+
+    #[test]
+    fn embedded_template_fallback() {
+        let embedded = generate(&TexTemplate::from_file_or_embedded("does_not_exist/timesheet.tex".into()));
+        let from_file = generate(&TexTemplate::new("templates/timesheet.tex".into()));
+        assert_eq!(embedded, from_file);
+
+        assert_eq!(generate(&TexTemplate::from_file_or_embedded("does_not_exist/custom.tex".into())), "");
+    }
+
+    #[test]
+    fn tex_commands_for_map() {
+        let map = std::collections::HashMap::from([
+            ("register_number".to_string(), "HRB 12345".to_string()),
+            ("court".to_string(), "Amtsgericht Berlin".to_string()),
+        ]);
+
+        let mut output = Vec::new();
+        super::generate_tex_commands_for_map(&mut output, "legal", &map).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), 
+            "\\newcommand{\\legalcourt}{Amtsgericht Berlin}\n\\newcommand{\\legalregisternumber}{HRB 12345}\n");
+    }
+
+    #[test]
+    fn check_tokens() {
+        let mut template = TexTemplate::from_string("%$INVOICE_DETAILS\n%$INVOICE_POSTIONS\n");
+        template
+            .token("INVOICE_DETAILS", |_| Ok(()))
+            .token("TAXES", |_| Ok(()))
+            .required_token("INVOICE_POSITIONS", |_| Ok(()));
+
+        let (unknown, missing) = template.check_tokens("%$INVOICE_DETAILS\n%$INVOICE_POSTIONS\n");
+        assert_eq!(unknown, ["INVOICE_POSTIONS"]);
+        assert_eq!(missing, ["INVOICE_POSITIONS"]);
+
+        assert!(template.generate(&mut std::io::sink()).is_ok());
+        let error = template.strict(true).generate(&mut std::io::sink()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("missing tokens INVOICE_POSITIONS"));
+    }
+
+    #[test]
+    fn conditional_sections() {
+        let text = "begin\n%$IF:has_discount\ndiscount\n%$IF:has_deposit\ndeposit\n%$ENDIF\n%$ENDIF\n%$IF:tax_exempt\nexempt\n%$ENDIF\nend\n";
+        let mut template = TexTemplate::from_string(text);
+        template.flag("has_discount", true).flag("has_deposit", false).flag("tax_exempt", false);
+        assert_eq!(generate(&template), "begin\ndiscount\nend\n");
+
+        template.flag("has_deposit", true).flag("tax_exempt", true);
+        assert_eq!(generate(&template), "begin\ndiscount\ndeposit\nexempt\nend\n");
+
+        // Sections of a false flag are skipped including nested sections with a true flag
+        template.flag("has_discount", false);
+        assert_eq!(generate(&template), "begin\nexempt\nend\n");
+
+        let (unknown, _) = template.check_tokens("%$IF:has_discont\n%$ENDIF\n");
+        assert_eq!(unknown, ["IF:has_discont"]);
+
+        let mut template = TexTemplate::from_string("%$IF:has_discount\ndiscount\n");
+        template.flag("has_discount", true).strict(true);
+        assert!(template.generate(&mut std::io::sink()).unwrap_err().to_string().contains("unbalanced"));
+
+        // Equal counts are not enough, an %$ENDIF must not precede its %$IF
+        let mut template = TexTemplate::from_string("%$ENDIF\n%$IF:has_discount\n");
+        template.flag("has_discount", true).strict(true);
+        assert!(template.generate(&mut std::io::sink()).unwrap_err().to_string().contains("unbalanced"));
+    }
+
+    #[test]
+    fn truncate() {
+        let message = "Fix \\& review code. ".repeat(10);
+        assert_eq!(message.chars().count(), 200);
+
+        let truncated = truncate_tex(&message, 60);
+        assert_eq!(truncated, "Fix \\& review code. ".repeat(3) + "Fi\\ldots{}");
+
+        assert_eq!(truncate_tex("Short", 60), "Short");
+        assert_eq!(truncate_tex("A \\& B", 4), "A \\&\\ldots{}");
+        assert_eq!(truncate_tex("\\textbf{Important} work", 5), "\\textbf{Imp}\\ldots{}");
+    }
+}
@@ -1,5 +1,7 @@
 use std::{io::Read, fs::File, path::{Path, PathBuf}};
 
+use crate::error::InvoicerError;
+
 pub trait FilePath: AsRef<std::path::Path> + AsRef<std::ffi::OsStr> {
 
     fn to_string(&self) -> String {
@@ -27,7 +29,7 @@ impl FilePath for &Path {
 }
 
 
-pub fn from_toml_file<T: serde::de::DeserializeOwned, P: FilePath>(p: P)  -> Result<T, Box<dyn std::error::Error>> {
+pub fn from_toml_file<T: serde::de::DeserializeOwned, P: FilePath>(p: P)  -> Result<T, InvoicerError> {
     let path_str = p.to_string();
     let mut file = std::fs::File::open(p)?;
     let mut s = String::new();
@@ -37,7 +39,7 @@ pub fn from_toml_file<T: serde::de::DeserializeOwned, P: FilePath>(p: P)  -> Res
         Ok(result) => Ok(result),
         Err(err) => {
             eprintln!("Error reading {}: {err}", path_str);
-            Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{err}"))))
+            Err(err.into())
         }
     }
 }
@@ -46,12 +48,22 @@ pub fn name_from_file<P: FilePath>(p: P) -> String {
     std::path::Path::new(&p).file_stem().unwrap().to_str().unwrap().to_string()
 }
 
+/// Home directory, `.` if it cannot be determined, e.g. in a sandbox without `HOME`.
 pub fn home_dir() -> String {
-    home::home_dir().unwrap_or(".".into()).into_os_string().into_string().unwrap()
+    dir_or_current(home::home_dir())
+}
+
+/// Current working directory, `.` if it cannot be determined, e.g. because it was removed.
+pub fn working_dir() -> String {
+    dir_or_current(std::env::current_dir().ok())
+}
+
+fn dir_or_current(dir: Option<PathBuf>) -> String {
+    dir.unwrap_or(".".into()).to_string_lossy().to_string()
 }
 
 pub trait FromTomlFile: serde::de::DeserializeOwned {
-    fn from_toml_file<P: FilePath>(p: P)  -> Result<Self, Box<dyn std::error::Error>> {
+    fn from_toml_file<P: FilePath>(p: P)  -> Result<Self, InvoicerError> {
         let mut file = std::fs::File::open(p)?;
         let mut s = String::new();
         file.read_to_string(&mut s)?;
@@ -106,13 +118,196 @@ impl Fingerprint for String {
     }
 }
 
+/// Make a name usable as part of a file name on all common file systems.
+/// Path separators and characters reserved on Windows are replaced by `_`.
+/// With `ascii`, German umlauts and accented Latin letters are transliterated, e.g. `Müller` to `Mueller`,
+/// and spaces and other non-ASCII characters are replaced by `_` as well.
+pub fn sanitize_filename(name: &str, ascii: bool) -> String {
+    let mut result = String::new();
+    for c in name.trim().chars() {
+        match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => result.push('_'),
+            c if c.is_control() => result.push('_'),
+            c if !ascii || c.is_ascii_alphanumeric() || "-_.+".contains(c) => result.push(c),
+            c => result.push_str(match c {
+                'ä' => "ae", 'ö' => "oe", 'ü' => "ue", 'Ä' => "Ae", 'Ö' => "Oe", 'Ü' => "Ue", 'ß' => "ss",
+                'à' | 'á' | 'â' | 'ã' | 'å' => "a", 'À' | 'Á' | 'Â' | 'Ã' | 'Å' => "A",
+                'è' | 'é' | 'ê' | 'ë' => "e", 'È' | 'É' | 'Ê' | 'Ë' => "E",
+                'ì' | 'í' | 'î' | 'ï' => "i", 'Ì' | 'Í' | 'Î' | 'Ï' => "I",
+                'ò' | 'ó' | 'ô' | 'õ' | 'ø' => "o", 'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ø' => "O",
+                'ù' | 'ú' | 'û' => "u", 'Ù' | 'Ú' | 'Û' => "U",
+                'ç' => "c", 'Ç' => "C", 'ñ' => "n", 'Ñ' => "N", 'ý' | 'ÿ' => "y", 'Ý' => "Y",
+                'æ' => "ae", 'Æ' => "Ae", 'œ' => "oe", 'Œ' => "Oe",
+                _ => "_",
+            }),
+        }
+    }
+    result
+}
+
+/// Insert a revision suffix before the extension, e.g. `invoice.tex` becomes `invoice_rev2.tex`.
+pub fn with_revision(filename: &str, revision: u32) -> String {
+    match filename.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{stem}_rev{revision}.{extension}"),
+        _ => format!("{filename}_rev{revision}"),
+    }
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Expand a glob pattern like `worklogs/2024-*.csv` into the sorted list of matching files.
+/// Wildcards can be used in any path component, see the `glob` crate for the syntax.
+/// A path without wildcards is returned as is, even if it does not exist.
+pub fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>, InvoicerError> {
+    if !has_wildcards(pattern) {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+
+    // Hidden files only match patterns starting with a dot
+    let options = glob::MatchOptions { require_literal_leading_dot: true, ..glob::MatchOptions::new() };
+    let paths = glob::glob_with(pattern, options)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid pattern '{pattern}': {err}")))?;
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path.map_err(std::io::Error::from)?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No files match '{pattern}'")).into());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Modification times of the given files and of the files directly inside the given directories.
+/// Missing files get `None`, so creating or removing them counts as a change as well.
+pub fn modification_times(paths: &[PathBuf]) -> Vec<(PathBuf, Option<std::time::SystemTime>)> {
+    let mut files = Vec::new();
+    for path in paths {
+        match std::fs::read_dir(path) {
+            Ok(read_dir) => {
+                let mut entries: Vec<PathBuf> = read_dir.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect();
+                entries.sort();
+                files.extend(entries);
+            }
+            Err(_) => files.push(path.clone()),
+        }
+    }
+
+    files.into_iter()
+        .map(|file| {
+            let modified = std::fs::metadata(&file).and_then(|metadata| metadata.modified()).ok();
+            (file, modified)
+        })
+        .collect()
+}
+
+/// Block until one of the given files or directories changes.
+/// The paths are polled every `interval`. After a change, it waits until nothing changed for `debounce`,
+/// so that an editor saving several files only triggers once.
+pub fn wait_for_changes(paths: &[PathBuf], interval: std::time::Duration, debounce: std::time::Duration) {
+    let initial = modification_times(paths);
+    let mut current = initial.clone();
+    while current == initial {
+        std::thread::sleep(interval);
+        current = modification_times(paths);
+    }
+
+    loop {
+        std::thread::sleep(debounce);
+        let latest = modification_times(paths);
+        if latest == current {
+            break;
+        }
+        current = latest;
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
+    #[test]
+    fn with_revision() {
+        assert_eq!(super::with_revision("202310_Invoice_ClientA.tex", 2), "202310_Invoice_ClientA_rev2.tex");
+        assert_eq!(super::with_revision("invoice", 1), "invoice_rev1");
+        assert_eq!(super::with_revision(".tex", 1), ".tex_rev1");
+    }
+
+    #[test]
+    fn missing_home_dir() {
+        // home::home_dir() returns None without HOME and passwd entry, e.g. in sandboxes
+        assert_eq!(super::dir_or_current(None), ".");
+        assert_eq!(super::dir_or_current(Some("/home/jane".into())), "/home/jane");
+        assert!(!super::working_dir().is_empty());
+    }
+
+    #[test]
+    fn sanitize_filename() {
+        use super::sanitize_filename;
+        assert_eq!(sanitize_filename("Müller/Söhne GmbH", false), "Müller_Söhne GmbH");
+        assert_eq!(sanitize_filename("Müller/Söhne GmbH", true), "Mueller_Soehne_GmbH");
+        assert_eq!(sanitize_filename("Café: \"Crème\"", true), "Cafe___Creme_");
+        assert_eq!(sanitize_filename("ClientA", true), "ClientA");
+    }
+
+    #[test]
+    fn modification_times() {
+        use super::modification_times;
+        use std::time::{Duration, SystemTime};
+
+        let dir = std::env::temp_dir().join(format!("invoicer_watch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("worklog.csv");
+        std::fs::write(&file, "Start,End,Message\n").unwrap();
+        let missing = dir.join("missing.toml");
+
+        let paths = [dir.clone(), missing.clone()];
+        let before = modification_times(&paths);
+        assert_eq!(before, vec![(file.clone(), before[0].1), (missing.clone(), None)]);
+        assert!(before[0].1.is_some());
+
+        std::fs::File::options().write(true).open(&file).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert_ne!(modification_times(&paths), before);
+
+        std::fs::write(&missing, "").unwrap();
+        assert_eq!(modification_times(&paths).len(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn fingerprint() {
         use super::Fingerprint;
         let fp = String::from("Test").fingerprint();
         assert!(!fp.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn expand_glob() {
+        use super::expand_glob;
+        use std::path::PathBuf;
+
+        let dir = std::env::temp_dir().join(format!("invoicer_glob_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("2024")).unwrap();
+        for file in ["2024/01.csv", "2024/02.csv", "2024/notes.txt", "2024/.hidden.csv"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let path = |file: &str| dir.join(file);
+        let pattern = |pattern: &str| format!("{}/{pattern}", dir.display());
+
+        assert_eq!(expand_glob(&pattern("2024/*.csv")).unwrap(), vec![path("2024/01.csv"), path("2024/02.csv")]);
+        assert_eq!(expand_glob(&pattern("20?4/0[2-9].csv")).unwrap(), vec![path("2024/02.csv")]);
+        assert_eq!(expand_glob(&pattern("*/notes.*")).unwrap(), vec![path("2024/notes.txt")]);
+        // Directories are not matched
+        assert!(expand_glob(&pattern("20*")).is_err());
+        assert!(expand_glob(&pattern("2024/*.toml")).is_err());
+        assert_eq!(expand_glob("missing.csv").unwrap(), vec![PathBuf::from("missing.csv")]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
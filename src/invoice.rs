@@ -1,18 +1,20 @@
 use chrono::Datelike;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::io::Write;
 use std::path::{PathBuf, Path};
 use crate::invoicer::{ Invoicer, HasDirectories, InvoiceFingerprints};
-use crate::locale::{Currency, Locale};
+use crate::locale::{Currency, Locale, Rounding};
 use crate::generate_tex::*;
-use crate::helpers::{ DateTime, date_to_str, FromTomlFile, FilePath, Fingerprint };
-use crate::worklog::{ Worklog, WorklogRecord };
+use crate::helpers::{ DateTime, FromTomlFile, FilePath, Fingerprint, sanitize_filename, with_revision };
+use crate::worklog::{ Worklog, WorklogRecord, Rate, tags_match, HOURS_UNIT };
+use crate::payment_qr::EpcPayment;
+use crate::error::InvoicerError;
 
 use std::collections::{HashMap, BTreeMap, HashSet};
 
 use struct_iterable::Iterable;
 
-#[derive(Debug, Deserialize, Iterable, Clone)]
+#[derive(Debug, Serialize, Deserialize, Iterable, Clone)]
 pub struct Contact {
     companyname: Option<String>,
     fullname: String,
@@ -26,17 +28,166 @@ pub struct Contact {
     website: Option<String>,
 }
 
+impl Contact {
+    pub fn fullname(&self) -> &String {
+        &self.fullname
+    }
+
+    /// Value of a contact field by its name, `None` if the field is not set or unknown.
+    fn field(&self, name: &str) -> Option<String> {
+        match name {
+            "companyname" => self.companyname.clone(),
+            "fullname" => Some(self.fullname.clone()),
+            "street" => Some(self.street.clone()),
+            "zipcode" => Some(self.zipcode.to_string()),
+            "city" => Some(self.city.clone()),
+            "country" => self.country.clone(),
+            "phone" => self.phone.clone(),
+            "fax" => self.fax.clone(),
+            "email" => Some(self.email.clone()),
+            "website" => self.website.clone(),
+            _ => None,
+        }
+    }
+
+    /// Address formatted by lines like `${ZIPCODE} ${CITY}`, joined with `separator`.
+    /// Lines whose fields are all unset are skipped.
+    pub fn address_block(&self, lines: &[String], separator: &str) -> String {
+        let mut block = Vec::new();
+        for line in lines {
+            let mut text = line.clone();
+            let mut has_value = false;
+            while let Some(begin) = text.find("${") {
+                let Some(end) = text[begin..].find('}').map(|end| begin + end) else {
+                    break;
+                };
+                let value = self.field(&text[begin + 2..end].to_lowercase());
+                has_value |= value.is_some();
+                text.replace_range(begin..=end, &value.unwrap_or_default());
+            }
+
+            let text = text.trim();
+            if has_value && !text.is_empty() {
+                block.push(text.to_string());
+            }
+        }
+        block.join(separator)
+    }
+}
+
 impl GenerateTexCommands for Contact {}
 
-#[derive(Debug, Deserialize, Iterable, Clone)]
+/// A named tax charged on the net sum, e.g. VAT, GST or sales tax.
+/// Negative rates are deducted, e.g. for withholding taxes.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Tax {
+    name: Option<String>,
+    rate: f32,
+}
+
+impl Tax {
+    pub fn new(name: Option<&str>, rate: f32) -> Self {
+        Self { name: name.map(|name| name.to_string()), rate }
+    }
+
+    /// Label of the tax, `None` for the value-added tax given by `tax_rate`.
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Label in tex, the `vat` translation if the tax has no name.
+    pub fn tex_label(&self) -> String {
+        self.name.clone().unwrap_or("\\trvat".to_string())
+    }
+}
+
+/// Kind of the discount of a promotion.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PromotionKind {
+    /// `value` percent of the net sum
+    Percent,
+    /// `value` in the invoice currency, at most the net sum
+    Absolute,
+}
+
+/// A named discount from the `[[promotions]]` list of the config, applied to a run with `--promo <name>`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Promotion {
+    name: String,
+    kind: PromotionKind,
+    value: f32,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    expires: Option<chrono::NaiveDate>,
+}
+
+/// Read a TOML date like `2024-12-31` or a string in this format.
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<chrono::NaiveDate>, D::Error> {
+    use serde::de::Error;
+    let date = match toml::Value::deserialize(deserializer)? {
+        toml::Value::Datetime(datetime) => datetime.to_string(),
+        toml::Value::String(date) => date,
+        value => return Err(D::Error::custom(format!("expected a date like 2024-12-31, found {value}"))),
+    };
+    chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d").map(Some).map_err(|e| D::Error::custom(format!("invalid date '{date}': {e}")))
+}
+
+impl Promotion {
+    pub fn new(name: &str, kind: PromotionKind, value: f32, expires: Option<chrono::NaiveDate>) -> Self {
+        Self { name: name.to_string(), kind, value, expires }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn kind(&self) -> PromotionKind {
+        self.kind
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Last day the promotion can be applied.
+    pub fn expires(&self) -> Option<chrono::NaiveDate> {
+        self.expires
+    }
+
+    /// The promotion can be applied to invoices of this date.
+    pub fn is_valid_on(&self, date: chrono::NaiveDate) -> bool {
+        self.expires.is_none_or(|expires| date <= expires)
+    }
+
+    /// Discount on the given net sum, never more than the sum.
+    pub fn discount(&self, sum: f32) -> f32 {
+        match self.kind {
+            PromotionKind::Percent => sum * self.value / 100.0,
+            PromotionKind::Absolute => self.value.min(sum),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Iterable, Clone)]
 pub struct Payment {
     accountholder: Option<String>,
     iban: String,
     bic: String,
     taxid: String,
     currency: Option<Currency>,
-    tax_rate: f32,
-    default_rate: Option<f32>
+    currency_symbol: Option<String>,
+    tax_rate: Option<f32>,
+    #[serde(default)]
+    taxes: Vec<Tax>,
+    default_rate: Option<f32>,
+    fallback_rate: Option<f32>,
+    require_explicit_rate: Option<bool>,
+    rounding: Option<Rounding>,
+    round_positions: Option<bool>,
 }
 
 impl Payment {
@@ -47,17 +198,141 @@ impl Payment {
         }
     }
 
+    /// Configured `currency_symbol` or the symbol of the currency.
     pub fn currency_symbol(&self) -> String {
-        self.currency().symbol()
+        self.currency().symbol_or(self.currency_symbol.as_ref())
+    }
+
+    pub fn accountholder(&self) -> Option<&String> {
+        self.accountholder.as_ref()
+    }
+
+    pub fn iban(&self) -> &String {
+        &self.iban
+    }
+
+    pub fn bic(&self) -> &String {
+        &self.bic
+    }
+
+    pub fn taxid(&self) -> &String {
+        &self.taxid
+    }
+
+    /// Combined rate of all taxes.
+    pub fn tax_rate(&self) -> f32 {
+        self.taxes().iter().map(|tax| tax.rate()).sum()
+    }
+
+    /// Taxes charged on the net sum.
+    /// A single `tax_rate` is a value-added tax without name.
+    pub fn taxes(&self) -> Vec<Tax> {
+        match self.tax_rate {
+            Some(tax_rate) if self.taxes.is_empty() => vec![Tax::new(None, tax_rate)],
+            _ => self.taxes.clone(),
+        }
+    }
+
+    /// Each tax with its amount on the net sum, rounded to cents.
+    pub fn tax_amounts(&self, sum: f32) -> Vec<(Tax, f32)> {
+        let rounding = self.rounding();
+        self.taxes().into_iter()
+            .map(|tax| {
+                let amount = rounding.round(sum * tax.rate() / 100.0, 2);
+                (tax, amount)
+            })
+            .collect()
+    }
+
+    pub fn default_rate(&self) -> Option<f32> {
+        self.default_rate
+    }
+
+    /// Rate used when neither the record, the recipient nor the payment have a rate.
+    pub fn fallback_rate(&self) -> f32 {
+        self.fallback_rate.unwrap_or(100.0)
+    }
+
+    /// If true, records without any rate are an error instead of using the fallback rate.
+    pub fn require_explicit_rate(&self) -> bool {
+        self.require_explicit_rate.unwrap_or(false)
+    }
+
+    /// Rounding of amounts, round half to even by default.
+    pub fn rounding(&self) -> Rounding {
+        self.rounding.unwrap_or_default()
+    }
+
+    /// If true (default), the net amount of each position is rounded before summing up,
+    /// so the printed positions add up to the printed sum.
+    /// Otherwise the exact amounts are summed up and only the sum is rounded.
+    pub fn round_positions(&self) -> bool {
+        self.round_positions.unwrap_or(true)
+    }
+
+    /// Copy with all settings that have a default set to their effective value.
+    pub fn with_defaults(&self) -> Self {
+        Self {
+            currency: Some(self.currency()),
+            currency_symbol: Some(self.currency_symbol()),
+            fallback_rate: Some(self.fallback_rate()),
+            require_explicit_rate: Some(self.require_explicit_rate()),
+            rounding: Some(self.rounding()),
+            round_positions: Some(self.round_positions()),
+            ..self.clone()
+        }
     }
 }
 
 impl GenerateTexCommands for Payment {}
 
+/// Tag information of a recipient, parsed from a string like `[default][template=consulting.tex] Consulting`.
+/// Leading annotations in square brackets are optional:
+/// * `[default]`: Tag is used for records without a matching tag.
+/// * `[template=<file>]`: Template for invoices whose positions all have this tag.
+/// * `[order=<n>]`: Positions with this tag are listed by ascending order, before positions without order.
 #[derive(Debug, Iterable, Clone)]
 pub struct RecipientTagInfo {
     is_default: bool,
     position_text: String,
+    template: Option<String>,
+    order: Option<i32>,
+}
+
+impl RecipientTagInfo {
+    pub fn is_default(&self) -> bool {
+        self.is_default
+    }
+
+    pub fn position_text(&self) -> &String {
+        &self.position_text
+    }
+
+    pub fn template(&self) -> Option<&String> {
+        self.template.as_ref()
+    }
+
+    pub fn order(&self) -> Option<i32> {
+        self.order
+    }
+}
+
+/// Written back in the annotated form it is read from, e.g. `[default][order=1]Development`.
+impl Serialize for RecipientTagInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = String::new();
+        if self.is_default {
+            value.push_str("[default]");
+        }
+        if let Some(template) = &self.template {
+            value.push_str(&format!("[template={template}]"));
+        }
+        if let Some(order) = self.order {
+            value.push_str(&format!("[order={order}]"));
+        }
+        value.push_str(&self.position_text);
+        serializer.serialize_str(&value)
+    }
 }
 
 impl<'de> Deserialize<'de> for RecipientTagInfo {
@@ -72,45 +347,230 @@ impl<'de> Deserialize<'de> for RecipientTagInfo {
 
 impl From<String> for RecipientTagInfo {
     fn from(value: String) -> Self {
-        let value = value.trim();
-        Self {
-            is_default: value.starts_with("[default]"),
-            position_text: value.replacen("[default]", "", 1)
-        }
+        Self::from(value.as_str())
     }
 }
 
 impl From<&str> for RecipientTagInfo {
     fn from(value: &str) -> Self {
-        let value = value.trim();
-        Self {
-            is_default: value.starts_with("[default]"),
-            position_text: value.replacen("[default]", "", 1)
+        let mut info = Self {
+            is_default: false,
+            position_text: String::new(),
+            template: None,
+            order: None,
+        };
+
+        let mut value = value.trim();
+        while value.starts_with('[') {
+            let Some(end) = value.find(']') else {
+                break;
+            };
+
+            let annotation = value[1..end].trim();
+            if annotation == "default" {
+                info.is_default = true;
+            } else if let Some(template) = annotation.strip_prefix("template=") {
+                info.template = Some(template.trim().to_string());
+            } else if let Some(order) = annotation.strip_prefix("order=").and_then(|order| order.trim().parse().ok()) {
+                info.order = Some(order);
+            } else {
+                break;
+            }
+            value = &value[end + 1..];
         }
+
+        info.position_text = value.to_string();
+        info
+    }
+}
+
+/// Hourly rate for the hours of an invoice beyond `from_hours`, given as `[[rate_tiers]]` of a recipient.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RateTier {
+    from_hours: f32,
+    rate: f32,
+}
+
+impl RateTier {
+    pub fn new(from_hours: f32, rate: f32) -> Self {
+        Self { from_hours, rate }
+    }
+
+    pub fn from_hours(&self) -> f32 {
+        self.from_hours
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.rate
     }
 }
 
 
 
-#[derive(Debug, Deserialize, Iterable, Clone)]
+/// Fee of a percentage of the positions above, e.g. an agency's management fee, given as `[[fees]]` of a recipient.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Fee {
+    text: String,
+    percent: f32,
+}
+
+impl Fee {
+    pub fn new(text: &str, percent: f32) -> Self {
+        Self { text: text.to_string(), percent }
+    }
+
+    pub fn text(&self) -> &String {
+        &self.text
+    }
+
+    pub fn percent(&self) -> f32 {
+        self.percent
+    }
+}
+
+#[derive(Serialize, Deserialize, Iterable, Clone)]
 pub struct Recipient {
     #[serde(skip)]
     name: String,
     contact: Contact,
     invoice: InvoiceConfig,
     default_rate: Option<f32>,
+    deposit: Option<f32>,
+    cap: Option<f32>,
+    currency: Option<Currency>,
+    payment_terms: Option<String>,
+    tax_exempt: Option<String>,
+    po_number: Option<String>,
+    #[serde(default)]
+    rate_tiers: Vec<RateTier>,
+    #[serde(default)]
+    fees: Vec<Fee>,
+    #[serde(default)]
+    attachments: Vec<String>,
+    #[serde(default)]
+    custom: HashMap<String, toml::Value>,
     tags: HashMap<String, RecipientTagInfo>
 }
 
+/// Like the derived implementation, but with sorted `custom` and `tags` maps,
+/// so the output and the invoice fingerprint built from it do not change between runs.
+impl std::fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recipient")
+            .field("name", &self.name)
+            .field("contact", &self.contact)
+            .field("invoice", &self.invoice)
+            .field("default_rate", &self.default_rate)
+            .field("deposit", &self.deposit)
+            .field("cap", &self.cap)
+            .field("currency", &self.currency)
+            .field("payment_terms", &self.payment_terms)
+            .field("tax_exempt", &self.tax_exempt)
+            .field("po_number", &self.po_number)
+            .field("rate_tiers", &self.rate_tiers)
+            .field("fees", &self.fees)
+            .field("attachments", &self.attachments)
+            .field("custom", &self.custom.iter().collect::<BTreeMap<_, _>>())
+            .field("tags", &self.tags.iter().collect::<BTreeMap<_, _>>())
+            .finish()
+    }
+}
+
 impl Recipient {
     pub fn name(&self) -> &String {
         &self.name
     }
 
-    pub fn from_tag(tag: &String, tag_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    pub fn contact(&self) -> &Contact {
+        &self.contact
+    }
+
+    pub fn invoice(&self) -> &InvoiceConfig {
+        &self.invoice
+    }
+
+    pub fn default_rate(&self) -> Option<f32> {
+        self.default_rate
+    }
+
+    /// Deposit the recipient already paid, as gross amount.
+    pub fn deposit(&self) -> Option<f32> {
+        self.deposit
+    }
+
+    /// Maximum net sum of an invoice, e.g. for a fixed budget billed by hours.
+    pub fn cap(&self) -> Option<f32> {
+        self.cap
+    }
+
+    /// Currency of the invoices for this recipient, the currency of the first payment account by default.
+    pub fn currency(&self) -> Option<&Currency> {
+        self.currency.as_ref()
+    }
+
+    /// Payment terms text with `${DAYS}` and `${DUEDATE}` placeholders, e.g. `Net ${DAYS}, 2% discount if paid within 7 days`.
+    pub fn payment_terms(&self) -> Option<&String> {
+        self.payment_terms.as_ref()
+    }
+
+    /// Reason why the recipient is exempt from taxes, e.g. a charity. No taxes are charged if it is set.
+    pub fn tax_exempt(&self) -> Option<&String> {
+        self.tax_exempt.as_ref()
+    }
+
+    /// Purchase order number of the recipient, shown on its invoices.
+    pub fn po_number(&self) -> Option<&String> {
+        self.po_number.as_ref()
+    }
+
+    /// Rate tiers of the `[[rate_tiers]]` list, ordered by `from_hours`.
+    pub fn rate_tiers(&self) -> Vec<RateTier> {
+        let mut tiers = self.rate_tiers.clone();
+        tiers.sort_by(|a, b| a.from_hours.total_cmp(&b.from_hours));
+        tiers
+    }
+
+    /// Percentage fees of the `[[fees]]` list, added after all other positions.
+    pub fn fees(&self) -> &Vec<Fee> {
+        &self.fees
+    }
+
+    /// Custom fields of the `[custom]` table as text, e.g. a purchase order number.
+    /// Only strings, integers, floats and booleans are supported, other values are skipped.
+    pub fn custom_fields(&self) -> HashMap<String, String> {
+        self.custom.iter()
+            .filter_map(|(key, value)| match value {
+                toml::Value::String(s) => Some(s.clone()),
+                toml::Value::Integer(i) => Some(i.to_string()),
+                toml::Value::Float(f) => Some(f.to_string()),
+                toml::Value::Boolean(b) => Some(b.to_string()),
+                _ => {
+                    eprintln!("{}: Warning: Custom field '{key}' is not a string, number or boolean and is skipped.", self.name);
+                    None
+                }
+            }.map(|value| (key.clone(), value)))
+            .collect()
+    }
+
+    /// Files attached to each invoice of the recipient.
+    pub fn attachments(&self) -> &Vec<String> {
+        &self.attachments
+    }
+
+    pub fn from_tag(tag: &String, tag_dir: &Path) -> Result<Self, InvoicerError> {
         Self::from_toml_file(Path::new(tag_dir).join(format!("{tag}.toml")))
     }
 
+    /// Commented recipient TOML with all fields stubbed, written by `invoicer new-recipient <name>`.
+    /// Derived from the recipient example of `invoicer schema`, see `schema::recipient_template`.
+    pub fn template(name: &str) -> String {
+        crate::schema::recipient_template(name)
+    }
+
     pub fn from_tags(tags: &HashSet<String>, tag_dir: &Path) -> Vec<Self> {
         let mut v = Vec::new();
         for tag in tags {
@@ -125,6 +585,11 @@ impl Recipient {
         &self.tags
     }
 
+    /// Order of positions with the given tag, see `RecipientTagInfo`.
+    pub fn tag_order(&self, tag: Option<&String>) -> Option<i32> {
+        tag.and_then(|tag| self.tags.get(tag)).and_then(|info| info.order())
+    }
+
     pub fn default_tag_name(&self) -> Option<&String> {
         for (name, tag) in &self.tags {
             if tag.is_default {
@@ -138,7 +603,7 @@ impl Recipient {
 
 
 impl FromTomlFile for Recipient {
-    fn from_toml_file<P: FilePath>(p: P)  -> Result<Self, Box<dyn std::error::Error>> {
+    fn from_toml_file<P: FilePath>(p: P)  -> Result<Self, InvoicerError> {
         let name_str = p.to_string();
         let mut recipient: Recipient = crate::helpers::from_toml_file(p)?;
         recipient.name = crate::helpers::name_from_file::<PathBuf>(name_str.into());
@@ -152,23 +617,47 @@ impl GenerateTexCommands for Recipient {
     fn generate_tex_commands<'a>(&self, w: &'a mut dyn Write, prefix: &str) -> std::io::Result<()> {
         generate_tex_command(w, format!("{prefix}name").as_str(), &self.name)?;
         self.contact.generate_tex_commands(w, prefix)?;
-        Ok(())
+
+        // Custom fields must not redefine the commands of the contact
+        let mut custom = self.custom_fields();
+        custom.retain(|key, _| {
+            let reserved = key == "name" || self.contact.iter().any(|(field, _)| field == key);
+            if reserved {
+                eprintln!("{}: Warning: Custom field '{key}' is skipped, because it is a contact field.", self.name);
+            }
+            !reserved
+        });
+        generate_tex_commands_for_map(w, prefix, &custom)
     }
 }
 
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InvoiceConfig {
     #[serde(rename = "locale")]
     locale_str: Option<String>,
     template: Option<String>,
     date_format: Option<String>,
     number_format: Option<String>,
+    number_scope: Option<NumberScope>,
     filename_format: Option<String>,
+    ascii_filenames: Option<bool>,
     days_for_payment: Option<u32>,
     calculate_value_added_tax: Option<bool>,
     timesheet: Option<bool>,
     timesheet_template: Option<String>,
+    timesheet_columns: Option<Vec<TimesheetColumn>>,
+    timesheet_nonbillable: Option<bool>,
+    payment_qr: Option<bool>,
+    generate_empty: Option<bool>,
+    rate_precision: Option<usize>,
+    amount_precision: Option<usize>,
+    position_text_max_len: Option<usize>,
+    position_hour_rounding: Option<f32>,
+    subject: Option<String>,
+    group_untagged_by: Option<UntaggedGrouping>,
+    position_sort: Option<PositionSort>,
+    positions_from: Option<PositionsFrom>,
 }
 
 macro_rules! default_getter {
@@ -185,34 +674,224 @@ impl InvoiceConfig {
     default_getter!(template, String, "invoice.tex");
     default_getter!(date_format, String, "%Y/%m/%d");
     default_getter!(number_format, String, "%Y%m${COUNTER}");
+    default_getter!(number_scope, NumberScope);
     default_getter!(filename_format, String, "${INVOICENUMBER}_${INVOICE}_${RECIPIENT}.tex");
+    default_getter!(ascii_filenames, bool, false);
     default_getter!(days_for_payment, u32, 14_u32);
     default_getter!(calculate_value_added_tax, bool, true);
     default_getter!(timesheet, bool, true);
     default_getter!(timesheet_template, String);
+    default_getter!(timesheet_nonbillable, bool, false);
+    default_getter!(payment_qr, bool, false);
+    default_getter!(generate_empty, bool, false);
+    default_getter!(rate_precision, usize, 2_usize);
+    default_getter!(amount_precision, usize, 2_usize);
+
+    /// Maximum number of characters of a position text, longer texts are truncated with an ellipsis.
+    pub fn position_text_max_len(&self) -> Option<usize> {
+        self.position_text_max_len
+    }
+
+    pub fn timesheet_columns(&self) -> Vec<TimesheetColumn> {
+        self.timesheet_columns.clone().unwrap_or(vec![TimesheetColumn::Start, TimesheetColumn::Hours, TimesheetColumn::Message])
+    }
+
+    /// Copy with all settings that have a default set to their effective value.
+    pub fn with_defaults(&self) -> Self {
+        Self {
+            locale_str: Some(self.locale_str()),
+            template: Some(self.template()),
+            date_format: Some(self.date_format()),
+            number_format: Some(self.number_format()),
+            number_scope: Some(self.number_scope()),
+            filename_format: Some(self.filename_format()),
+            ascii_filenames: Some(self.ascii_filenames()),
+            days_for_payment: Some(self.days_for_payment()),
+            calculate_value_added_tax: Some(self.calculate_value_added_tax()),
+            timesheet: Some(self.timesheet()),
+            timesheet_template: Some(self.timesheet_template()),
+            timesheet_columns: Some(self.timesheet_columns()),
+            timesheet_nonbillable: Some(self.timesheet_nonbillable()),
+            payment_qr: Some(self.payment_qr()),
+            generate_empty: Some(self.generate_empty()),
+            rate_precision: Some(self.rate_precision()),
+            amount_precision: Some(self.amount_precision()),
+            subject: Some(self.subject.clone().unwrap_or("${PERIOD}".to_string())),
+            group_untagged_by: Some(self.group_untagged_by.unwrap_or_default()),
+            position_sort: Some(self.position_sort.unwrap_or_default()),
+            positions_from: Some(self.positions_from.unwrap_or_default()),
+            ..self.clone()
+        }
+    }
+}
+
+
+
+
+/// How untagged records are merged into positions if the recipient has no default tag.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UntaggedGrouping {
+    /// Records with the same message are merged
+    #[default]
+    Message,
+    /// Records of the same calendar day are merged into a position labeled with the date
+    Day,
+    /// Each record is a position of its own
+    None,
+}
+
+/// Order of the positions on the invoice.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionSort {
+    /// By the `[order=<n>]` annotation of the tag, then by text
+    #[default]
+    Tag,
+    /// By text
+    Alphabetical,
+    /// By net amount, largest first
+    AmountDesc,
+    /// By net amount, smallest first
+    AmountAsc,
 }
 
+/// How the positions are created from the worklog records.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionsFrom {
+    /// Records are merged into a position per tag
+    #[default]
+    Tags,
+    /// Each record is a position of its own, prefixed with its date and ordered by date
+    Chronological,
+}
 
+/// Scope in which the `${COUNTER}` of invoice numbers is continued.
+/// The counter starts at 1 again in each new scope, e.g. each month.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberScope {
+    /// One continuous counter for all invoices
+    Global,
+    /// The counter restarts each year
+    Yearly,
+    /// The counter restarts each month
+    #[default]
+    Monthly,
+    /// Each recipient has its own continuous counter
+    PerRecipient,
+}
 
 
 use std::ops::AddAssign;
 
 
+/// A column of the timesheet table.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimesheetColumn {
+    /// Start date and time of the record
+    Start,
+    /// End date and time of the record
+    End,
+    /// Hours of the record
+    Hours,
+    /// Message of the record
+    Message,
+    /// Comma-separated tags of the record
+    Tags,
+    /// Running total of hours
+    Total,
+}
+
+impl TimesheetColumn {
+    /// Column specification for the LaTeX tabular.
+    fn tabular_spec(&self) -> &'static str {
+        match self {
+            TimesheetColumn::Start | TimesheetColumn::End => "p{4cm}",
+            TimesheetColumn::Hours | TimesheetColumn::Total => "p{2cm}",
+            TimesheetColumn::Message => "p{9cm}",
+            TimesheetColumn::Tags => "p{3cm}",
+        }
+    }
+
+    /// Header text of the column, from the locale's translations.
+    fn header(&self, locale: &Locale) -> String {
+        let (key, default) = match self {
+            TimesheetColumn::Start => ("date", "Date"),
+            TimesheetColumn::End => ("end", "End"),
+            TimesheetColumn::Hours => ("hours", "Hours"),
+            TimesheetColumn::Message => ("description", "Description"),
+            TimesheetColumn::Tags => ("tags", "Tags"),
+            TimesheetColumn::Total => ("totalhours", "Total hours"),
+        };
+        locale.translation(key).cloned().unwrap_or(default.to_string())
+    }
+}
+
 pub struct Timesheet {
     worklog: Worklog,
     template_file: String,
     template_dir: String,
+    search_path: Vec<PathBuf>,
     locale: Locale,
+    columns: Vec<TimesheetColumn>,
 }
 
 impl Timesheet {
-    pub fn new<P: FilePath>(template_file: P, locale: Locale) -> Self {
+    pub fn new<P: FilePath>(template_file: P, locale: Locale, columns: Vec<TimesheetColumn>) -> Self {
         Self {
             worklog: Worklog::new(),
             template_file: template_file.file_name(),
             template_dir: template_file.parent(),
+            search_path: vec![template_file.parent().into()],
             locale: locale.clone(),
+            columns,
+        }
+    }
+
+    /// Directories searched for the template, by default the directory of the template file.
+    pub fn set_search_path(&mut self, search_path: Vec<PathBuf>) {
+        self.search_path = search_path;
+    }
+
+    pub fn columns(&self) -> &Vec<TimesheetColumn> {
+        &self.columns
+    }
+
+    /// Begin of the timesheet table with the column header.
+    fn generate_header(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        let spec = self.columns.iter().map(|c| c.tabular_spec()).collect::<String>();
+        let header = self.columns.iter().map(|c| format!("\\textbf{{{}}}", c.header(&self.locale))).collect::<Vec<_>>().join(" & ");
+        writeln!(w, "\\begin{{longtable}}{{{spec}}}")?;
+        writeln!(w, "{header} \\\\")?;
+        writeln!(w, "\\hline")
+    }
+
+    /// One table row per record with the configured columns.
+    fn generate_rows(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        let mut total = 0.0_f32;
+        for record in self.worklog.records() {
+            if record.is_billable() && record.is_hours() {
+                total += record.hours;
+            }
+            let row = self.columns.iter().map(|column| match column {
+                TimesheetColumn::Start => record.start.clone(),
+                TimesheetColumn::End => record.end_date().format("%m/%d/%Y %H:%M").to_string(),
+                TimesheetColumn::Hours if record.is_hours() => self.locale.format_number(record.hours, 2),
+                TimesheetColumn::Hours => format!("{}{}", self.locale.format_number(record.hours, 2), self.locale.unit(record.unit())),
+                TimesheetColumn::Message => record.message.clone(),
+                TimesheetColumn::Tags => {
+                    let mut tags = record.tags().into_iter().collect::<Vec<_>>();
+                    tags.sort();
+                    tags.join(", ")
+                }
+                TimesheetColumn::Total => self.locale.format_number(total, 2),
+            }).collect::<Vec<_>>();
+            writeln!(w, "{}\\\\", row.join(" & "))?;
         }
+        Ok(())
     }
     
     pub fn add_record(&mut self, record: WorklogRecord) {
@@ -226,33 +905,51 @@ impl Timesheet {
     pub fn len(&self) -> usize {
         self.worklog.len()
     }
+
+    pub fn worklog(&self) -> &Worklog {
+        &self.worklog
+    }
 }
 
-impl GenerateTex for Timesheet {
-    fn generate_tex<'a>(&self, w: &'a mut dyn Write) -> std::io::Result<()> {
-        let mut template = TexTemplate::new(self.template_dir().join(self.template_file.clone()));
+impl Timesheet {
+    /// Generate the timesheet with the flags of the invoice for `%$IF` sections, see `Invoice::template_flags`.
+    pub fn generate_tex_with_flags(&self, w: &mut dyn Write, flags: &[(&'static str, bool)]) -> std::io::Result<()> {
+        let mut template = TexTemplate::from_search_path(&self.search_path, &self.template_file);
+        for (name, value) in flags {
+            template.flag(name, *value);
+        }
         template
-            .token("WORKLOG", |w| {
-                for record in self.worklog.records() {
-                    writeln!(w, "{} & {} & {}\\\\", record.start, self.locale.format_number(record.hours, 2), record.message)?;
-                }
-                Ok(())
-            })
+            .token("WORKLOG_HEADER", |w| self.generate_header(w))
+            .required_token("WORKLOG", |w| self.generate_rows(w))
             .generate(w)
     }
+}
+
+impl GenerateTex for Timesheet {
+    fn generate_tex<'a>(&self, w: &'a mut dyn Write) -> std::io::Result<()> {
+        self.generate_tex_with_flags(w, &[])
+    }
 
     fn template_dir(&self) -> PathBuf {
         self.template_dir.clone().into()
     }
 }
 
+/// An invoice for a single recipient.
+///
+/// An invoice is created for an `Invoicer`, which provides the config, directories and invoice date.
+/// Positions are added from a worklog with `add_worklog` or directly with `add_position`.
+/// The invoice number is assigned with `generate_number` and the LaTeX output is written with `generate_tex`.
 pub struct Invoice<'a> {
     invoicer: &'a Invoicer,
     config: &'a InvoiceConfig,
     number: String,
+    counter: Option<u32>,
+    revision: Option<u32>,
     recipient: Recipient,
     positions: Vec<InvoicePosition>,
     timesheet: Option<Timesheet>,
+    tiered_hours: f32,
     begin_date: DateTime,
     end_date: DateTime,
 }
@@ -263,24 +960,43 @@ impl<'a> Invoice<'a> {
             invoicer: invoicer,
             config: invoicer.config().invoice(),
             number: String::new(),
+            counter: None,
+            revision: None,
             recipient: recipient,
             positions: Vec::new(),
             timesheet: None,
+            tiered_hours: 0.0,
             begin_date: DateTime::MAX,
             end_date: DateTime::MIN,
         }
     }
 
-    pub fn locale(&self) -> Locale {
-        let locale_str = match &self.recipient.invoice.locale_str {
+    /// Locale file from the command line, the recipient or the config, in that order, `en` by default.
+    pub fn locale_file(&self) -> PathBuf {
+        let locale_str = match self.invoicer.locale() {
             Some(locale) => locale.clone(),
-            None => match &self.config.locale_str {
+            None => match &self.recipient.invoice.locale_str {
                 Some(locale) => locale.clone(),
-                None => String::from("en")
+                None => match &self.config.locale_str {
+                    Some(locale) => locale.clone(),
+                    None => String::from("en")
+                }
             }
         };
 
-        Locale::from_toml_file(self.invoicer.locale_dir().join(format!("{}.toml", locale_str))).unwrap()
+        self.invoicer.locale_dir().join(format!("{}.toml", locale_str))
+    }
+
+    /// Locale of the invoice, see `locale_file`.
+    /// If the invoice currency differs from the currency of the locale, amounts and rates are shown in the invoice currency.
+    /// Panics if the locale file cannot be loaded, `Invoicer::check_recipient_locales` checks this beforehand.
+    pub fn locale(&self) -> Locale {
+        let mut locale = Locale::from_toml_file(self.locale_file()).unwrap();
+        locale.set_rounding(self.payment().rounding());
+        if *locale.currency() != self.currency() {
+            locale.set_currency(self.currency(), Some(self.currency_symbol()));
+        }
+        locale
     }
 
     pub fn date(&self) -> DateTime {
@@ -289,11 +1005,14 @@ impl<'a> Invoice<'a> {
     
     pub fn add_position(&mut self, position: InvoicePosition) {
         self.positions.push(position);
+        self.update_percentage_positions();
     }
 
+    /// Assign the invoice number of the fingerprint or a new number with the given counter.
+    /// A counter whose number is already used by another invoice in `fingerprints` is skipped.
+    /// Returns the counter for the next invoice.
     pub fn generate_number(&mut self, counter: u32, fingerprints: Option<&InvoiceFingerprints>) -> u32 {        
-        let date = self.invoicer.date();
-
+        let mut counter = counter;
         match fingerprints {
             Some(fingerprints) => {
                 // We have a fingerprint
@@ -301,66 +1020,274 @@ impl<'a> Invoice<'a> {
                     self.number = fingerprints.number_for_fingerprint(self.fingerprint());
                     return counter;
                 }
+
+                let requested = counter;
+                while fingerprints.contains_number(self.number_with_counter(counter)) {
+                    counter += 1;
+                }
+                if counter != requested {
+                    eprintln!("{}: Warning: Invoice number {} is already used, using {} instead.", 
+                        self.recipient.name(), self.number_with_counter(requested), self.number_with_counter(counter));
+                }
             }
             None => {}
         }
 
-        self.number = self.config.number_format()
-            .replace("%Y", format!("{:04}", date.year()).as_str())
-            .replace("%m", format!("{:02}", date.month()).as_str())
-            .replace("${COUNTER}", format!("{:02}", counter).as_str());
+        self.number = self.number_with_counter(counter);
+        self.counter = Some(counter);
 
         counter + 1
     }
 
-    pub fn positions(&self) -> &Vec<InvoicePosition> {
-        &self.positions
+    /// Counter of a newly generated invoice number, `None` if the number was reused from a fingerprint.
+    pub fn counter(&self) -> Option<u32> {
+        self.counter
     }
 
-    pub fn default_rate(&self) -> f32 {
-        self.recipient.default_rate
-            .unwrap_or(self.payment().default_rate.unwrap_or(100.0))
+    /// Key of the counter scope of this invoice, e.g. `2024-03` for the monthly scope.
+    pub fn number_scope_key(&self) -> String {
+        match self.config.number_scope() {
+            NumberScope::Global => "global".to_string(),
+            NumberScope::Yearly => format!("{:04}", self.date().year()),
+            NumberScope::Monthly => format!("{:04}-{:02}", self.date().year(), self.date().month()),
+            NumberScope::PerRecipient => format!("recipient {}", self.recipient.name()),
+        }
     }
 
-    pub fn generate_timesheet(&self) -> bool {
-        (self.config.timesheet() && !self.config.timesheet_template().is_empty()) || self.timesheet.is_some()
+    pub fn positions(&self) -> &Vec<InvoicePosition> {
+        &self.positions
     }
 
-    pub fn add_worklog(&mut self, worklog: &Worklog) {
-        let mut positions: BTreeMap<String, InvoicePosition> = BTreeMap::new();
+    /// Order of the positions from the recipient or the config, by tag order by default.
+    pub fn position_sort(&self) -> PositionSort {
+        self.recipient.invoice.position_sort
+            .or(self.config.position_sort)
+            .unwrap_or_default()
+    }
 
-        for record in worklog.records() {
-            self.begin_date = record.begin_date().min(self.begin_date);
-            self.end_date = record.end_date().max(self.end_date);
+    /// Increment in hours to which the merged hours of each position are rounded up, e.g. `0.25`, from the recipient or the config.
+    /// Positions are not rounded by default.
+    pub fn position_hour_rounding(&self) -> Option<f32> {
+        self.recipient.invoice.position_hour_rounding
+            .or(self.config.position_hour_rounding)
+            .filter(|increment| *increment > 0.0)
+    }
 
-            let tags = self.recipient.tags();
+    /// Whether positions are merged per tag or listed per record, from the recipient or the config, per tag by default.
+    pub fn positions_from(&self) -> PositionsFrom {
+        self.recipient.invoice.positions_from
+            .or(self.config.positions_from)
+            .unwrap_or_default()
+    }
 
-            let mut position = InvoicePosition::from_worklog_record(&record, worklog.rate());
+    /// Positions in the order they appear on the invoice, see `position_sort`.
+    /// Positions with the same sort key are sorted by text.
+    /// With `positions_from = "chronological"`, the positions are in the order of their dates instead.
+    /// Percentage positions like fees always come last, in the order they were added.
+    pub fn sorted_positions(&self) -> Vec<&InvoicePosition> {
+        self.sorted_indices().into_iter().map(|index| &self.positions[index]).collect()
+    }
 
-            let mut key = String::new();
-            for tag in &record.tags() {
-                if tags.contains_key(tag) {
-                    key = tag.clone(); 
-                    position.text = self.recipient.tags().get(&key).unwrap().position_text.clone();
-                }
+    /// Indices of the positions in the order of `sorted_positions`.
+    fn sorted_indices(&self) -> Vec<usize> {
+        let mut indices = (0..self.positions.len()).collect::<Vec<_>>();
+        let percentage = |index: &usize| self.positions[*index].is_percentage();
+        if self.positions_from() == PositionsFrom::Chronological {
+            indices.sort_by_key(percentage);
+            return indices;
+        }
+        let position_sort = self.position_sort();
+        indices.sort_by(|a, b| {
+            let order = |p: &InvoicePosition| self.recipient.tag_order(p.tag()).unwrap_or(i32::MAX);
+            let (a, b) = (&self.positions[*a], &self.positions[*b]);
+            if a.is_percentage() || b.is_percentage() {
+                return a.is_percentage().cmp(&b.is_percentage());
             }
+            match position_sort {
+                PositionSort::Tag => order(a).cmp(&order(b)),
+                PositionSort::Alphabetical => std::cmp::Ordering::Equal,
+                PositionSort::AmountDesc => b.net().total_cmp(&a.net()),
+                PositionSort::AmountAsc => a.net().total_cmp(&b.net()),
+            }.then_with(|| a.text().cmp(b.text()))
+        });
+        indices
+    }
 
-            if key.is_empty() {
-                if let Some(default_tag_name) = self.recipient.default_tag_name() {
-                    key = default_tag_name.clone();
-                    position.text = self.recipient.tags().get(&key).unwrap().position_text.clone();
-                } else {
-                    key = record.message.clone();
-                }   
+    /// Set the base of each percentage position to the subtotal of the positions listed before it.
+    /// Earlier percentage positions are part of the base, the position itself is not.
+    fn update_percentage_positions(&mut self) {
+        let rounding = self.payment().rounding();
+        let round_positions = self.payment().round_positions();
+        let mut subtotal = 0.0_f32;
+        for index in self.sorted_indices() {
+            let position = &mut self.positions[index];
+            if position.is_percentage() {
+                position.price_per_item = subtotal;
             }
+            subtotal += if round_positions { rounding.round(position.net(), 2) } else { position.net() };
+        }
+    }
 
-            positions.entry(key).and_modify(|k| *k += position.clone()).or_insert(position);
-            
-            if self.generate_timesheet() {
-                if self.timesheet.is_none() {
-                    self.timesheet = Some(Timesheet::new(Path::new(&self.template_dir()).join(self.config.timesheet_template()), self.locale()));
+    /// Add a position for each fee of the recipient, unless the invoice is empty or has them already.
+    fn add_fees(&mut self) {
+        if self.positions.is_empty() || self.positions.iter().any(InvoicePosition::is_percentage) {
+            return;
+        }
+        for fee in &self.recipient.fees {
+            self.positions.push(InvoicePosition::percentage(&fee.text, fee.percent));
+        }
+    }
+
+    /// Default rate from the command line, the recipient or the payment config, in that order.
+    pub fn default_rate(&self) -> Option<f32> {
+        self.invoicer.rate()
+            .or(self.recipient.default_rate)
+            .or(self.payment().default_rate())
+    }
+
+    /// Deposit already paid from the command line or the recipient, as gross amount.
+    pub fn deposit(&self) -> Option<f32> {
+        self.invoicer.deposit().or(self.recipient.deposit)
+    }
+
+    /// Attached files of the recipient, followed by those from the command line.
+    pub fn attachments(&self) -> Vec<String> {
+        self.recipient.attachments.iter().chain(self.invoicer.attachments()).cloned().collect()
+    }
+
+    /// Amount left to pay after subtracting the deposit from the total.
+    pub fn balance_due(&self) -> f32 {
+        self.payment().rounding().round(self.total() - self.deposit().unwrap_or_default(), 2)
+    }
+
+    /// Rate for worklog records without an own rate.
+    /// Falls back to the payment's `fallback_rate` unless `require_explicit_rate` is set.
+    pub fn worklog_rate(&self, worklog: &Worklog) -> Result<f32, InvoicerError> {
+        self.check_worklog_currency(worklog)?;
+
+        if let Some(rate) = self.default_rate() {
+            return Ok(rate);
+        }
+
+        let payment = self.payment();
+        if payment.require_explicit_rate() {
+            if let Some(record) = worklog.records().iter().find(|record| record.rate.is_none()) {
+                return Err(InvoicerError::InvalidWorklog(
+                    format!("Record '{} {}' for recipient '{}' has no rate and no default rate is configured", record.start, record.message, self.recipient.name())));
+            }
+        }
+
+        Ok(payment.fallback_rate())
+    }
+
+    /// Check that all currency-qualified rates of the worklog are in the invoice currency.
+    pub fn check_worklog_currency(&self, worklog: &Worklog) -> Result<(), InvoicerError> {
+        let currency = self.currency();
+        match worklog.records().iter().find(|record| record.currency().is_some_and(|c| *c != currency)) {
+            Some(record) => Err(InvoicerError::InvalidWorklog(
+                format!("Mixed currencies for recipient '{}': record '{} {}' is in {:?}, the invoice is in {:?}", 
+                    self.recipient.name(), record.start, record.message, record.currency().unwrap(), currency))),
+            None => Ok(())
+        }
+    }
+
+    pub fn generate_timesheet(&self) -> bool {
+        (self.config.timesheet() && !self.config.timesheet_template().is_empty()) || self.timesheet.is_some()
+    }
+
+    /// Grouping of untagged records from the recipient or the config, by message by default.
+    pub fn group_untagged_by(&self) -> UntaggedGrouping {
+        self.recipient.invoice.group_untagged_by
+            .or(self.config.group_untagged_by)
+            .unwrap_or_default()
+    }
+
+    fn add_to_timesheet(&mut self, record: WorklogRecord) {
+        if self.timesheet.is_none() {
+            let mut timesheet = Timesheet::new(Path::new(&self.template_dir()).join(self.config.timesheet_template()), self.locale(), self.config.timesheet_columns());
+            timesheet.set_search_path(self.invoicer.template_search_path());
+            self.timesheet = Some(timesheet);
+        }
+        self.timesheet.as_mut().unwrap().add_record(record);
+    }
+
+    pub fn add_worklog(&mut self, worklog: &Worklog) {
+        let mut positions: BTreeMap<String, InvoicePosition> = BTreeMap::new();
+        let mut tiered: BTreeMap<String, InvoicePosition> = BTreeMap::new();
+        let rate_tiers = self.recipient.rate_tiers();
+        let case_insensitive_tags = self.invoicer.config().worklog().case_insensitive_tags();
+        let group_untagged_by = self.group_untagged_by();
+        let chronological = self.positions_from() == PositionsFrom::Chronological;
+        let locale = self.locale();
+
+        for (index, record) in worklog.records().iter().enumerate() {
+            if !record.is_billable() {
+                if self.generate_timesheet() && self.config.timesheet_nonbillable() {
+                    let mut record = record.clone();
+                    record.rate = Some(Rate { value: 0.0, currency: None });
+                    self.add_to_timesheet(record);
+                }
+                continue;
+            }
+
+            self.begin_date = record.begin_date().min(self.begin_date);
+            self.end_date = record.end_date().max(self.end_date);
+
+            let tags = self.recipient.tags();
+
+            let mut position = InvoicePosition::from_worklog_record(&record, worklog.rate());
+
+            let mut key = String::new();
+            for tag in &record.tags() {
+                if let Some(recipient_tag) = tags.keys().find(|recipient_tag| tags_match(recipient_tag, tag, case_insensitive_tags)) {
+                    key = recipient_tag.clone(); 
+                    position.text = self.recipient.tags().get(&key).unwrap().position_text.clone();
+                    position.tag = Some(key.clone());
                 }
-                self.timesheet.as_mut().unwrap().add_record(record.clone());
+            }
+
+            if key.is_empty() {
+                if let Some(default_tag_name) = self.recipient.default_tag_name() {
+                    key = default_tag_name.clone();
+                    position.text = self.recipient.tags().get(&key).unwrap().position_text.clone();
+                    position.tag = Some(key.clone());
+                } else {
+                    key = match group_untagged_by {
+                        UntaggedGrouping::Message => record.message.clone(),
+                        UntaggedGrouping::Day => {
+                            // Label the day, not the record, so records of one day share the text of their position
+                            let day = record.begin_date().date().and_time(chrono::NaiveTime::MIN);
+                            position.text = locale.format_date(day, &self.config.date_format());
+                            day.format("%Y-%m-%d").to_string()
+                        }
+                        UntaggedGrouping::None => format!("record {index:06}"),
+                    };
+                }   
+            }
+
+            if chronological {
+                key = format!("{} {index:06}", record.begin_date().format("%Y-%m-%d %H:%M"));
+                position.text = format!("{}: {}", locale.format_date(record.begin_date(), &self.config.date_format()), record.message);
+            }
+
+            // Records in other units than hours become separate positions, since amounts of different units can not be added
+            if !record.is_hours() {
+                key = format!("{key} [{}]", record.unit());
+            }
+
+            // Records with an own rate or in other units keep their rate and do not count towards the rate tiers
+            let target = if rate_tiers.is_empty() || record.rate.is_some() || !record.is_hours() { &mut positions } else { &mut tiered };
+            target.entry(key).and_modify(|k| *k += position.clone()).or_insert(position);
+            
+            if self.generate_timesheet() {
+                self.add_to_timesheet(record.clone());
+            }
+        }
+
+        // Round the merged hours of each position, records in other units are kept as they are
+        if let Some(increment) = self.position_hour_rounding() {
+            for position in positions.values_mut().chain(tiered.values_mut()).filter(|position| position.unit == HOURS_UNIT) {
+                position.amount = ((position.amount / increment) - 1.0e-4).ceil() * increment;
             }
         }
 
@@ -368,85 +1295,406 @@ impl<'a> Invoice<'a> {
             self.positions.push(position)
         }
 
+        for (_, position) in tiered {
+            let positions = self.split_into_tiers(position, &rate_tiers);
+            self.positions.extend(positions);
+        }
+
+        // Like the timesheet, sort by date each time a worklog was added
+        if chronological {
+            self.positions.sort_by_key(|position| position.date);
+        }
+
+        self.add_fees();
+        self.update_percentage_positions();
+
         // Sort timesheet each time a worklog was added
         if self.generate_timesheet() {
-            println!("Generated timesheet with {}", self.timesheet.as_ref().unwrap().len());
+            eprintln!("Generated timesheet with {}", self.timesheet.as_ref().unwrap().len());
             self.timesheet.as_mut().unwrap().sort();
         }
     }
 
+    /// Split a position billed at the default rate at the `from_hours` thresholds of the rate tiers.
+    /// The thresholds refer to the hours of all tiered positions of the invoice so far.
+    fn split_into_tiers(&mut self, position: InvoicePosition, rate_tiers: &[RateTier]) -> Vec<InvoicePosition> {
+        let begin = self.tiered_hours;
+        let end = begin + position.amount;
+        self.tiered_hours = end;
+
+        let mut bounds = vec![(0.0, position.price_per_item)];
+        bounds.extend(rate_tiers.iter().map(|tier| (tier.from_hours, tier.rate)));
+
+        let mut positions = Vec::new();
+        for (index, (from_hours, rate)) in bounds.iter().enumerate() {
+            let to_hours = bounds.get(index + 1).map(|(from_hours, _)| *from_hours).unwrap_or(f32::INFINITY);
+            let hours = end.min(to_hours) - begin.max(*from_hours);
+            if hours > 0.0 {
+                positions.push(InvoicePosition { amount: hours, price_per_item: *rate, ..position.clone() });
+            }
+        }
+        positions
+    }
+
     pub fn number(&self) -> String {
         self.number.clone()
     }
 
+    /// Invoice number for a counter, `%B` and `%b` are replaced by the translated month name.
     pub fn number_with_counter(&self, counter: u32) -> String {
-        self.config.number_format()
+        self.locale().translate_month(&self.config.number_format(), self.date().month())
             .replace("%Y", format!("{:04}", self.date().year()).as_str())
             .replace("%m", format!("{:02}", self.date().month()).as_str())
             .replace("${COUNTER}", format!("{:02}", counter).as_str())
+            .replace("${RECIPIENT}", self.recipient.name())
     }
 
 
-    fn begin_date(&self) -> DateTime {
+    /// Begin of the performance period, i.e. the start of the earliest worklog record.
+    pub fn begin_date(&self) -> DateTime {
         self.begin_date
     }
 
-    fn end_date(&self) -> DateTime {
+    /// End of the performance period, i.e. the end of the latest worklog record.
+    pub fn end_date(&self) -> DateTime {
         self.end_date
     }
 
-    pub fn sum(&self) -> f32 {
+    /// Set the performance period, which is otherwise taken from the worklog.
+    pub fn set_period(&mut self, begin_date: DateTime, end_date: DateTime) {
+        self.begin_date = begin_date;
+        self.end_date = end_date;
+    }
+
+    pub fn recipient(&self) -> &Recipient {
+        &self.recipient
+    }
+
+    pub fn config(&self) -> &InvoiceConfig {
+        self.config
+    }
+
+    pub fn invoicer(&self) -> &Invoicer {
+        self.invoicer
+    }
+
+    pub fn timesheet(&self) -> Option<&Timesheet> {
+        self.timesheet.as_ref()
+    }
+
+    /// Subject of the invoice from the recipient or the config, `${PERIOD}` by default.
+    /// `${MONTH}` is replaced by the month and year of the begin of the period, e.g. `March 2024`.
+    /// `${PERIOD}` is the month if the period lies within one month, otherwise the begin and end date.
+    pub fn subject(&self) -> String {
+        let subject = self.recipient.invoice.subject.clone()
+            .or(self.config.subject.clone())
+            .unwrap_or("${PERIOD}".to_string());
+
+        let locale = self.locale();
+        let month = locale.format_date(self.begin_date, "%B %Y");
+        let period = if (self.begin_date.year(), self.begin_date.month()) == (self.end_date.year(), self.end_date.month()) {
+            month.clone()
+        } else {
+            let date_format = self.config.date_format();
+            format!("{}{}{}", locale.format_date(self.begin_date, &date_format), locale.period_separator(), locale.format_date(self.end_date, &date_format))
+        };
+
+        subject
+            .replace("${MONTH}", &month)
+            .replace("${PERIOD}", &period)
+    }
+
+    /// Days for payment from the command line, the recipient or the config, in that order, 14 by default.
+    pub fn days_for_payment(&self) -> u32 {
+        self.invoicer.days_for_payment()
+            .or(self.recipient.invoice.days_for_payment)
+            .unwrap_or(self.config.days_for_payment())
+    }
+
+    /// Purchase order number from the command line or the recipient.
+    pub fn po_number(&self) -> Option<String> {
+        self.invoicer.po_number().or(self.recipient.po_number()).cloned()
+    }
+
+    /// Date by which the invoice has to be paid, `days_for_payment` after the invoice date.
+    pub fn due_date(&self) -> DateTime {
+        self.date() + chrono::Duration::days(self.days_for_payment() as i64)
+    }
+
+    /// Payment terms of the recipient or the `paymentterms` translation, `Payable within ${DAYS} days` by default.
+    /// The translation has the plural forms `paymentterms.one` and `paymentterms.other` for the days for payment.
+    /// `${DAYS}` is replaced by the days for payment, `${DUEDATE}` by the due date.
+    pub fn payment_terms(&self) -> String {
+        let days = self.days_for_payment();
+        let terms = self.recipient.payment_terms.clone()
+            .or(self.locale().tr_plural("paymentterms", days))
+            .unwrap_or("Payable within ${DAYS} days".to_string());
+
+        terms
+            .replace("${DAYS}", &days.to_string())
+            .replace("${DUEDATE}", &self.locale().format_date(self.due_date(), &self.config.date_format()))
+    }
+
+    /// Performance period as one text, e.g. for `\invoiceperiod`.
+    /// A single day is shown as one date, a complete month as month and year, e.g. `March 2024`.
+    /// Other periods are shown as begin and end date, joined with the `period_separator` of the locale.
+    pub fn period(&self) -> String {
+        let locale = self.locale();
+        let date_format = self.config.date_format();
+        let (begin, end) = (self.begin_date.date(), self.end_date.date());
+
+        let last_of_month = begin.with_day(1)
+            .and_then(|first| first.checked_add_months(chrono::Months::new(1)))
+            .and_then(|next| next.pred_opt());
+
+        if begin == end {
+            locale.format_date(self.begin_date, &date_format)
+        } else if begin.day() == 1 && Some(end) == last_of_month {
+            locale.format_date(self.begin_date, "%B %Y")
+        } else {
+            format!("{}{}{}", locale.format_date(self.begin_date, &date_format), locale.period_separator(), locale.format_date(self.end_date, &date_format))
+        }
+    }
+
+    /// Template for this invoice.
+    /// A template given to the invoicer, e.g. with `--template`, always wins.
+    /// If all positions share one tag with a template, the tag's template is used.
+    /// Otherwise, the template of the recipient or the global config is used.
+    pub fn template(&self) -> String {
+        if let Some(template) = self.invoicer.template() {
+            return template.clone();
+        }
+
+        let mut position_tags = self.positions.iter().map(|position| position.tag());
+        if let Some(Some(first)) = position_tags.next() {
+            if position_tags.all(|tag| tag == Some(first)) {
+                if let Some(template) = self.recipient.tags().get(first).and_then(|info| info.template()) {
+                    return template.clone();
+                }
+            }
+        }
+
+        self.recipient.invoice.template.clone().unwrap_or(self.config.template())
+    }
+
+    /// Hours and net sum of the positions of each recipient tag, untagged positions (`None`) last.
+    /// Tags are ordered like the positions with `position_sort = "tag"`.
+    pub fn tag_breakdown(&self) -> Vec<(Option<String>, f32, f32)> {
+        let rounding = self.payment().rounding();
+        let mut breakdown: BTreeMap<(bool, i32, Option<String>), (f32, f32)> = BTreeMap::new();
+        for position in &self.positions {
+            let tag = position.tag().cloned();
+            let key = (tag.is_none(), self.recipient.tag_order(tag.as_ref()).unwrap_or(i32::MAX), tag);
+            let (hours, net) = breakdown.entry(key).or_default();
+            if position.unit == HOURS_UNIT {
+                *hours += position.amount;
+            }
+            *net += position.net();
+        }
+        breakdown.into_iter()
+            .map(|((_, _, tag), (hours, net))| (tag, hours, rounding.round(net, 2)))
+            .collect()
+    }
+
+    /// Sum of the amounts of all positions billed in hours.
+    pub fn total_hours(&self) -> f32 {
+        self.positions.iter()
+            .filter(|position| position.unit == HOURS_UNIT)
+            .map(|position| position.amount)
+            .sum()
+    }
+
+    /// Sum of the net amounts of all positions, each rounded to cents like on the invoice unless `round_positions` is false.
+    pub fn positions_sum(&self) -> f32 {
+        let rounding = self.payment().rounding();
+        let round_positions = self.payment().round_positions();
         let mut sum = 0.0_f32;
         for position in &self.positions {
-            sum += position.net();
+            sum += if round_positions { rounding.round(position.net(), 2) } else { position.net() };
+        }
+        rounding.round(sum, 2)
+    }
+
+    /// Sum of the positions capped at the `cap` of the recipient, before the discount of a promotion.
+    pub fn capped_sum(&self) -> f32 {
+        match self.recipient.cap {
+            Some(cap) => self.positions_sum().min(cap),
+            None => self.positions_sum(),
         }
-        sum
+    }
+
+    /// Discount of the promotion applied with `--promo`, zero without promotion.
+    pub fn discount(&self) -> f32 {
+        match self.invoicer.promotion() {
+            Some(promotion) => self.payment().rounding().round(promotion.discount(self.capped_sum()), 2),
+            None => 0.0,
+        }
+    }
+
+    /// Net sum of the invoice, i.e. the sum of the positions capped at the `cap` of the recipient, minus the discount.
+    pub fn sum(&self) -> f32 {
+        match self.invoicer.promotion() {
+            Some(_) => self.payment().rounding().round(self.capped_sum() - self.discount(), 2),
+            None => self.capped_sum(),
+        }
+    }
+
+    /// The sum of the positions exceeds the `cap` of the recipient.
+    pub fn is_capped(&self) -> bool {
+        self.recipient.cap.is_some_and(|cap| self.positions_sum() > cap)
     }
 
     pub fn sum_with_tax(&self) -> f32 {        
-        self.sum() * (1.0 + self.tax_rate() / 100.0)
+        self.payment().rounding().round(self.sum() + self.tax(), 2)
+    }
+
+    /// Total amount to pay, including VAT if it is calculated.
+    pub fn total(&self) -> f32 {
+        if self.calculate_value_added_tax() {
+            self.sum_with_tax()
+        } else {
+            self.sum()
+        }
     }
 
+    /// Sum of all tax amounts.
     pub fn tax(&self) -> f32 {
-        self.sum_with_tax() - self.sum() 
+        let rounding = self.payment().rounding();
+        rounding.round(self.taxes().iter().map(|(_, amount)| amount).sum(), 2)
+    }
+
+    /// Each tax with its amount, rounded to cents like on the invoice.
+    /// Tax-exempt recipients are charged no taxes.
+    pub fn taxes(&self) -> Vec<(Tax, f32)> {
+        if self.recipient.tax_exempt.is_some() {
+            return Vec::new();
+        }
+        self.payment().tax_amounts(self.sum())
     }
 
+    /// Write a `\invoicetax{label}{rate}{amount}` line for each tax.
+    fn generate_tax_lines(&self, w: &mut dyn Write) -> std::io::Result<()> {
+        let l = self.locale();
+        for (tax, amount) in self.taxes() {
+            writeln!(w, "\\invoicetax{{{label}}}{{{rate}}}{{{amount}}}",
+                label = tax.tex_label(),
+                rate = tax.rate(),
+                amount = l.format_amount(amount)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Payment account matching the invoice currency, the first account if there is none.
     pub fn payment(&self) -> &Payment {
-        &self.invoicer.config().payment()
+        self.invoicer.config().payment_for_currency(&self.currency())
     }
 
     pub fn tax_rate(&self) -> f32 {
-        self.payment().tax_rate
+        self.payment().tax_rate()
     }
 
+    /// Currency of the recipient or of the first payment account.
     pub fn currency(&self) -> Currency {
-        self.payment().currency()
+        self.recipient.currency.clone()
+            .unwrap_or(self.invoicer.config().payment().currency())
     }
 
+    /// Symbol of the invoice currency, overridden by the `currency_symbol` of the payment account in this currency.
     pub fn currency_symbol(&self) -> String {
-        self.payment().currency_symbol()
+        let payment = self.payment();
+        if payment.currency() == self.currency() {
+            payment.currency_symbol()
+        } else {
+            self.currency().symbol()
+        }
     }
 
+    /// Invoice is marked as paid, see `Invoicer::set_paid`.
+    pub fn paid(&self) -> bool {
+        self.invoicer.paid()
+    }
+
+    /// Taxes are calculated if enabled in the config and the recipient is not tax-exempt.
     pub fn calculate_value_added_tax(&self) -> bool {
-        self.config.calculate_value_added_tax()
+        self.config.calculate_value_added_tax() && self.recipient.tax_exempt.is_none()
     }
 
+    /// Boolean properties of the invoice for `%$IF:<flag>` sections of templates.
+    pub fn template_flags(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("calculate_value_added_tax", self.calculate_value_added_tax()),
+            ("tax_exempt", self.recipient.tax_exempt.is_some()),
+            ("has_timesheet", self.generate_timesheet()),
+            ("has_discount", self.invoicer.promotion().is_some()),
+            ("has_deposit", self.deposit().is_some()),
+            ("has_attachments", !self.attachments().is_empty()),
+            ("is_capped", self.is_capped()),
+            ("payment_qr", self.config.payment_qr()),
+            ("paid", self.paid()),
+        ]
+    }
+
+    /// File name of the tex file from `filename_format`.
+    /// Path separators in the recipient name are replaced, with `ascii_filenames` also umlauts, accents and spaces.
     pub fn filename(&self) -> String {
-        self.config.filename_format()
+        let filename = self.config.filename_format()
             .replace("${INVOICENUMBER}", self.number().as_str())
             .replace("${INVOICE}", &self.locale().tr("invoice".to_string()))
-            .replace("${RECIPIENT}", &self.recipient.name)
+            .replace("${RECIPIENT}", &sanitize_filename(&self.recipient.name, self.config.ascii_filenames()));
+        match self.revision {
+            Some(revision) => with_revision(&filename, revision),
+            None => filename,
+        }
+    }
+
+    /// Append `_rev<revision>` to the file name, e.g. if a file with the same name already exists.
+    pub fn set_revision(&mut self, revision: u32) {
+        self.revision = Some(revision);
+    }
+
+    /// File name of the payment QR code image, next to the tex file.
+    pub fn qr_filename(&self) -> String {
+        let filename = self.filename();
+        format!("{}_qr.png", filename.strip_suffix(".tex").unwrap_or(&filename))
+    }
+
+    /// SEPA credit transfer for the balance due of this invoice,
+    /// with the invoice number and the purchase order number, if any, as reference.
+    pub fn epc_payment(&self) -> EpcPayment {
+        let payment = self.payment();
+        EpcPayment {
+            bic: payment.bic().clone(),
+            name: payment.accountholder().cloned().unwrap_or(self.invoicer.config().contact().fullname().clone()),
+            iban: payment.iban().clone(),
+            currency: self.currency().str().clone(),
+            amount: self.balance_due(),
+            reference: match self.po_number() {
+                Some(po_number) => format!("{} {po_number}", self.number()),
+                None => self.number(),
+            },
+        }
     }
 }
 
 
 impl<'a> Fingerprint for Invoice<'a> {
+    /// SHA-256 hash identifying the invoice of a recipient for a period, see `InvoiceFingerprints`.
+    /// It is built from the begin and end date of the period, all settings of the recipient
+    /// (name, contact, `[invoice]` section, rates, currency, payment terms, PO number, attachments, custom fields and tags)
+    /// and the effective `days_for_payment`, including `--days-for-payment`,
+    /// as well as the tag, date, text, amount, price and unit of each position.
+    /// Any change of the positions, e.g. a corrected worklog, leads to a new invoice number.
     fn fingerprint(&self) -> String {
-        format!("{}\n{}\n{:?}\n{:?}", 
+        let positions = self.positions.iter()
+            .map(|position| format!("{:?} {:?} {} {} {} {}", position.tag, position.date, position.text, position.amount, position.price_per_item, position.unit))
+            .collect::<Vec<_>>();
+        format!("{}\n{}\n{:?}\n{:?}\n{}", 
             self.begin_date(),
             self.end_date(),
             self.recipient,
-            self.config.days_for_payment()
+            self.days_for_payment(),
+            positions.join("\n")
         ).fingerprint()
     }
 }
@@ -458,19 +1706,52 @@ struct InvoiceDetails {
     number: String,
     periodbegin: String,
     periodend: String,
+    period: String,
     daysforpayment: u32,
+    duedate: String,
+    paymentterms: String,
+    paid: Option<String>,
+    qr: Option<String>,
+    totalhours: String,
+    deposit: Option<String>,
+    balancedue: Option<String>,
+    taxexemptreason: Option<String>,
+    ponumber: Option<String>,
+    subject: String,
+    logo: Option<String>,
 }
 
 impl InvoiceDetails {
     pub fn from_invoice<'a>(invoice: &'a Invoice) -> Self {
         let date_format = invoice.config.date_format();
+        let locale = invoice.locale();
 
         Self {
-            date: date_to_str(invoice.date(), &date_format),
+            date: locale.format_date(invoice.date(), &date_format),
             number: invoice.number(),
-            periodbegin: date_to_str(invoice.begin_date(), &date_format),
-            periodend: date_to_str(invoice.end_date(), &date_format),
-            daysforpayment: invoice.config.days_for_payment()
+            periodbegin: locale.format_date(invoice.begin_date(), &date_format),
+            periodend: locale.format_date(invoice.end_date(), &date_format),
+            period: invoice.period(),
+            daysforpayment: invoice.days_for_payment(),
+            duedate: locale.format_date(invoice.due_date(), &date_format),
+            paymentterms: invoice.payment_terms(),
+            paid: if invoice.paid() {
+                Some(invoice.locale().translation("paid").cloned().unwrap_or("PAID".to_string()))
+            } else {
+                None
+            },
+            qr: if invoice.config.payment_qr() {
+                Some(invoice.qr_filename())
+            } else {
+                None
+            },
+            totalhours: invoice.locale().format_number(invoice.total_hours(), 2),
+            deposit: invoice.deposit().map(|deposit| invoice.locale().format_amount(deposit)),
+            balancedue: invoice.deposit().map(|_| invoice.locale().format_amount(invoice.balance_due())),
+            taxexemptreason: invoice.recipient.tax_exempt.clone(),
+            ponumber: invoice.po_number(),
+            subject: invoice.subject(),
+            logo: invoice.invoicer.config().logo().map(|logo| logo.to_string_lossy().to_string()),
         } 
     }
 }
@@ -480,10 +1761,13 @@ impl GenerateTexCommands for InvoiceDetails {}
 
 #[derive(Clone)]
 pub struct InvoicePosition {
+    tag: Option<String>,
+    date: Option<DateTime>,
     text: String,
     amount: f32,
     price_per_item: f32,
-    unit: String 
+    unit: String,
+    percentage: bool,
 }
 
 impl AddAssign for InvoicePosition {
@@ -493,10 +1777,16 @@ impl AddAssign for InvoicePosition {
 
         let sum = self.amount + other.amount; 
         *self = InvoicePosition {
+            tag: self.tag.clone(),
+            date: match (self.date, other.date) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
             text: self.text.clone(), 
             amount: sum,
             price_per_item: (self.amount * self.price_per_item + other.amount * other.price_per_item) / sum,
-            unit: self.unit.clone()
+            unit: self.unit.clone(),
+            percentage: self.percentage,
         }
     }
 }
@@ -504,25 +1794,107 @@ impl AddAssign for InvoicePosition {
 
 
 impl InvoicePosition {
+    pub fn new(text: &str, amount: f32, price_per_item: f32, unit: &str) -> Self {
+        Self {
+            tag: None,
+            date: None,
+            text: text.to_string(),
+            amount,
+            price_per_item,
+            unit: unit.to_string(),
+            percentage: false,
+        }
+    }
+
+    /// Position of `percent` of the positions listed before it, see `Invoice::sorted_positions`.
+    /// Its price per item is this base, set by the invoice.
+    pub fn percentage(text: &str, percent: f32) -> Self {
+        Self {
+            tag: None,
+            date: None,
+            text: text.to_string(),
+            amount: percent,
+            price_per_item: 0.0,
+            unit: String::from("%"),
+            percentage: true,
+        }
+    }
+
     pub fn from_worklog_record(w: &WorklogRecord, default_rate: f32) -> Self {
         Self {
+            tag: None,
+            date: Some(w.begin_date()),
             text: w.message.clone(),
             amount: w.hours,
-            price_per_item: w.rate.unwrap_or(default_rate),
-            unit: String::from("h")
+            price_per_item: w.rate_value().unwrap_or(default_rate),
+            unit: w.unit().to_string(),
+            percentage: false,
         }
     }
 
-    fn net(&self) -> f32 {
-        self.amount * self.price_per_item
+    /// Recipient tag the position was created for, if any.
+    pub fn tag(&self) -> Option<&String> {
+        self.tag.as_ref()
+    }
+
+    /// Begin of the earliest worklog record of the position, `None` for positions not created from a worklog.
+    pub fn date(&self) -> Option<DateTime> {
+        self.date
+    }
+
+    pub fn text(&self) -> &String {
+        &self.text
+    }
+
+    pub fn amount(&self) -> f32 {
+        self.amount
+    }
+
+    pub fn price_per_item(&self) -> f32 {
+        self.price_per_item
+    }
+
+    pub fn unit(&self) -> &String {
+        &self.unit
+    }
+
+    /// Whether the amount is a percentage of the positions before, e.g. a fee.
+    pub fn is_percentage(&self) -> bool {
+        self.percentage
+    }
+
+    /// Net amount of the position, i.e. amount times price per item, or the percentage of the base for percentage positions.
+    pub fn net(&self) -> f32 {
+        match self.percentage {
+            true => self.amount * self.price_per_item / 100.0,
+            false => self.amount * self.price_per_item,
+        }
     }
 
-    fn generate_tex<'a>(&self, w: &'a mut dyn Write, l: &Locale) -> std::io::Result<()> {
+    /// Write the position as `\position` command, with the amount formatted with `amount_precision`
+    /// and the rate with `rate_precision` decimals of the config.
+    /// The text is truncated to `position_text_max_len` characters if given.
+    /// Percentage positions have the percentage in the text and no amount and rate.
+    fn generate_tex<'a>(&self, w: &'a mut dyn Write, l: &Locale, config: &InvoiceConfig) -> std::io::Result<()> {
+        let text_max_len = config.position_text_max_len();
+        if self.percentage {
+            return writeln!(w, "\\position{{{text} ({percent}\\%)}}{{}}{{}}{{{net}}}",
+                text = match text_max_len {
+                    Some(max_len) => truncate_tex(&self.text, max_len),
+                    None => self.text.clone(),
+                },
+                percent = l.format_number(self.amount, 2),
+                net = l.format_amount(self.net()));
+        }
+
         writeln!(w, "\\position{{{text}}}{{{amount}{unit}}}{{{rate}}}{{{net}}}", 
-            text = self.text,
-            amount = l.format_number(self.amount, 2),
-            unit = self.unit,
-            rate = format!("{p}{currency}/{unit}", p = self.price_per_item, currency = l.currency().symbol(), unit = self.unit),
+            text = match text_max_len {
+                Some(max_len) => truncate_tex(&self.text, max_len),
+                None => self.text.clone(),
+            },
+            amount = l.format_number(self.amount, config.amount_precision()),
+            unit = l.unit(&self.unit),
+            rate = format!("{p}{currency}/{unit}", p = l.format_number(self.price_per_item, config.rate_precision()), currency = l.currency_symbol(), unit = l.unit(&self.unit)),
             net = l.format_amount(self.net()))
     }
 }
@@ -531,7 +1903,11 @@ impl InvoicePosition {
 
 impl<'a> GenerateTex for Invoice<'a> {
     fn generate_tex(&self, w: &mut dyn Write) -> std::io::Result<()> {
-        let mut template = TexTemplate::new(self.invoicer.template_dir().join(self.config.template())); 
+        let mut template = TexTemplate::from_search_path(&self.invoicer.template_search_path(), &self.template());
+        template.strict(self.invoicer.strict_template());
+        for (name, value) in self.template_flags() {
+            template.flag(name, value);
+        }
         
         template
             .token("INVOICE_SUMMARY", |w| {
@@ -545,45 +1921,125 @@ impl<'a> GenerateTex for Invoice<'a> {
             .token("INVOICE_FINGERPRINT", |w| {
                 writeln!(w, "% {}", self.fingerprint())
             })
-            .token("LANGUAGE", |w| {
+            .required_token("LANGUAGE", |w| {
                 self.locale().generate_tex(w)
             })
-            .token("RECIPIENT_ADDRESS", |w| {            
-                self.recipient.generate_tex_commands(w, "recipient")
+            .required_token("RECIPIENT_ADDRESS", |w| {            
+                self.recipient.generate_tex_commands(w, "recipient")?;
+                let l = self.locale();
+                generate_tex_command(w, "recipientaddressblock", &self.recipient.contact().address_block(l.address_format(), l.address_separator()))
             })
-            .token("BILLER_ADDRESS", |w| {            
-                self.invoicer.config().contact().generate_tex_commands(w, "my")
+            .required_token("BILLER_ADDRESS", |w| {            
+                let contact = self.invoicer.config().contact();
+                contact.generate_tex_commands(w, "my")?;
+                let l = self.locale();
+                generate_tex_command(w, "myaddressblock", &contact.address_block(l.address_format(), l.address_separator()))
             })
-            .token("PAYMENT_DETAILS", |w| {
+            .required_token("PAYMENT_DETAILS", |w| {
                 self.payment().generate_tex_commands(w, "my")
             })
-            .token("INVOICE_DETAILS", |w| {
+            .token("LEGAL", |w| {
+                let legal = self.invoicer.config().legal();
+                generate_tex_commands_for_map(w, "legal", legal)?;
+
+                let mut keys = legal.keys().collect::<Vec<_>>();
+                keys.sort();
+                if !keys.is_empty() {
+                    let footer = keys.iter().map(|key| legal[*key].as_str()).collect::<Vec<_>>().join("\\sepdot{}");
+                    generate_tex_command(w, "legalfooter", &footer)?;
+                }
+                Ok(())
+            })
+            .required_token("INVOICE_DETAILS", |w| {
                 let details = InvoiceDetails::from_invoice(&self);
                 details.generate_tex_commands(w, "invoice")
             })
-            .token("INVOICE_POSITIONS", |w: &mut dyn Write| {
-                for position in &self.positions {
-                    position.generate_tex(w, &self.locale())?;
+            .required_token("INVOICE_POSITIONS", |w: &mut dyn Write| {
+                for position in self.sorted_positions() {
+                    position.generate_tex(w, &self.locale(), self.config)?;
                 }
                 Ok(())
             })
-            .token("INVOICE_SUM", |w: &mut dyn Write| {
+            .required_token("INVOICE_SUM", |w: &mut dyn Write| {
                 let l = self.locale();                
-                if self.config.calculate_value_added_tax() {
-                    writeln!(w, "\\invoicesum{{{sum}}}{{{tax_rate}}}{{{tax}}}{{{sum_with_tax}}}", 
-                        sum = l.format_amount(self.sum()), 
-                        tax_rate = self.tax_rate(), 
-                        tax = l.format_amount(self.tax()), 
-                        sum_with_tax = l.format_amount(self.sum_with_tax()) 
-                    )
+                if self.is_capped() {
+                    writeln!(w, "\\invoicecap{{{positions_sum}}}{{{sum}}}",
+                        positions_sum = l.format_amount(self.positions_sum()),
+                        sum = l.format_amount(self.sum())
+                    )?;
+                }
+
+                if let Some(promotion) = self.invoicer.promotion() {
+                    writeln!(w, "\\invoicediscount{{{name}}}{{{percent}}}{{{discount}}}",
+                        name = promotion.name(),
+                        percent = match promotion.kind() {
+                            PromotionKind::Percent => format!("{}\\%", promotion.value()),
+                            PromotionKind::Absolute => String::new(),
+                        },
+                        discount = l.format_amount(self.discount())
+                    )?;
+                }
+
+                if self.calculate_value_added_tax() {
+                    writeln!(w, "\\invoicesubtotal{{{sum}}}", sum = l.format_amount(self.sum()))?;
+                    self.generate_tax_lines(w)?;
+                    writeln!(w, "\\invoicetotal{{{sum_with_tax}}}", sum_with_tax = l.format_amount(self.sum_with_tax()))?;
                 } else {
                     writeln!(w, "\\invoicesumnotax{{{sum}}}",
                         sum = l.format_amount(self.sum()), 
-                    )
+                    )?;
+                }
+
+                if let Some(deposit) = self.deposit() {
+                    writeln!(w, "\\invoicedeposit{{{deposit}}}{{{balance_due}}}",
+                        deposit = l.format_amount(deposit),
+                        balance_due = l.format_amount(self.balance_due())
+                    )?;
+                }
+                Ok(())
+            })
+            .token("TAXES", |w| {
+                if self.calculate_value_added_tax() {
+                    self.generate_tax_lines(w)?;
+                    writeln!(w, "\\invoicetaxtotal{{{tax}}}", tax = self.locale().format_amount(self.tax()))?;
+                }
+                Ok(())
+            })
+            .token("TAG_BREAKDOWN", |w| {
+                let l = self.locale();
+                writeln!(w, "\\begin{{invoicetagbreakdown}}")?;
+                for (tag, hours, net) in self.tag_breakdown() {
+                    let label = match &tag {
+                        Some(tag) => self.recipient.tags().get(tag)
+                            .map(|info| info.position_text().clone())
+                            .filter(|text| !text.is_empty())
+                            .unwrap_or(tag.clone()),
+                        None => "\\truntagged".to_string(),
+                    };
+                    writeln!(w, "\\tagbreakdown{{{label}}}{{{hours}}}{{{net}}}",
+                        hours = l.format_number(hours, 2),
+                        net = l.format_amount(net)
+                    )?;
                 }
+                writeln!(w, "\\end{{invoicetagbreakdown}}")
+            })
+            .token("ATTACHMENTS", |w| {
+                let attachments = self.attachments();
+                if !attachments.is_empty() {
+                    writeln!(w, "\\begin{{invoiceattachments}}")?;
+                    for attachment in attachments {
+                        let name = Path::new(&attachment).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(attachment);
+                        writeln!(w, "\\attachment{{{name}}}")?;
+                    }
+                    writeln!(w, "\\end{{invoiceattachments}}")?;
+                }
+                Ok(())
             })
             .token("INVOICE_VALUE_TAX_NOTE", |w| {
-                if !self.config.calculate_value_added_tax() {
+                if self.recipient.tax_exempt.is_some() {
+                    writeln!(w, "\\invoicetaxexemptreason\\\\")?;
+                    writeln!(w, "\\vspace{{0.5cm}}")
+                } else if !self.config.calculate_value_added_tax() {
                     writeln!(w, "\\trinvoicevaluetaxnote")
                 } else {
                     Ok(())
@@ -592,7 +2048,7 @@ impl<'a> GenerateTex for Invoice<'a> {
             .token("TIMESHEET", |w| {
                 if self.generate_timesheet() {
                     writeln!(w, "\\newpage")?;
-                    self.timesheet.as_ref().unwrap().generate_tex(w)?; 
+                    self.timesheet.as_ref().unwrap().generate_tex_with_flags(w, &self.template_flags())?;
                 }
                 Ok(())
             })
@@ -604,3 +2060,1058 @@ impl<'a> GenerateTex for Invoice<'a> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{Invoice, InvoiceConfig, InvoiceDetails, InvoicePosition, Recipient, RecipientTagInfo, Tax, Timesheet, TimesheetColumn};
+    use crate::locale::{Currency, Locale};
+    use crate::helpers::FromTomlFile;
+    use crate::generate_tex::{GenerateTex, GenerateTexCommands};
+    use crate::invoicer::{Config, Invoicer};
+    use crate::worklog::Worklog;
+
+    const CONFIG: &str = r#"
+[directories]
+config = "${WORKING_DIR}"
+locales = "${CONFIG_DIR}/locales"
+
+[contact]
+fullname = "John Doe"
+street = "123 Fake St."
+zipcode = 1234
+email = "john@doe.com"
+city = "Berlin"
+
+[payment]
+iban = "DE123456789012345678"
+bic = "MYBANKID"
+taxid = "12345678"
+tax_rate = 19.0
+default_rate = 100.0
+
+[invoice]
+locale = "en"
+timesheet = false
+"#;
+
+    const RECIPIENT: &str = r#"
+[contact]
+fullname = "Jane Doe"
+street = "Musterstraße 12"
+email = "jane@doe.com"
+zipcode = 1234
+city = "Berlin"
+
+[invoice]
+
+[tags]
+"#;
+
+    fn invoicer(config: &str) -> Invoicer {
+        Invoicer::new(toml::from_str::<Config>(config).unwrap(), None, None)
+    }
+
+    fn recipient(toml: &str) -> Recipient {
+        let mut recipient: Recipient = toml::from_str(toml).unwrap();
+        recipient.name = "Client".to_string();
+        recipient
+    }
+
+    fn worklog(csv: &str) -> Worklog {
+        Worklog::from_csv(csv.as_bytes()).unwrap()
+    }
+
+    fn assert_approx_eq(a: f32, b: f32) {
+        assert!((a - b).abs() < 1.0e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn position_net() {
+        let position = InvoicePosition::new("Development", 2.5, 100.0, "h");
+        assert_approx_eq(position.net(), 250.0);
+    }
+
+    #[test]
+    fn position_add_assign_weighted_price() {
+        let mut position = InvoicePosition::new("Development", 2.0, 100.0, "h");
+        position += InvoicePosition::new("Development", 3.0, 50.0, "h");
+
+        assert_approx_eq(position.amount(), 5.0);
+        assert_approx_eq(position.price_per_item(), 70.0);
+        assert_approx_eq(position.net(), 350.0);
+        assert_eq!(position.unit(), "h");
+    }
+
+    #[test]
+    #[should_panic]
+    fn position_add_assign_different_units() {
+        let mut position = InvoicePosition::new("Development", 2.0, 100.0, "h");
+        position += InvoicePosition::new("Development", 3.0, 50.0, "pcs");
+    }
+
+    #[test]
+    fn position_from_worklog_record() {
+        let csv = r#""Tags","Start","Hours","Rate","Message"
+"","10/04/2023 14:00",3,80,"Review"
+"","10/05/2023 14:00",2,,"Setup"
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        let records = worklog.records();
+
+        let position = InvoicePosition::from_worklog_record(&records[0], 100.0);
+        assert_eq!(position.text(), "Review");
+        assert_approx_eq(position.net(), 240.0);
+
+        let position = InvoicePosition::from_worklog_record(&records[1], 100.0);
+        assert_approx_eq(position.price_per_item(), 100.0);
+        assert_approx_eq(position.net(), 200.0);
+    }
+
+    #[test]
+    fn position_rate_localized() {
+        let locale = Locale::from_toml_file(std::path::Path::new("locales/de.toml")).unwrap();
+        let position = InvoicePosition::new("Development", 1.5, 99.5, "h");
+
+        let config: InvoiceConfig = toml::from_str("").unwrap();
+
+        let mut output = Vec::new();
+        position.generate_tex(&mut output, &locale, &config).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\\position{Development}{1,50Std.}{99,50€/Std.}{149,25€}\n");
+
+        let mut output = Vec::new();
+        let config: InvoiceConfig = toml::from_str("rate_precision = 0").unwrap();
+        InvoicePosition::new("Development", 1.0, 1200.0, "d").generate_tex(&mut output, &locale, &config).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("{1.200€/Tage}"));
+    }
+
+    #[test]
+    fn invoice_currency_overrides_locale() {
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(&format!("currency = \"USD\"\n{RECIPIENT}")));
+        assert_eq!(*invoice.locale().currency(), Currency::from("USD".to_string()));
+        invoice.add_position(InvoicePosition::new("Consulting", 2.0, 100.0, "h"));
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\position{Consulting}{2.00h}{100.00$/h}{200.00$}"), "{output}");
+        assert!(!output.contains('€'), "{output}");
+
+        // Invoices in the currency of the locale keep its symbol
+        let invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        assert_eq!(invoice.locale().currency_symbol(), "€");
+    }
+
+    #[test]
+    fn amount_and_rate_precision() {
+        let locale = Locale::from_toml_file(std::path::Path::new("locales/en.toml")).unwrap();
+        let position = InvoicePosition::new("API calls", 1234.5, 0.085, "h");
+
+        // The rate keeps its third decimal while the amounts stay at 2 decimals
+        let config: InvoiceConfig = toml::from_str("rate_precision = 3").unwrap();
+        let mut output = Vec::new();
+        position.generate_tex(&mut output, &locale, &config).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\\position{API calls}{1,234.50h}{0.085€/h}{104.93€}\n");
+
+        let config: InvoiceConfig = toml::from_str("rate_precision = 3\namount_precision = 1").unwrap();
+        let mut output = Vec::new();
+        position.generate_tex(&mut output, &locale, &config).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("{1,234.5h}{0.085€/h}"));
+    }
+
+    #[test]
+    fn position_text_max_len() {
+        let invoicer = invoicer(&CONFIG.replace("timesheet = false", "timesheet = false\nposition_text_max_len = 60"));
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        let message = "x".repeat(200);
+        invoice.add_worklog(&worklog(&format!(r#""Tags","Start","Hours","Message"
+"","10/04/2023 14:00",1,"{message}"
+"#)));
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("\\position{{{}\\ldots{{}}}}", "x".repeat(59))), "{output}");
+        assert!(!output.contains(&message));
+    }
+
+    #[test]
+    fn recipient_tag_info_annotations() {
+        let info = RecipientTagInfo::from("Software Development");
+        assert!(!info.is_default());
+        assert_eq!(info.position_text(), "Software Development");
+        assert!(info.template().is_none());
+
+        let info = RecipientTagInfo::from("[default]Software Development");
+        assert!(info.is_default());
+        assert_eq!(info.position_text(), "Software Development");
+
+        let info = RecipientTagInfo::from("[default][template=consulting.tex] Consulting");
+        assert!(info.is_default());
+        assert_eq!(info.position_text(), " Consulting");
+        assert_eq!(info.template().unwrap(), "consulting.tex");
+    }
+
+    #[test]
+    fn positions_sorted_by_tag_order() {
+        let invoicer = invoicer(CONFIG);
+        let recipient = recipient(&format!(r#"{RECIPIENT}
+alpha = "Alpha"
+beta = "Beta"
+zulu = "[order=1]Zulu"
+"#));
+        let mut invoice = Invoice::new(&invoicer, recipient);
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"beta","10/04/2023 14:00",1,"b"
+"zulu","10/05/2023 14:00",1,"z"
+"alpha","10/06/2023 14:00",1,"a"
+"#));
+
+        let texts = invoice.sorted_positions().iter().map(|p| p.text().clone()).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["Zulu", "Alpha", "Beta"]);
+    }
+
+    #[test]
+    fn legal_commands() {
+        let invoicer = invoicer(&format!(r#"{CONFIG}
+[legal]
+register = "HRB 12345"
+court = "Amtsgericht Berlin"
+"#));
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","10/04/2023 14:00",1,"Review"
+"#));
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\newcommand{\\legalregister}{HRB 12345}"));
+        assert!(output.contains("\\newcommand{\\legalfooter}{Amtsgericht Berlin\\sepdot{}HRB 12345}"));
+    }
+
+    #[test]
+    fn deposit() {
+        let invoicer = invoicer(&CONFIG.replace("tax_rate = 19.0", "tax_rate = 25.0"));
+        let recipient = recipient(&format!("deposit = 100.0\n{RECIPIENT}"));
+        let mut invoice = Invoice::new(&invoicer, recipient);
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","10/04/2023 14:00",4,"Review"
+"#));
+
+        assert_approx_eq(invoice.sum(), 400.0);
+        assert_approx_eq(invoice.total(), 500.0);
+        assert_eq!(invoice.deposit(), Some(100.0));
+        assert_approx_eq(invoice.balance_due(), 400.0);
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\invoicedeposit{100.00€}{400.00€}"), "{output}");
+        assert!(output.contains("\\newcommand{\\invoicebalancedue}{400.00€}"));
+    }
+
+    #[test]
+    fn named_taxes() {
+        let invoicer = invoicer(&CONFIG.replace("tax_rate = 19.0", r#"taxes = [{ name = "GST", rate = 5.0 }, { name = "PST", rate = 7.0 }]"#));
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","10/04/2023 14:00",4,"Review"
+"#));
+
+        assert_approx_eq(invoice.tax_rate(), 12.0);
+        assert_eq!(invoice.taxes().iter().map(|(_, amount)| *amount).collect::<Vec<_>>(), [20.0, 28.0]);
+        assert_approx_eq(invoice.tax(), 48.0);
+        assert_approx_eq(invoice.total(), 448.0);
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\invoicesubtotal{400.00€}\n\\invoicetax{GST}{5}{20.00€}\n\\invoicetax{PST}{7}{28.00€}\n\\invoicetotal{448.00€}"), "{output}");
+    }
+
+    #[test]
+    fn single_tax_rate() {
+        let invoicer = invoicer(CONFIG);
+        assert_eq!(invoicer.config().payment().taxes(), [Tax::new(None, 19.0)]);
+
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","10/04/2023 14:00",1,"Review"
+"#));
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("\\invoicetax{\\trvat}{19}{19.00€}"));
+    }
+
+    #[test]
+    fn month_name_in_number() {
+        let config = CONFIG.replace("locale = \"en\"", "locale = \"de\"\nnumber_format = \"%Y-%b-${COUNTER}\"\ndate_format = \"%d. %B %Y\"");
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 3).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let invoicer = Invoicer::new(toml::from_str::<Config>(&config).unwrap(), Some(date), None);
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.generate_number(3, None);
+
+        assert_eq!(invoice.number(), "2024-Mär-03");
+        assert_eq!(InvoiceDetails::from_invoice(&invoice).date, "03. März 2024");
+    }
+
+    #[test]
+    fn address_block() {
+        let contact = recipient(&RECIPIENT.replace("[contact]", "[contact]\ncountry = \"USA\"")).contact().clone();
+        let lines = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(contact.address_block(&lines(&["${COMPANYNAME}", "${FULLNAME}", "${STREET}", "${ZIPCODE} ${CITY}", "${COUNTRY}"]), "\\\\"),
+            "Jane Doe\\\\Musterstraße 12\\\\1234 Berlin\\\\USA");
+        assert_eq!(contact.address_block(&lines(&["${FULLNAME}", "${CITY}, ${ZIPCODE}", "${FAX}"]), ", "),
+            "Jane Doe, Berlin, 1234");
+
+        let invoicer = invoicer(CONFIG);
+        let invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("\\newcommand{\\recipientaddressblock}{Jane Doe\\\\Musterstraße 12\\\\1234 Berlin}"));
+    }
+
+    #[test]
+    fn attachments() {
+        let mut invoicer = invoicer(CONFIG);
+        invoicer.set_attachments(vec!["receipts/hotel_2023.pdf".to_string()]);
+        let recipient = recipient(&format!("attachments = [\"timesheet.pdf\"]\n{RECIPIENT}"));
+        let invoice = Invoice::new(&invoicer, recipient);
+        assert_eq!(invoice.attachments(), ["timesheet.pdf", "receipts/hotel_2023.pdf"]);
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\begin{invoiceattachments}\n\\attachment{timesheet.pdf}\n\\attachment{hotel_2023.pdf}\n\\end{invoiceattachments}"), "{output}");
+    }
+
+    #[test]
+    fn subject() {
+        let invoicer = invoicer(&CONFIG.replace("locale = \"en\"", "locale = \"de\"\ndate_format = \"%d.%m.%Y\""));
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","03/04/2024 14:00",1,"Review"
+"","03/28/2024 09:00",2,"Setup"
+"#));
+        assert_eq!(invoice.subject(), "März 2024");
+
+        let recipient = recipient(&RECIPIENT.replace("[invoice]", "[invoice]\nsubject = \"Beratung ${MONTH}, Zeitraum ${PERIOD}\""));
+        let mut invoice = Invoice::new(&invoicer, recipient);
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","02/27/2024 14:00",1,"Review"
+"","03/28/2024 09:00",2,"Setup"
+"#));
+        assert_eq!(invoice.subject(), "Beratung Februar 2024, Zeitraum 27.02.2024 -- 28.03.2024");
+        assert_eq!(InvoiceDetails::from_invoice(&invoice).subject, invoice.subject());
+    }
+
+    #[test]
+    fn group_untagged_by_day() {
+        let csv = r#""Tags","Start","Hours","Message"
+"","10/04/2023 09:00",2,"Review"
+"","10/04/2023 14:00",1.5,"Code review"
+"","10/05/2023 10:00",1,"Review"
+"#;
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.group_untagged_by(), crate::invoice::UntaggedGrouping::Message);
+        assert_eq!(invoice.positions().len(), 2);
+
+        let mut invoice = Invoice::new(&invoicer, recipient(&RECIPIENT.replace("[invoice]", "[invoice]\ngroup_untagged_by = \"day\"")));
+        invoice.add_worklog(&worklog(csv));
+        let positions = invoice.sorted_positions();
+        assert_eq!(positions.iter().map(|position| position.text().as_str()).collect::<Vec<_>>(), ["2023/10/04", "2023/10/05"]);
+        assert_approx_eq(positions[0].amount, 3.5);
+        assert_approx_eq(positions[1].amount, 1.0);
+
+        let mut invoice = Invoice::new(&invoicer, recipient(&RECIPIENT.replace("[invoice]", "[invoice]\ngroup_untagged_by = \"none\"")));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.positions().len(), 3);
+
+        // A date format with time labels each day once
+        let with_time = self::invoicer(&CONFIG.replace("[invoice]", "[invoice]\ndate_format = \"%Y/%m/%d %H:%M\""));
+        let mut invoice = Invoice::new(&with_time, recipient(&RECIPIENT.replace("[invoice]", "[invoice]\ngroup_untagged_by = \"day\"")));
+        invoice.add_worklog(&worklog(csv));
+        let positions = invoice.sorted_positions();
+        assert_eq!(positions.iter().map(|position| position.text().as_str()).collect::<Vec<_>>(), ["2023/10/04 00:00", "2023/10/05 00:00"]);
+        assert_approx_eq(positions[0].amount, 3.5);
+    }
+
+    #[test]
+    fn positions_chronological() {
+        let csv = r#"Tags,Start,Hours,Message
+,10/05/2023 09:00,1,Review
+,10/04/2023 14:00,2,Development
+,10/04/2023 09:00,1.5,Development
+"#;
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(&RECIPIENT.replace("[invoice]", "[invoice]\npositions_from = \"chronological\"")));
+        assert_eq!(invoice.positions_from(), crate::invoice::PositionsFrom::Chronological);
+        invoice.add_worklog(&worklog(csv));
+        invoice.add_worklog(&worklog(r#"Tags,Start,Hours,Message
+,10/04/2023 11:00,0.5,Call
+"#));
+
+        let positions = invoice.sorted_positions();
+        assert_eq!(positions.iter().map(|position| position.text().as_str()).collect::<Vec<_>>(),
+            ["2023/10/04: Development", "2023/10/04: Call", "2023/10/04: Development", "2023/10/05: Review"]);
+        assert_eq!(positions.iter().map(|position| position.amount()).collect::<Vec<_>>(), [1.5, 0.5, 2.0, 1.0]);
+        assert!(positions.windows(2).all(|pair| pair[0].date() <= pair[1].date()));
+    }
+
+    #[test]
+    fn nonbillable() {
+        let csv = r#"Tags,Start,Hours,Message,Billable
+,10/04/2023 09:00,2,Review,yes
+,10/04/2023 14:00,1,Internal meeting,no
+nonbillable,10/05/2023 10:00,1.5,Pro-bono,
+"#;
+        let with_timesheet = CONFIG.replace("timesheet = false", "timesheet = true\ntimesheet_template = \"timesheet.tex\"");
+        let default = invoicer(&with_timesheet);
+        let mut invoice = Invoice::new(&default, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.positions().len(), 1);
+        assert_approx_eq(invoice.total_hours(), 2.0);
+        assert_eq!(invoice.timesheet.as_ref().unwrap().len(), 1);
+
+        let shown = invoicer(&with_timesheet.replace("timesheet = true", "timesheet = true\ntimesheet_nonbillable = true"));
+        let mut invoice = Invoice::new(&shown, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.positions().len(), 1);
+        let timesheet = invoice.timesheet.as_ref().unwrap();
+        assert_eq!(timesheet.len(), 3);
+        assert_eq!(timesheet.worklog().records().iter().filter(|record| record.rate_value() == Some(0.0)).count(), 2);
+    }
+
+    #[test]
+    fn position_sort() {
+        let csv = r#""Tags","Start","Hours","Message"
+"","10/04/2023 09:00",2,"Analysis"
+"","10/04/2023 14:00",5,"Development"
+"","10/05/2023 10:00",1,"Testing"
+"","10/05/2023 11:00",3,"Deployment"
+"#;
+        let texts = |invoice: &Invoice| invoice.sorted_positions().iter().map(|position| position.text().clone()).collect::<Vec<_>>();
+        let invoicer = invoicer(CONFIG);
+
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.position_sort(), crate::invoice::PositionSort::Tag);
+        assert_eq!(texts(&invoice), ["Analysis", "Deployment", "Development", "Testing"]);
+
+        let mut invoice = Invoice::new(&invoicer, recipient(&RECIPIENT.replace("[invoice]", "[invoice]\nposition_sort = \"amount_desc\"")));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(texts(&invoice), ["Development", "Deployment", "Analysis", "Testing"]);
+
+        let mut invoice = Invoice::new(&invoicer, recipient(&RECIPIENT.replace("[invoice]", "[invoice]\nposition_sort = \"amount_asc\"")));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(texts(&invoice), ["Testing", "Analysis", "Deployment", "Development"]);
+    }
+
+    #[test]
+    fn fingerprint() {
+        use crate::helpers::Fingerprint;
+        let csv = r#""Tags","Start","Hours","Message"
+"dev","10/04/2023 09:00",2,"Review"
+"ops","10/05/2023 10:00",1,"Deployment"
+"#;
+        let recipient_toml = RECIPIENT.replace("[tags]", "[custom]\norder = \"PO-1\"\nproject = \"Apollo\"\n\n[tags]\ndev = \"Development\"\nops = \"Operations\"\nqa = \"Testing\"");
+        let invoicer = invoicer(CONFIG);
+        let create = || {
+            let mut invoice = Invoice::new(&invoicer, recipient(&recipient_toml));
+            invoice.add_worklog(&worklog(csv));
+            invoice
+        };
+
+        // Identical invoices, with recipients loaded separately, have the same fingerprint
+        let invoice = create();
+        assert_eq!(invoice.fingerprint().len(), 64);
+        assert_eq!(invoice.fingerprint(), create().fingerprint());
+
+        // Another position is another invoice
+        let mut corrected = create();
+        corrected.add_position(InvoicePosition::new("Travel", 1.0, 50.0, ""));
+        assert_ne!(invoice.fingerprint(), corrected.fingerprint());
+        let mut corrected = Invoice::new(&invoicer, recipient(&recipient_toml));
+        corrected.add_worklog(&worklog(&csv.replace(",2,", ",3,")));
+        assert_ne!(invoice.fingerprint(), corrected.fingerprint());
+
+        // Another period or recipient is another invoice
+        let mut other_period = create();
+        other_period.set_period(invoice.begin_date(), invoice.end_date() + chrono::Duration::days(1));
+        assert_ne!(invoice.fingerprint(), other_period.fingerprint());
+
+        let mut other_recipient = Invoice::new(&invoicer, recipient(&recipient_toml.replace("Jane Doe", "John Doe")));
+        other_recipient.add_worklog(&worklog(csv));
+        assert_ne!(invoice.fingerprint(), other_recipient.fingerprint());
+
+        // Payment days given on the command line change the due date and thereby the fingerprint
+        let mut later = self::invoicer(CONFIG);
+        later.set_days_for_payment(30);
+        let mut other_days = Invoice::new(&later, recipient(&recipient_toml));
+        other_days.add_worklog(&worklog(csv));
+        assert_ne!(invoice.fingerprint(), other_days.fingerprint());
+    }
+
+    #[test]
+    fn promotion() {
+        use crate::invoice::{Promotion, PromotionKind};
+        let csv = r#""Tags","Start","Hours","Message"
+"","10/04/2023 09:00",10,"Development"
+"#;
+        let mut invoicer = invoicer(&CONFIG.replace("[invoice]", r#"[[promotions]]
+name = "Spring"
+kind = "percent"
+value = 10
+expires = 2099-12-31
+
+[[promotions]]
+name = "Welcome"
+kind = "absolute"
+value = 2000
+
+[invoice]"#));
+        let date = |s: &str| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+        assert_eq!(invoicer.config().promotion("Spring"), Some(&Promotion::new("Spring", PromotionKind::Percent, 10.0, Some(date("2099-12-31")))));
+
+        invoicer.set_promotion("Spring").unwrap();
+        let mut invoice = Invoice::new(&invoicer, recipient(&RECIPIENT.replace("[invoice]", "default_rate = 100.0\n\n[invoice]")));
+        invoice.add_worklog(&worklog(csv));
+        assert_approx_eq(invoice.capped_sum(), 1000.0);
+        assert_approx_eq(invoice.discount(), 100.0);
+        assert_approx_eq(invoice.sum(), 900.0);
+
+        let mut tex = Vec::new();
+        invoice.generate_tex(&mut tex).unwrap();
+        assert!(String::from_utf8(tex).unwrap().contains("\\invoicediscount{Spring}{10\\%}"));
+
+        // An absolute discount does not exceed the sum
+        invoicer.set_promotion("Welcome").unwrap();
+        let mut invoice = Invoice::new(&invoicer, recipient(&RECIPIENT.replace("[invoice]", "default_rate = 100.0\n\n[invoice]")));
+        invoice.add_worklog(&worklog(csv));
+        assert_approx_eq(invoice.sum(), 0.0);
+
+        assert!(invoicer.set_promotion("Unknown").is_err());
+    }
+
+    #[test]
+    fn promotion_expired() {
+        let config = CONFIG.replace("[invoice]", "[[promotions]]\nname = \"Spring\"\nkind = \"percent\"\nvalue = 10\nexpires = \"2023-10-31\"\n\n[invoice]");
+        let at = |s: &str| Invoicer::new(toml::from_str::<Config>(&config).unwrap(), Some(crate::helpers::DateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()), None);
+
+        assert!(at("2023-10-31 23:00").set_promotion("Spring").is_ok());
+
+        let mut invoicer = at("2023-11-01 00:00");
+        assert!(matches!(invoicer.set_promotion("Spring"), Err(crate::error::InvoicerError::InvalidConfig(_))));
+        assert!(invoicer.promotion().is_none());
+    }
+
+    #[test]
+    fn tag_breakdown() {
+        let csv = r#""Tags","Start","Hours","Message"
+"dev","10/04/2023 09:00",2,"Review"
+"ops","10/04/2023 14:00",1.5,"Deployment"
+"dev","10/05/2023 10:00",3,"Feature"
+"","10/05/2023 15:00",0.5,"Call"
+"#;
+        let recipient_toml = RECIPIENT.replace("[invoice]", "default_rate = 100.0\n\n[invoice]")
+            .replace("[tags]", "[tags]\ndev = \"Development\"\nops = \"[order=1]\"");
+        let dir = std::env::temp_dir().join(format!("invoicer_tag_breakdown_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("breakdown.tex"), "%$TAG_BREAKDOWN\n").unwrap();
+        let mut invoicer = invoicer(CONFIG);
+        invoicer.set_template_path(vec![dir.clone()]);
+        invoicer.set_template("breakdown.tex");
+        let mut invoice = Invoice::new(&invoicer, recipient(&recipient_toml));
+        invoice.add_worklog(&worklog(csv));
+
+        let breakdown = invoice.tag_breakdown();
+        assert_eq!(breakdown.iter().map(|(tag, _, _)| tag.as_deref()).collect::<Vec<_>>(), [Some("ops"), Some("dev"), None]);
+        assert_approx_eq(breakdown[1].1, 5.0);
+        assert_approx_eq(breakdown[1].2, 500.0);
+        assert_approx_eq(breakdown[0].2, 150.0);
+
+        let mut tex = Vec::new();
+        invoice.generate_tex(&mut tex).unwrap();
+        let tex = String::from_utf8(tex).unwrap();
+        assert!(tex.contains("\\tagbreakdown{ops}{1.50}"));
+        assert!(tex.contains("\\tagbreakdown{Development}{5.00}"));
+        assert!(tex.contains("\\tagbreakdown{\\truntagged}{0.50}"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filename_sanitized() {
+        let default = invoicer(CONFIG);
+        let mut recipient = recipient(RECIPIENT);
+        recipient.set_name("Müller/Söhne");
+        let mut invoice = Invoice::new(&default, recipient.clone());
+        invoice.generate_number(1, None);
+        assert!(invoice.filename().ends_with("_Müller_Söhne.tex"));
+
+        let ascii = invoicer(&CONFIG.replace("[invoice]", "[invoice]\nascii_filenames = true"));
+        let mut invoice = Invoice::new(&ascii, recipient);
+        invoice.generate_number(1, None);
+        assert!(invoice.filename().ends_with("_Mueller_Soehne.tex"));
+        assert_eq!(invoice.qr_filename(), invoice.filename().replace(".tex", "_qr.png"));
+    }
+
+    #[test]
+    fn rate_tiers() {
+        let csv = r#""Tags","Start","Hours","Rate","Message"
+"","10/04/2023 09:00",30,,"Development"
+"","10/05/2023 09:00",20,,"Review"
+"","10/06/2023 09:00",5,150,"Workshop"
+"#;
+        let tiered = format!("default_rate = 120.0\n\n[[rate_tiers]]\nfrom_hours = 60\nrate = 80.0\n\n[[rate_tiers]]\nfrom_hours = 40\nrate = 100.0\n{RECIPIENT}");
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(&tiered));
+        let mut first = worklog(csv);
+        first.set_rate(120.0);
+        invoice.add_worklog(&first);
+
+        let positions: Vec<_> = invoice.positions().iter()
+            .map(|position| (position.text().as_str(), position.amount(), position.price_per_item()))
+            .collect();
+        assert_eq!(positions, vec![
+            ("Workshop", 5.0, 150.0),
+            ("Development", 30.0, 120.0),
+            ("Review", 10.0, 120.0),
+            ("Review", 10.0, 100.0),
+        ]);
+        assert_approx_eq(invoice.positions_sum(), 750.0 + 3600.0 + 1200.0 + 1000.0);
+
+        // The tiers continue with the hours of further worklogs
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","10/07/2023 09:00",15,"Testing"
+"#));
+        let testing: Vec<_> = invoice.positions()[4..].iter().map(|position| (position.amount(), position.price_per_item())).collect();
+        assert_eq!(testing, vec![(10.0, 100.0), (5.0, 80.0)]);
+    }
+
+    #[test]
+    fn record_units() {
+        let csv = r#""Tags","Start","Hours","Rate","Unit","Message"
+"","10/04/2023 09:00",2,,,"Development"
+"","10/05/2023 09:00",3,,"h","Development"
+"","10/06/2023 09:00",1,500,"pcs","Logo design"
+"","10/06/2023 14:00",2,100,"pcs","Development"
+"#;
+        let invoicer = invoicer(&CONFIG.replace("timesheet = false", "timesheet = true\ntimesheet_template = \"timesheet.tex\""));
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        let mut records = worklog(csv);
+        records.set_rate(100.0);
+        invoice.add_worklog(&records);
+
+        let mut positions: Vec<_> = invoice.positions().iter()
+            .map(|position| (position.text().as_str(), position.amount(), position.unit().as_str(), position.price_per_item()))
+            .collect();
+        positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(positions, vec![
+            ("Development", 2.0, "pcs", 100.0),
+            ("Development", 5.0, "h", 100.0),
+            ("Logo design", 1.0, "pcs", 500.0),
+        ]);
+        assert_approx_eq(invoice.total_hours(), 5.0);
+        assert_approx_eq(invoice.positions_sum(), 200.0 + 500.0 + 500.0);
+
+        // Pieces have no duration and are shown with their unit in the timesheet, the total counts hours only
+        assert_eq!(invoice.end_date().format("%Y-%m-%d %H:%M").to_string(), "2023-10-06 14:00");
+        let mut rows = Vec::new();
+        invoice.timesheet.as_ref().unwrap().generate_rows(&mut rows).unwrap();
+        let rows = String::from_utf8(rows).unwrap();
+        let pieces = invoice.locale().unit("pcs").to_string();
+        assert!(rows.contains(&format!("10/06/2023 09:00 & 1.00{pieces} & Logo design")), "{rows}");
+    }
+
+    #[test]
+    fn position_hour_rounding() {
+        let csv = r#""Tags","Start","Hours","Rate","Unit","Message"
+"","10/04/2023 09:00",1.6,,,"Development"
+"","10/05/2023 09:00",1.5,,,"Development"
+"","10/05/2023 14:00",2,,,"Review"
+"","10/06/2023 09:00",0.3,500,"pcs","Logo design"
+"#;
+        let rounded = RECIPIENT.replace("[invoice]", "[invoice]\nposition_hour_rounding = 0.25");
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(&rounded));
+        let mut records = worklog(csv);
+        records.set_rate(100.0);
+        invoice.add_worklog(&records);
+
+        // The merged 3.1 hours are rounded up, not the single records
+        let mut positions: Vec<_> = invoice.positions().iter()
+            .map(|position| (position.text().as_str(), position.amount()))
+            .collect();
+        positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(positions, vec![("Development", 3.25), ("Logo design", 0.3), ("Review", 2.0)]);
+        assert_approx_eq(invoice.total_hours(), 5.25);
+
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&records);
+        assert_approx_eq(invoice.total_hours(), 5.1);
+    }
+
+    #[test]
+    fn percentage_fee() {
+        let csv = r#""Tags","Start","Hours","Rate","Message"
+"","10/04/2023 09:00",10,,"Development"
+"","10/05/2023 09:00",5,,"Review"
+"","10/06/2023 09:00",2,50,"Documentation"
+"#;
+        let with_fee = format!("default_rate = 100.0\n\n[[fees]]\ntext = \"Management fee\"\npercent = 10.0\n{RECIPIENT}")
+            .replace("[invoice]", "[invoice]\nposition_sort = \"amount_asc\"");
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(&with_fee));
+        let mut items = worklog(csv);
+        items.set_rate(100.0);
+        invoice.add_worklog(&items);
+
+        let positions = invoice.sorted_positions();
+        assert_eq!(positions.iter().map(|position| position.text().as_str()).collect::<Vec<_>>(),
+            ["Documentation", "Review", "Development", "Management fee"]);
+        assert!(positions[3].is_percentage());
+        assert_approx_eq(positions[3].price_per_item(), 1600.0);
+        assert_approx_eq(positions[3].net(), 160.0);
+        assert_approx_eq(invoice.positions_sum(), 1760.0);
+        assert_approx_eq(invoice.total_hours(), 17.0);
+
+        // A later position is part of the base, the fee stays last
+        invoice.add_position(InvoicePosition::new("Travel", 1.0, 400.0, ""));
+        assert_eq!(invoice.sorted_positions().last().unwrap().text(), "Management fee");
+        assert_approx_eq(invoice.positions_sum(), 2200.0);
+
+        let mut tex = Vec::new();
+        invoice.generate_tex(&mut tex).unwrap();
+        let tex = String::from_utf8(tex).unwrap();
+        assert!(tex.contains("\\position{Management fee (10.00\\%)}{}{}{200.00€}"), "{tex}");
+    }
+
+    #[test]
+    fn tax_exempt() {
+        let csv = r#""Tags","Start","Hours","Message"
+"","10/04/2023 09:00",10,"Development"
+"#;
+        let exempt = format!("default_rate = 100.0\ntax_exempt = \"Tax-exempt charity according to section 4 No. 18 UStG\"\n{RECIPIENT}");
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(&exempt));
+        invoice.add_worklog(&worklog(csv));
+
+        assert!(!invoice.calculate_value_added_tax());
+        assert!(invoice.taxes().is_empty());
+        assert_eq!(invoice.tax(), 0.0);
+        assert_approx_eq(invoice.total(), 1000.0);
+        assert_approx_eq(invoice.sum_with_tax(), 1000.0);
+
+        let mut tex = Vec::new();
+        invoice.generate_tex(&mut tex).unwrap();
+        let tex = String::from_utf8(tex).unwrap();
+        assert!(tex.contains("\\newcommand{\\invoicetaxexemptreason}{Tax-exempt charity according to section 4 No. 18 UStG}"));
+        assert!(tex.contains("\\invoicetaxexemptreason\\\\"));
+        assert!(!tex.lines().any(|line| line.trim() == "\\trinvoicevaluetaxnote"));
+        assert!(!tex.contains("\\invoicetax{\\trvat}{19}"));
+    }
+
+    #[test]
+    fn template_flags() {
+        let invoicer = invoicer(CONFIG);
+        let flag = |invoice: &Invoice, name: &str| invoice.template_flags().iter().find(|(flag, _)| *flag == name).unwrap().1;
+
+        let invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        assert!(flag(&invoice, "calculate_value_added_tax"));
+        assert!(!flag(&invoice, "tax_exempt"));
+        assert!(!flag(&invoice, "has_discount"));
+        assert!(!flag(&invoice, "has_deposit"));
+
+        let invoice = Invoice::new(&invoicer, recipient(&format!("deposit = 500.0\ntax_exempt = \"Reverse charge\"\n{RECIPIENT}")));
+        assert!(!flag(&invoice, "calculate_value_added_tax"));
+        assert!(flag(&invoice, "tax_exempt"));
+        assert!(flag(&invoice, "has_deposit"));
+    }
+
+    #[test]
+    fn po_number() {
+        let base = invoicer(CONFIG);
+        let invoice = Invoice::new(&base, recipient(RECIPIENT));
+        assert_eq!(InvoiceDetails::from_invoice(&invoice).ponumber, None);
+        assert_eq!(invoice.epc_payment().reference, invoice.number());
+
+        let invoice = Invoice::new(&base, recipient(&format!("po_number = \"4500012345\"\n{RECIPIENT}")));
+        let details = InvoiceDetails::from_invoice(&invoice);
+        assert_eq!(details.ponumber.as_deref(), Some("4500012345"));
+        let mut output = Vec::new();
+        details.generate_tex_commands(&mut output, "invoice").unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("\\newcommand{\\invoiceponumber}{4500012345}\n"));
+        assert_eq!(invoice.epc_payment().reference, format!("{} 4500012345", invoice.number()));
+
+        // The number given on the command line takes precedence
+        let mut cli = invoicer(CONFIG);
+        cli.set_po_number("PO-77");
+        let invoice = Invoice::new(&cli, recipient(&format!("po_number = \"4500012345\"\n{RECIPIENT}")));
+        assert_eq!(invoice.po_number().as_deref(), Some("PO-77"));
+    }
+
+    #[test]
+    fn payment_accounts() {
+        let config = CONFIG.replace("[payment]", "[[payment]]") + r#"
+[[payment]]
+iban = "US1234567890"
+bic = "USBANKID"
+taxid = "12345678"
+currency = "USD"
+currency_symbol = "US$"
+tax_rate = 0.0
+"#;
+        let invoicer = invoicer(&config);
+        assert_eq!(invoicer.config().payments().len(), 2);
+
+        let eur = Invoice::new(&invoicer, recipient(RECIPIENT));
+        assert_eq!(eur.currency(), "EUR".to_string().into());
+        assert_eq!(eur.payment().iban(), "DE123456789012345678");
+
+        let usd = Invoice::new(&invoicer, recipient(&format!("currency = \"USD\"\n{RECIPIENT}")));
+        assert_eq!(usd.currency(), "USD".to_string().into());
+        assert_eq!(usd.payment().iban(), "US1234567890");
+        assert_eq!(usd.tax_rate(), 0.0);
+        assert_eq!(usd.currency_symbol(), "US$");
+        assert_eq!(eur.currency_symbol(), "€");
+
+        let mut details = Vec::new();
+        usd.payment().generate_tex_commands(&mut details, "my").unwrap();
+        assert!(String::from_utf8(details).unwrap().contains("\\newcommand{\\myiban}{US1234567890}"));
+
+        // Without an account in the currency, the first account is used
+        let chf = Invoice::new(&invoicer, recipient(&format!("currency = \"CHF\"\n{RECIPIENT}")));
+        assert_eq!(chf.payment().iban(), "DE123456789012345678");
+    }
+
+    #[test]
+    fn recipient_template() {
+        let mut recipient: Recipient = toml::from_str(&Recipient::template("ClientA")).unwrap();
+        recipient.set_name("ClientA");
+        assert_eq!(recipient.contact().fullname(), "Jane Doe");
+        assert_eq!(recipient.default_tag_name().unwrap(), "ClientA");
+        assert!(recipient.default_rate().is_none());
+        assert!(recipient.custom_fields().is_empty());
+    }
+
+    #[test]
+    fn payment_terms() {
+        let invoicer = Invoicer::new(toml::from_str::<Config>(CONFIG).unwrap(), chrono::NaiveDate::from_ymd_opt(2024, 3, 25).unwrap().and_hms_opt(0, 0, 0), None);
+        let invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        assert_eq!(invoice.payment_terms(), "Payable within 14 days");
+
+        let one_day = Invoicer::new(toml::from_str::<Config>(&CONFIG.replace("[invoice]", "[invoice]\ndays_for_payment = 1")).unwrap(), None, None);
+        assert_eq!(Invoice::new(&one_day, recipient(RECIPIENT)).payment_terms(), "Payable within 1 day");
+
+        let recipient = recipient(&format!("payment_terms = \"Net ${{DAYS}}, due on ${{DUEDATE}}\"\n{RECIPIENT}"));
+        let invoice = Invoice::new(&invoicer, recipient);
+        assert_eq!(invoice.payment_terms(), "Net 14, due on 2024/04/08");
+
+        let details = InvoiceDetails::from_invoice(&invoice);
+        assert_eq!(details.duedate, "2024/04/08");
+        assert_eq!(details.paymentterms, invoice.payment_terms());
+    }
+
+    #[test]
+    fn days_for_payment() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 25).unwrap().and_hms_opt(0, 0, 0);
+        let mut extended = Invoicer::new(toml::from_str::<Config>(&CONFIG.replace("[invoice]", "[invoice]\ndays_for_payment = 10")).unwrap(), date, None);
+        let with_days = recipient(&RECIPIENT.replace("[invoice]", "[invoice]\ndays_for_payment = 21"));
+        assert_eq!(Invoice::new(&extended, recipient(RECIPIENT)).days_for_payment(), 10);
+        assert_eq!(Invoice::new(&extended, with_days.clone()).days_for_payment(), 21);
+
+        // The command line takes precedence over the recipient and the config
+        extended.set_days_for_payment(30);
+        for recipient in [recipient(RECIPIENT), with_days] {
+            let invoice = Invoice::new(&extended, recipient);
+            let details = InvoiceDetails::from_invoice(&invoice);
+            assert_eq!(details.daysforpayment, 30);
+            assert_eq!(details.duedate, "2024/04/24");
+            assert_eq!(details.paymentterms, "Payable within 30 days");
+        }
+    }
+
+    #[test]
+    fn period() {
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","03/01/2024 09:00",1,"Review"
+"","03/31/2024 14:00",2,"Setup"
+"#));
+        assert_eq!(invoice.period(), "March 2024");
+        assert_eq!(InvoiceDetails::from_invoice(&invoice).period, "March 2024");
+
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","03/04/2024 09:00",1,"Review"
+"","03/04/2024 14:00",2,"Setup"
+"#));
+        assert_eq!(invoice.period(), "2024/03/04");
+
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","03/04/2024 09:00",1,"Review"
+"","03/28/2024 14:00",2,"Setup"
+"#));
+        assert_eq!(invoice.period(), "2024/03/04 -- 2024/03/28");
+    }
+
+    #[test]
+    fn round_positions() {
+        let positions = [("Review", 1.004), ("Setup", 1.004), ("Meeting", 1.004)];
+
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        positions.iter().for_each(|(text, amount)| invoice.add_position(InvoicePosition::new(text, *amount, 1.0, "h")));
+        assert!(invoice.payment().round_positions());
+        // Each position is printed as 1.00, so the sum is 3.00 instead of 3.01
+        assert_approx_eq(invoice.sum(), 3.0);
+
+        let invoicer = self::invoicer(&CONFIG.replace("[payment]", "[payment]\nround_positions = false"));
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        positions.iter().for_each(|(text, amount)| invoice.add_position(InvoicePosition::new(text, *amount, 1.0, "h")));
+        assert_approx_eq(invoice.sum(), 3.01);
+    }
+
+    #[test]
+    fn cap() {
+        let invoicer = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&invoicer, recipient(&format!("cap = 1000.0\n{RECIPIENT}")));
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"","10/04/2023 14:00",12,"Development"
+"#));
+
+        assert!(invoice.is_capped());
+        assert_approx_eq(invoice.positions_sum(), 1200.0);
+        assert_approx_eq(invoice.sum(), 1000.0);
+        assert_approx_eq(invoice.tax(), 190.0);
+        assert_approx_eq(invoice.total(), 1190.0);
+
+        let mut output = Vec::new();
+        invoice.generate_tex(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\invoicecap{1,200.00€}{1,000.00€}\n\\invoicesubtotal{1,000.00€}"), "{output}");
+    }
+
+    #[test]
+    fn custom_fields() {
+        let recipient = recipient(&format!(r#"{RECIPIENT}
+[custom]
+ponumber = "PO-4711"
+project_code = 42
+fullname = "Ignored"
+"#));
+        let mut output = Vec::new();
+        recipient.generate_tex_commands(&mut output, "recipient").unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\newcommand{\\recipientponumber}{PO-4711}\n"), "{output}");
+        assert!(output.contains("\\newcommand{\\recipientprojectcode}{42}\n"));
+        assert!(!output.contains("Ignored"));
+    }
+
+    #[test]
+    fn total_hours() {
+        let invoicer = invoicer(CONFIG);
+        let recipient = recipient(&format!(r#"{RECIPIENT}
+dev = "Software Development"
+"#));
+        let mut invoice = Invoice::new(&invoicer, recipient);
+        invoice.add_worklog(&worklog(r#""Tags","Start","Hours","Message"
+"dev","10/04/2023 14:00",2.5,"Review"
+"","10/05/2023 14:00",1.25,"Setup"
+"#));
+        invoice.positions.push(InvoicePosition::new("Travel", 120.0, 0.3, "km"));
+
+        assert_eq!(invoice.positions().len(), 3);
+        assert_approx_eq(invoice.total_hours(), 3.75);
+    }
+
+    #[test]
+    fn case_insensitive_tags() {
+        let csv = r#""Tags","Start","Hours","Message"
+"DEV","10/04/2023 14:00",1,"Review"
+"#;
+        let recipient = recipient(&format!(r#"{RECIPIENT}
+dev = "Software Development"
+"#));
+
+        let case_sensitive = invoicer(CONFIG);
+        let mut invoice = Invoice::new(&case_sensitive, recipient.clone());
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.positions()[0].text(), "Review");
+
+        let case_insensitive = invoicer(&format!("{CONFIG}\n[worklog]\ncase_insensitive_tags = true\n"));
+        let mut invoice = Invoice::new(&case_insensitive, recipient);
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.positions()[0].text(), "Software Development");
+        assert_eq!(invoice.positions()[0].tag().unwrap(), "dev");
+    }
+
+    #[test]
+    fn template_precedence() {
+        let csv = r#""Tags","Start","Hours","Message"
+"dev","10/04/2023 14:00",1,"Review"
+"#;
+        let mut invoicer = invoicer(CONFIG);
+        let with_template = recipient(&RECIPIENT.replace("[invoice]", "[invoice]\ntemplate = \"recipient.tex\""));
+
+        let mut invoice = Invoice::new(&invoicer, recipient(RECIPIENT));
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.template(), "invoice.tex");
+
+        let mut invoice = Invoice::new(&invoicer, with_template.clone());
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.template(), "recipient.tex");
+
+        invoicer.set_template("redesign.tex");
+        let mut invoice = Invoice::new(&invoicer, with_template);
+        invoice.add_worklog(&worklog(csv));
+        assert_eq!(invoice.template(), "redesign.tex");
+    }
+
+    #[test]
+    fn timesheet_columns() {
+        let mut timesheet = Timesheet::new(std::path::Path::new("templates/timesheet.tex"), Locale::default(), 
+            vec![TimesheetColumn::Start, TimesheetColumn::End, TimesheetColumn::Hours, TimesheetColumn::Total, TimesheetColumn::Tags]);
+        for record in worklog(r#""Tags","Start","Hours","Message"
+"b,a","10/04/2023 14:00",1.5,"Review"
+"a","10/05/2023 09:00",2,"Setup"
+"#).records() {
+            timesheet.add_record(record.clone());
+        }
+
+        let mut out = Vec::new();
+        timesheet.generate_header(&mut out).unwrap();
+        timesheet.generate_rows(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), r#"\begin{longtable}{p{4cm}p{4cm}p{2cm}p{2cm}p{3cm}}
+\textbf{Date} & \textbf{End} & \textbf{Hours} & \textbf{Total hours} & \textbf{Tags} \\
+\hline
+10/04/2023 14:00 & 10/04/2023 15:30 & 1.50 & 1.50 & a, b\\
+10/05/2023 09:00 & 10/05/2023 11:00 & 2.00 & 3.50 & a\\
+"#);
+    }
+
+    #[test]
+    fn timesheet_flags() {
+        let dir = std::env::temp_dir().join(format!("invoicer_timesheet_flags_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("timesheet.tex"), "%$IF:has_discount\ndiscount\n%$ENDIF\n%$WORKLOG\n").unwrap();
+        let timesheet = Timesheet::new(dir.join("timesheet.tex").as_path(), Locale::default(), vec![TimesheetColumn::Hours]);
+
+        let generate = |flags: &[(&'static str, bool)]| {
+            let mut out = Vec::new();
+            timesheet.generate_tex_with_flags(&mut out, flags).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(generate(&[("has_discount", true)]).starts_with("discount\n"));
+        assert!(!generate(&[("has_discount", false)]).contains("discount"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
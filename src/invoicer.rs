@@ -1,10 +1,10 @@
-use std::{path::{PathBuf, Path}, fmt::Display, collections::HashMap, io::Read};
+use std::{path::{PathBuf, Path}, fmt::Display, collections::{HashMap, HashSet, BTreeMap, BTreeSet}, io::Read};
 
 use chrono::Datelike;
 use serde::{Deserialize, Serialize};
 use toml::map::Map;
 
-use crate::{worklog::Worklog, invoice::*, helpers::*, generate_tex::GenerateTex};
+use crate::{error::InvoicerError, worklog::{Worklog, WorklogConfig, tags_match}, invoice::*, helpers::*, generate_tex::{GenerateTex, embedded_template, find_in_search_path, missing_inputs, template_text}, locale::{Currency, Locale}};
 
 pub trait HasDirectories {
     fn config_dir(&self) -> PathBuf;
@@ -14,7 +14,7 @@ pub trait HasDirectories {
     fn locale_dir(&self) -> PathBuf;
 
     fn working_dir(&self) -> PathBuf {
-        std::env::current_dir().unwrap()
+        working_dir().into()
     }
 
     fn format_path(&self, s: &String) -> String { s.clone() }
@@ -29,16 +29,48 @@ pub trait HasDirectories {
 }
 
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 struct Directories {
     config: Option<String>,
     tags: Option<String>,
     templates: Option<String>,
+    #[serde(default)]
+    template_path: Vec<String>,
     invoices: Option<String>,
     locales: Option<String>,
 }
 
 
+impl Directories {
+    /// Directories searched for templates, the configured `template_path` followed by the template directory.
+    fn template_search_path(&self) -> Vec<PathBuf> {
+        let mut search_path: Vec<PathBuf> = self.template_path.iter().map(|dir| self.format_path(dir).into()).collect();
+        search_path.push(self.template_dir());
+        search_path
+    }
+
+    /// Copy with all directories as absolute paths, only placeholders like `${YEAR}` are kept.
+    fn resolved(&self) -> Self {
+        let absolute = |path: PathBuf| Some(std::path::absolute(&path).unwrap_or(path).to_string_lossy().to_string());
+        Self {
+            config: absolute(self.config_dir()),
+            tags: absolute(self.tag_dir()),
+            templates: absolute(self.template_dir()),
+            template_path: self.template_search_path().into_iter()
+                .take(self.template_path.len())
+                .filter_map(absolute)
+                .collect(),
+            invoices: absolute(self.invoice_dir()),
+            locales: absolute(self.locale_dir()),
+        }
+    }
+
+    /// Configured invoice directory before substituting variables.
+    fn invoices_setting(&self) -> String {
+        self.invoices.clone().unwrap_or(String::from("${HOME}/Documents/invoices/${YEAR}"))
+    }
+}
+
 impl HasDirectories for Directories {
     fn config_dir(&self) -> PathBuf {
         self.config.as_ref().unwrap_or(&String::from("${HOME}/.invoicer"))
@@ -55,7 +87,7 @@ impl HasDirectories for Directories {
     }
 
     fn invoice_dir(&self) -> PathBuf {
-        self.format_path(&self.invoices.as_ref().unwrap_or(&String::from("${HOME}/Documents/invoices/${YEAR}"))).into()
+        self.format_path(&self.invoices_setting()).into()
     }
 
     fn locale_dir(&self) -> PathBuf {
@@ -64,17 +96,22 @@ impl HasDirectories for Directories {
 
     fn format_path(&self, s: &String) -> String {
         s.replace("${HOME}", &home_dir())
-            .replace("${WORKING_DIR}", &std::env::current_dir().unwrap().into_os_string().into_string().unwrap())
-            .replace("${CONFIG_DIR}", &self.config_dir().into_os_string().into_string().unwrap())
+            .replace("${WORKING_DIR}", &working_dir())
+            .replace("${CONFIG_DIR}", &self.config_dir().to_string_lossy())
     }
 }
 
 
-#[derive(Debug, Deserialize, Clone)]
-enum OverwriteBehaviour {
+/// What happens if the tex file of an invoice already exists.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum OverwriteBehaviour {
+    /// Overwrite the existing file
     Force,
+    /// Rename the existing tex and PDF file with a `_rev<N>` suffix
     RenameOld,
+    /// Write the new invoice with a `_rev<N>` suffix
     RenameNew,
+    /// Keep the existing file and skip the invoice
     Skip,
 }
 
@@ -85,19 +122,57 @@ impl Default for OverwriteBehaviour {
 }
 
 
-#[derive(Debug, Deserialize, Clone)]
+/// Recipient defined inline in the config as `[[recipient]]`, named by its `name` key instead of a file name.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct InlineRecipient {
+    name: String,
+    #[serde(flatten)]
+    recipient: Recipient,
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pdf_generator: Option<String>,
+    qr_generator: Option<String>,
+    pdf_combiner: Option<String>,
+    logo: Option<String>,
     #[serde(default)]
     overwrite: OverwriteBehaviour,
     #[serde(default)]
     directories: Directories,
     contact: Contact,
-    payment: Payment,
+    #[serde(deserialize_with = "deserialize_payments")]
+    payment: Vec<Payment>,
     invoice: InvoiceConfig,
+    #[serde(default)]
+    worklog: WorklogConfig,
+    #[serde(default)]
+    exchange_rates: HashMap<String, f32>,
+    #[serde(default)]
+    legal: HashMap<String, String>,
+    #[serde(default, rename = "recipient")]
+    recipients: Vec<InlineRecipient>,
+    #[serde(default)]
+    promotions: Vec<Promotion>,
+}
+
+/// Read a single `[payment]` table or several `[[payment]]` accounts.
+fn deserialize_payments<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<Payment>, D::Error> {
+    use serde::de::Error;
+    let payments = match toml::Value::deserialize(deserializer)? {
+        toml::Value::Array(values) => values.into_iter()
+            .map(|value| value.try_into().map_err(D::Error::custom))
+            .collect::<Result<Vec<Payment>, _>>()?,
+        value => vec![value.try_into().map_err(D::Error::custom)?],
+    };
+    if payments.is_empty() {
+        return Err(D::Error::custom("at least one payment account is required"));
+    }
+    Ok(payments)
 }
 
-pub fn toml_file_to_map<P: FilePath>(p: P)  -> Result<Map<String, toml::Value>, Box<dyn std::error::Error>> {
+pub fn toml_file_to_map<P: FilePath>(p: P)  -> Result<Map<String, toml::Value>, InvoicerError> {
     let path_str = p.to_string();
     let mut file = std::fs::File::open(p)?;
     let mut s = String::new();
@@ -107,64 +182,290 @@ pub fn toml_file_to_map<P: FilePath>(p: P)  -> Result<Map<String, toml::Value>,
         Ok(result) => Ok(result),
         Err(err) => {
             eprintln!("Error reading {}: {err}", path_str);
-            Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{err}"))))
+            Err(err.into())
         }
     }
 }
 
 
 impl Config {
-    pub fn from_toml_file<P: FilePath>(filename: P) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn from_toml_file<P: FilePath>(filename: P) -> Result<Self, InvoicerError> {
         crate::helpers::from_toml_file::<Self, P>(filename)
     }
 
-    pub fn from_toml_files(filename: Option<impl FilePath>) -> Result<Self, Box<dyn std::error::Error>> {
-        
-        let mut toml = toml::Table::new();
+    /// Load `~/invoicer.toml`, `./invoicer.toml` and the given file, in this order of increasing precedence.
+    /// Missing files in the home and working directory are skipped, a missing given file is an error.
+    pub fn from_toml_files(filename: Option<impl FilePath>) -> Result<Self, InvoicerError> {
+        let mut files = Vec::new();
+        match home::home_dir() {
+            Some(home_dir) => files.push(home_dir.join("invoicer.toml")),
+            None => eprintln!("Warning: No home directory found, ~/invoicer.toml is not loaded"),
+        }
+        files.push(PathBuf::from(working_dir()).join("invoicer.toml"));
+
+        if let Some(filename) = filename {
+            let filename = PathBuf::from(&filename);
+            if !filename.exists() {
+                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("Config file {filename:?} not found")).into());
+            }
+            files.push(filename);
+        }
+
+        Self::from_layered_toml_files(&files)
+    }
 
-        fn merge_map(p: PathBuf, toml: &mut Map<String, toml::Value>) {
-            if p.exists() {
-                let map = toml_file_to_map(p).unwrap();
-                for (key, value) in map {
-                    toml.insert(key, value);
+    /// Merge the TOML files, later files take precedence. Missing files are skipped.
+    /// Tables like `[invoice]` are merged key by key, so a file only needs to contain the settings it overrides.
+    /// Other values, including arrays like `[[payment]]`, are replaced as a whole.
+    pub fn from_layered_toml_files(files: &[PathBuf]) -> Result<Self, InvoicerError> {
+        fn merge_map(toml: &mut Map<String, toml::Value>, map: Map<String, toml::Value>) {
+            for (key, value) in map {
+                match (toml.get_mut(&key), value) {
+                    (Some(toml::Value::Table(table)), toml::Value::Table(value)) => merge_map(table, value),
+                    (_, value) => { toml.insert(key, value); }
                 }
             }
         }
 
-        merge_map(home::home_dir().unwrap().join("invoicer.toml"), &mut toml);
-        merge_map(std::env::current_dir().unwrap().join("invoicer.toml"), &mut toml);
-        if let Some(filename) = filename {
-            merge_map(PathBuf::from(&filename), &mut toml);
+        let mut toml = toml::Table::new();
+        let mut loaded = HashSet::new();
+        for file in files.iter().filter(|file| file.exists()) {
+            // The same file may be given twice, e.g. ./invoicer.toml as --config
+            if loaded.insert(std::fs::canonicalize(file)?) {
+                merge_map(&mut toml, toml_file_to_map(file.clone())?);
+            }
         }
 
-        Ok(Self::deserialize(toml).unwrap())
+        Ok(Self::deserialize(toml)?)
+    }
+
+    /// Command to render payment QR codes as PNG, `qrencode` by default.
+    pub fn qr_generator(&self) -> String {
+        self.qr_generator.clone().unwrap_or("qrencode".to_string())
+    }
+
+    /// Command to combine PDFs, called with the input files followed by the output file, `pdfunite` by default.
+    pub fn pdf_combiner(&self) -> String {
+        self.pdf_combiner.clone().unwrap_or("pdfunite".to_string())
+    }
+
+    /// Logo or letterhead image for the templates, with `${HOME}`, `${WORKING_DIR}` and `${CONFIG_DIR}` substituted.
+    pub fn logo(&self) -> Option<PathBuf> {
+        self.logo.as_ref().map(|logo| self.directories.format_path(logo).into())
+    }
+
+    /// Directories searched for templates and their `\\input` files, see `Invoicer::template_search_path`.
+    pub fn template_search_path(&self) -> Vec<PathBuf> {
+        self.directories.template_search_path()
+    }
+
+    /// Legal information of the biller like company register and managing director, `[legal]` section.
+    pub fn legal(&self) -> &HashMap<String, String> {
+        &self.legal
+    }
+
+    pub fn overwrite(&self) -> OverwriteBehaviour {
+        self.overwrite
+    }
+
+    pub fn set_overwrite(&mut self, overwrite: OverwriteBehaviour) {
+        self.overwrite = overwrite;
+    }
+
+    /// Promotions of the `[[promotions]]` list.
+    pub fn promotions(&self) -> &Vec<Promotion> {
+        &self.promotions
+    }
+
+    /// Promotion with the given name.
+    pub fn promotion(&self, name: &str) -> Option<&Promotion> {
+        self.promotions.iter().find(|promotion| promotion.name() == name)
+    }
+
+    /// Recipients defined inline with `[[recipient]]` tables.
+    pub fn recipients(&self) -> Vec<Recipient> {
+        self.recipients.iter().map(|inline| {
+            let mut recipient = inline.recipient.clone();
+            recipient.set_name(&inline.name);
+            recipient
+        }).collect()
     }
 
     pub fn contact(&self) -> &Contact {
         &self.contact
     }
 
+    /// The first payment account, which also defines the default currency.
     pub fn payment(&self) -> &Payment {
+        &self.payment[0]
+    }
+
+    /// All payment accounts in the order of the config.
+    pub fn payments(&self) -> &Vec<Payment> {
         &self.payment
     }
 
+    /// Payment account in the given currency, the first account if there is none.
+    pub fn payment_for_currency(&self, currency: &Currency) -> &Payment {
+        self.payment.iter()
+            .find(|payment| payment.currency() == *currency)
+            .unwrap_or(self.payment())
+    }
+
     pub fn invoice(&self) -> &InvoiceConfig {
         &self.invoice
     }
 
+    pub fn worklog(&self) -> &WorklogConfig {
+        &self.worklog
+    }
+
+    /// Copy of the config as it is in effect, with absolute directories and all defaults filled in.
+    pub fn effective(&self) -> Self {
+        Self {
+            qr_generator: Some(self.qr_generator()),
+            pdf_combiner: Some(self.pdf_combiner()),
+            logo: self.logo().map(|logo| logo.to_string_lossy().to_string()),
+            directories: self.directories.resolved(),
+            payment: self.payment.iter().map(Payment::with_defaults).collect(),
+            invoice: self.invoice.with_defaults(),
+            worklog: self.worklog.with_defaults(),
+            ..self.clone()
+        }
+    }
+
+    /// The effective config as TOML, see `effective`.
+    pub fn effective_toml(&self) -> Result<String, InvoicerError> {
+        Ok(toml::to_string(&self.effective())?)
+    }
+
+    /// Override the configured invoice directory, e.g. with `--output-dir`.
+    /// Placeholders like `${YEAR}` are still replaced.
     pub fn set_invoice_dir(&mut self, p: impl FilePath) {
         self.directories.invoices = Some(p.to_string());
     }
+
+    pub fn set_tag_dir(&mut self, p: impl FilePath) {
+        self.directories.tags = Some(p.to_string());
+    }
+
+    pub fn set_csv_delimiter(&mut self, delimiter: char) {
+        self.worklog.set_delimiter(delimiter);
+    }
+
+    /// Exchange rate to convert an amount in `from` into the report currency `to`.
+    /// The rates in `[exchange_rates]` are the value of one unit of a currency in the report currency.
+    pub fn exchange_rate(&self, from: &Currency, to: &Currency) -> Option<f32> {
+        if from == to {
+            Some(1.0)
+        } else {
+            self.exchange_rates.get(from.str()).copied()
+        }
+    }
+
+    /// Check the business rules of the configuration.
+    /// Returns all issues found, an empty list means the configuration is valid.
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+
+        for payment in &self.payment {
+            let taxes = payment.taxes();
+            for tax in &taxes {
+                match tax.name() {
+                    None if !(0.0..=100.0).contains(&tax.rate()) =>
+                        issues.push(ConfigIssue::new("payment.tax_rate", format!("{} is not within 0 and 100", tax.rate()))),
+                    Some(name) if !(-100.0..=100.0).contains(&tax.rate()) =>
+                        issues.push(ConfigIssue::new("payment.taxes", format!("rate {} of {name} is not within -100 and 100", tax.rate()))),
+                    _ => {}
+                }
+            }
+            if taxes.is_empty() && self.invoice.calculate_value_added_tax() {
+                issues.push(ConfigIssue::new("payment.tax_rate", "either tax_rate or taxes must be given"));
+            }
+
+            for (field, value) in [("payment.iban", payment.iban()), ("payment.bic", payment.bic()), ("payment.taxid", payment.taxid())] {
+                if value.trim().is_empty() {
+                    issues.push(ConfigIssue::new(field, "must not be empty"));
+                }
+            }
+        }
+
+        let locale_file = self.directories.locale_dir().join(format!("{}.toml", self.invoice.locale_str()));
+        if !locale_file.exists() {
+            issues.push(ConfigIssue::new("invoice.locale", format!("locale file {:?} does not exist", locale_file)));
+        }
+
+        if self.invoice.number_scope() == NumberScope::PerRecipient && !self.invoice.number_format().contains("${RECIPIENT}") {
+            issues.push(ConfigIssue::new("invoice.number_format", "must contain ${RECIPIENT} for number_scope per_recipient"));
+        }
+
+        for (index, promotion) in self.promotions.iter().enumerate() {
+            let valid = match promotion.kind() {
+                PromotionKind::Percent => (0.0..=100.0).contains(&promotion.value()),
+                PromotionKind::Absolute => promotion.value() >= 0.0,
+            };
+            if !valid {
+                issues.push(ConfigIssue::new("promotions.value", format!("{} of {} is not a valid {:?} discount", promotion.value(), promotion.name(), promotion.kind())));
+            }
+            if self.promotions[..index].iter().any(|other| other.name() == promotion.name()) {
+                issues.push(ConfigIssue::new("promotions.name", format!("{} is defined several times", promotion.name())));
+            }
+        }
+
+        let search_path = self.directories.template_search_path();
+        if find_in_search_path(&search_path, &self.invoice.template()).is_none() && embedded_template(&self.invoice.template()).is_none() {
+            issues.push(ConfigIssue::new("invoice.template", format!("template file {} does not exist in {:?}", self.invoice.template(), search_path)));
+        }
+
+        issues
+    }
+}
+
+
+/// A problem found while validating the configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
 }
 
 
 
-pub struct InvoiceFingerprints(bimap::BiMap<String, String>);
+/// Invoice numbers by fingerprint and the last used counter of each number scope, stored in `fingerprints.toml`.
+pub struct InvoiceFingerprints(bimap::BiMap<String, String>, BTreeMap<String, u32>);
 
 
 impl InvoiceFingerprints {
     pub fn add(&mut self, invoice: &Invoice) {
         self.0.insert(invoice.fingerprint(), invoice.number());
+        if let Some(counter) = invoice.counter() {
+            let last = self.1.entry(invoice.number_scope_key()).or_insert(counter);
+            *last = counter.max(*last);
+        }
+    }
+
+    /// Last used counter in a number scope, see `Invoice::number_scope_key`.
+    pub fn counter(&self, scope_key: &str) -> Option<u32> {
+        self.1.get(scope_key).copied()
+    }
+
+    pub fn set_counter(&mut self, scope_key: &str, counter: u32) {
+        self.1.insert(scope_key.to_string(), counter);
     }
 
     pub fn contains_fingerprint(&self, f: String) -> bool {
@@ -176,13 +477,87 @@ impl InvoiceFingerprints {
     }
 
     pub fn number_for_fingerprint(&self, f: String) -> String {
-        self.0.get_by_right(&f).unwrap().clone()
+        self.0.get_by_left(&f).unwrap().clone()
+    }
+
+    pub fn insert(&mut self, fingerprint: String, number: String) {
+        self.0.insert(fingerprint, number);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Rebuild fingerprints from the tex files generated by invoicer in a directory.
+    /// Fingerprint and number are read from the `INVOICE_FINGERPRINT` and `INVOICE_DETAILS` sections.
+    /// Returns the fingerprints and a list of problems, e.g. duplicate invoice numbers.
+    pub fn from_tex_files(dir: &Path) -> Result<(Self, Vec<String>), InvoicerError> {
+        let mut tex_files = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "tex"))
+            .collect::<Vec<_>>();
+        tex_files.sort();
+
+        let mut fingerprints = Self::default();
+        let mut files_for_number: HashMap<String, PathBuf> = HashMap::new();
+        let mut problems = Vec::new();
+
+        for tex_file in tex_files {
+            let (fingerprint, number) = Self::fingerprint_and_number_from_tex_file(&tex_file)?;
+
+            let Some(number) = number else {
+                problems.push(format!("{:?}: No invoice number found", tex_file));
+                continue;
+            };
+
+            if let Some(other) = files_for_number.get(&number) {
+                problems.push(format!("{:?}: Duplicate invoice number {number}, already used in {:?}", tex_file, other));
+                continue;
+            }
+            files_for_number.insert(number.clone(), tex_file.clone());
+
+            match fingerprint {
+                Some(fingerprint) => fingerprints.insert(fingerprint, number),
+                None => problems.push(format!("{:?}: No fingerprint found for invoice number {number}", tex_file)),
+            }
+        }
+
+        Ok((fingerprints, problems))
+    }
+
+    fn fingerprint_and_number_from_tex_file(path: &Path) -> Result<(Option<String>, Option<String>), InvoicerError> {
+        let mut fingerprint = None;
+        let mut number = None;
+        let mut fingerprint_follows = false;
+
+        for line in read_lines(path)? {
+            let line = line?;
+            let line = line.trim();
+
+            if fingerprint_follows {
+                fingerprint = line.strip_prefix('%').map(|f| f.trim().to_string());
+                fingerprint_follows = false;
+            }
+
+            if line == "%$INVOICE_FINGERPRINT" {
+                fingerprint_follows = true;
+            } else if let Some(n) = line.strip_prefix("\\newcommand{\\invoicenumber}{") {
+                number = n.strip_suffix('}').map(|n| n.to_string());
+            }
+        }
+
+        Ok((fingerprint, number))
     }
 }
 
 impl Default for InvoiceFingerprints {
     fn default() -> Self {
-        InvoiceFingerprints(bimap::BiMap::new())
+        InvoiceFingerprints(bimap::BiMap::new(), BTreeMap::new())
     }
 }
 
@@ -192,36 +567,223 @@ impl From<HashMap<String, String>> for InvoiceFingerprints {
         for (k, v) in map {
             bimap.insert(k, v);
         }
-        Self(bimap)
+        Self(bimap, BTreeMap::new())
     }
 }
 
 impl FromTomlFile for InvoiceFingerprints {}
 
+/// Key of the table with the last used counters, files without it are read with empty counters.
+const COUNTERS_KEY: &str = "counters";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FingerprintEntry {
+    Number(String),
+    Counters(BTreeMap<String, u32>),
+}
+
 impl<'de> Deserialize<'de>  for InvoiceFingerprints {
     fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        let s: HashMap<String, String> = Deserialize::deserialize(deserializer)?;
-        Ok(Self::from(s))
+        let s: HashMap<String, FingerprintEntry> = Deserialize::deserialize(deserializer)?;
+        let mut fingerprints = Self::default();
+        for (k, v) in s {
+            match v {
+                FingerprintEntry::Number(number) => fingerprints.insert(k, number),
+                FingerprintEntry::Counters(counters) if k == COUNTERS_KEY => fingerprints.1 = counters,
+                FingerprintEntry::Counters(_) => return Err(serde::de::Error::custom(format!("unexpected table {k}"))),
+            }
+        }
+        Ok(fingerprints)
     }
 }
 
 impl Serialize for InvoiceFingerprints {
     fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         use serde::ser::SerializeMap;
-        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        let mut map = serializer.serialize_map(Some(self.0.len() + 1))?;
         for (k, v) in &self.0 {
             map.serialize_entry(&k, &v)?;
         }
+        if !self.1.is_empty() {
+            map.serialize_entry(COUNTERS_KEY, &self.1)?;
+        }
         map.end()
     }
 }
 
 
 
+/// A generated invoice in the ledger.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub recipient: String,
+    pub number: String,
+    /// Invoice date as `%Y-%m-%d`
+    pub date: String,
+    /// Total amount to pay, including VAT if it is calculated
+    pub total: f32,
+    pub currency: String,
+}
+
+/// History of all generated invoices, stored in `ledger.toml` next to `fingerprints.toml`.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct Ledger {
+    #[serde(default)]
+    invoices: Vec<LedgerEntry>,
+}
+
+impl FromTomlFile for Ledger {}
+
+impl Ledger {
+    /// Record a generated invoice. An invoice generated again with the same number replaces its entry.
+    pub fn add(&mut self, invoice: &Invoice) {
+        let entry = LedgerEntry {
+            recipient: invoice.recipient().name().clone(),
+            number: invoice.number(),
+            date: invoice.date().format("%Y-%m-%d").to_string(),
+            total: invoice.total(),
+            currency: invoice.currency().str().clone(),
+        };
+        match self.invoices.iter_mut().find(|existing| existing.number == entry.number) {
+            Some(existing) => *existing = entry,
+            None => self.invoices.push(entry),
+        }
+    }
+
+    pub fn entries(&self) -> &Vec<LedgerEntry> {
+        &self.invoices
+    }
+
+    /// Invoices of a recipient, sorted by date and number.
+    pub fn entries_for(&self, recipient: &str, case_insensitive: bool) -> Vec<&LedgerEntry> {
+        let mut entries = self.invoices.iter()
+            .filter(|entry| tags_match(&entry.recipient, recipient, case_insensitive))
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.number.cmp(&b.number)));
+        entries
+    }
+
+    /// Invoices of a recipient with the running total in their currency.
+    pub fn history(&self, recipient: &str, case_insensitive: bool) -> Vec<(&LedgerEntry, f32)> {
+        let mut running_totals: HashMap<&String, f32> = HashMap::new();
+        self.entries_for(recipient, case_insensitive).into_iter()
+            .map(|entry| {
+                let running_total = running_totals.entry(&entry.currency).or_default();
+                *running_total += entry.total;
+                (entry, *running_total)
+            })
+            .collect()
+    }
+}
+
+
+/// Hours and amounts of an estimate, see `Invoicer::estimate`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EstimateTotals {
+    pub hours: f32,
+    pub net: f32,
+    pub tax: f32,
+    pub gross: f32,
+}
+
+/// What-if totals of the worklog at a single rate, without recipients or invoices.
+#[derive(Debug)]
+pub struct Estimate {
+    pub rate: f32,
+    pub currency: Currency,
+    /// Totals per worklog tag, each tag is treated as a recipient
+    pub recipients: BTreeMap<String, EstimateTotals>,
+    /// Totals of all billable records, records with several tags are counted once
+    pub total: EstimateTotals,
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let currency = &self.currency;
+        let line = |f: &mut std::fmt::Formatter<'_>, name: &str, totals: &EstimateTotals| writeln!(f,
+            "{name}: {hours} hours, net {net:.2} {currency:?}, tax {tax:.2} {currency:?}, gross {gross:.2} {currency:?}",
+            hours = Reconciliation::round(totals.hours), net = totals.net, tax = totals.tax, gross = totals.gross);
+
+        writeln!(f, "Estimate at {:.2} {currency:?}/h", self.rate)?;
+        for (recipient, totals) in &self.recipients {
+            line(f, &format!("  {recipient}"), totals)?;
+        }
+        line(f, "Total", &self.total)
+    }
+}
+
+/// Comparison of the worklog hours with the hours billed by the invoices of one run.
+/// For a complete config, `delta` is zero and there are no unmatched records.
+#[derive(Debug, Serialize)]
+pub struct Reconciliation {
+    /// Sum of the hours of all billable worklog records
+    pub worklog_hours: f32,
+    /// Sum of the hours of non-billable worklog records, which are not invoiced
+    pub nonbillable_hours: f32,
+    /// Sum of the hours of all invoices
+    pub billed_hours: f32,
+    /// Worklog hours not billed, e.g. by records without recipient tag or weights below 100%
+    pub delta: f32,
+    /// Sum of the hours of records whose tags match no recipient
+    pub unmatched_hours: f32,
+    /// Records whose tags match no recipient, as `start: message (tags)`
+    pub unmatched_records: Vec<String>,
+    /// Billed hours per recipient
+    pub recipients: BTreeMap<String, f32>,
+}
+
+impl Reconciliation {
+    fn round(hours: f32) -> f32 {
+        (hours * 100.0).round() / 100.0
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.delta == 0.0 && self.unmatched_records.is_empty()
+    }
+}
+
+impl Display for Reconciliation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Worklog hours: {}", self.worklog_hours)?;
+        if self.nonbillable_hours > 0.0 {
+            writeln!(f, "Non-billable hours: {}", self.nonbillable_hours)?;
+        }
+        for (recipient, hours) in &self.recipients {
+            writeln!(f, "  {recipient}: {hours} hours")?;
+        }
+        writeln!(f, "Billed hours: {}", self.billed_hours)?;
+        writeln!(f, "Delta: {}", self.delta)?;
+        for record in &self.unmatched_records {
+            writeln!(f, "  Unmatched: {record}")?;
+        }
+        Ok(())
+    }
+}
+
+
 pub struct Invoicer {
     config: Config,
     date: DateTime,
-    counter: u32,
+    counter: Option<u32>,
+    rate: Option<f32>,
+    template: Option<String>,
+    deposit: Option<f32>,
+    days_for_payment: Option<u32>,
+    po_number: Option<String>,
+    paid: bool,
+    report_currency: Option<Currency>,
+    only: Vec<String>,
+    attachments: Vec<String>,
+    combine: Option<PathBuf>,
+    strict_template: bool,
+    strict: bool,
+    preview: bool,
+    verbose: bool,
+    reconcile: Option<PathBuf>,
+    template_path: Vec<PathBuf>,
+    locale: Option<String>,
+    promotion: Option<Promotion>,
     worklog: Worklog,
     recipients: Vec<Recipient>,
 }
@@ -231,126 +793,816 @@ impl Invoicer {
         Self {
             config: config.clone(),
             date: date.unwrap_or(now()),
-            counter: counter.unwrap_or(1),
+            counter,
+            rate: None,
+            template: None,
+            deposit: None,
+            days_for_payment: None,
+            po_number: None,
+            paid: false,
+            report_currency: None,
+            only: Vec::new(),
+            attachments: Vec::new(),
+            combine: None,
+            strict_template: false,
+            strict: false,
+            preview: false,
+            verbose: false,
+            reconcile: None,
+            template_path: Vec::new(),
+            locale: None,
+            promotion: None,
             worklog: Worklog::new(),
             recipients: Vec::new(),
         }
     }
 
-    fn fingerprint_file(&self) -> PathBuf {
-        self.config.directories.config_dir().join("fingerprints.toml")
+    /// Directories searched for templates before the configured ones, e.g. from `--template-path`.
+    pub fn set_template_path(&mut self, template_path: Vec<PathBuf>) {
+        self.template_path = template_path;
     }
 
-    pub fn append_worklog(&mut self, worklog: &Worklog) {
-        self.worklog.append(worklog);
+    /// Directories searched for templates and their `\\input` files, in this order:
+    /// the directories given with `set_template_path`, the `template_path` of the config and the template directory.
+    /// Embedded templates are used if no directory contains a template.
+    pub fn template_search_path(&self) -> Vec<PathBuf> {
+        let mut search_path = self.template_path.clone();
+        search_path.extend(self.config.template_search_path());
+        search_path
     }
 
-    pub fn append_worklog_from_csv_file(&mut self, csv: &str) -> Result<(), Box<dyn std::error::Error>> {
-        match Worklog::from_csv_file(&csv) {
-            Ok(worklog) => {
-                self.append_worklog(&worklog);
-                Ok(())
-            }
-            Err(e) => {
-                eprintln!("Error loading worklog {csv}: {e}");
-                Err(e)
-            }
+    /// Set a locale for all invoices, which takes precedence over the locales of the recipients and the config.
+    /// Fails if the locale file does not exist in the locale directory.
+    pub fn set_locale(&mut self, locale: &str) -> Result<(), InvoicerError> {
+        let locale_file = self.locale_dir().join(format!("{locale}.toml"));
+        if !locale_file.exists() {
+            return Err(InvoicerError::InvalidConfig(format!("locale file {:?} for --locale {locale} does not exist", locale_file)));
         }
+        self.locale = Some(locale.to_string());
+        Ok(())
     }
 
-    pub fn has_recipients(&self) -> bool {
-        !self.recipients.is_empty()
+    pub fn locale(&self) -> Option<&String> {
+        self.locale.as_ref()
     }
 
-    pub fn add_recipient(&mut self, recipient: Recipient) {
-        self.recipients.push(recipient);
+    /// Apply the promotion with this name of the `[[promotions]]` list to all invoices.
+    /// Fails if there is no such promotion or it expired before the invoice date.
+    pub fn set_promotion(&mut self, name: &str) -> Result<(), InvoicerError> {
+        let promotion = self.config.promotion(name)
+            .ok_or(InvoicerError::InvalidConfig(format!("unknown promotion '{name}'")))?;
+        if !promotion.is_valid_on(self.date.date()) {
+            return Err(InvoicerError::InvalidConfig(format!("promotion '{name}' expired on {}", promotion.expires().unwrap_or_default())));
+        }
+        self.promotion = Some(promotion.clone());
+        Ok(())
     }
 
-    pub fn add_recipients_from_worklog(&mut self) {
-        let mut recipients = Recipient::from_tags(self.worklog.tags(), &self.tag_dir());
-        self.recipients.append(&mut recipients);
+    pub fn promotion(&self) -> Option<&Promotion> {
+        self.promotion.as_ref()
     }
 
-    pub fn add_recipient_from_toml_file<P: FilePath>(&mut self, toml: P) -> Result<(), Box<dyn std::error::Error>> {
-        let s = toml.to_string();
-        match Recipient::from_toml_file(toml) {
-            Ok(recipient) => {
-                self.recipients.push(recipient);
-                Ok(())
-            },
-            Err(e) => {
-                eprintln!("Could not load recipient '{}': {e}!", s);
-                Err(e)
-            },
-        }
+    /// Set a rate for all recipients which takes precedence over the configured default rates.
+    /// Records with their own rate still use it.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = Some(rate);
     }
 
+    pub fn rate(&self) -> Option<f32> {
+        self.rate
+    }
 
-    pub fn generate(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Set a template for all invoices, relative to the template directory.
+    /// It takes precedence over the templates of tags, recipients and the config.
+    pub fn set_template(&mut self, template: &str) {
+        self.template = Some(template.to_string());
+    }
 
-        println!("{}", self);
-        
-        self.mkdir()?;
+    pub fn template(&self) -> Option<&String> {
+        self.template.as_ref()
+    }
 
+    /// Set a deposit for all invoices, which takes precedence over the deposits of the recipients.
+    pub fn set_deposit(&mut self, deposit: f32) {
+        self.deposit = Some(deposit);
+    }
 
-        // Return if no recipients are given
-        if self.recipients.is_empty() {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No recipient given!")));
-        }
+    pub fn deposit(&self) -> Option<f32> {
+        self.deposit
+    }
 
-        let mut counter = self.counter;
+    /// Set the days for payment of all invoices, which take precedence over those of the recipients and the config.
+    pub fn set_days_for_payment(&mut self, days: u32) {
+        self.days_for_payment = Some(days);
+    }
 
-        let mut fingerprints = InvoiceFingerprints::from_toml_file(self.fingerprint_file()).unwrap_or_default();
+    pub fn days_for_payment(&self) -> Option<u32> {
+        self.days_for_payment
+    }
 
-        // Create an invoice for each recipient
-        for recipient in &self.recipients {
-            let mut worklog = self.worklog.from_records_with_tag(recipient.name());
-            let mut invoice = Invoice::new(&self,  recipient.clone());
-            worklog.set_rate(invoice.default_rate());
+    /// Set the purchase order number of all invoices, which takes precedence over those of the recipients.
+    pub fn set_po_number(&mut self, po_number: &str) {
+        self.po_number = Some(po_number.to_string());
+    }
 
-            counter = invoice.generate_number(counter, Some(&fingerprints));
-            
-            let tex_file = Path::new(&self.invoice_dir()).join(invoice.filename());
-            invoice.add_worklog(&worklog);
+    pub fn po_number(&self) -> Option<&String> {
+        self.po_number.as_ref()
+    }
 
-            if invoice.positions().is_empty() {
-                eprintln!("{:?}: Warning: The generated invoice contains no positions, no invoice will be generated!", tex_file);
-                continue;
-            }
+    /// Mark all generated invoices as paid.
+    /// The invoice number is kept because the paid status is not part of the fingerprint.
+    pub fn set_paid(&mut self, paid: bool) {
+        self.paid = paid;
+    }
 
-            if tex_file.exists() {
-                eprintln!("{:?}: Warning: The tex file to be generated already exists.", tex_file);
-                continue;
-            }
+    pub fn paid(&self) -> bool {
+        self.paid
+    }
 
-            invoice.generate_tex_file(&tex_file)?;
+    /// Currency for the grand total printed after generating all invoices.
+    pub fn set_report_currency(&mut self, currency: Currency) {
+        self.report_currency = Some(currency);
+    }
 
-            self.generate_pdf(&tex_file)?;
+    /// Files attached to all invoices, listed after the attachments of the recipients.
+    pub fn set_attachments(&mut self, attachments: Vec<String>) {
+        self.attachments = attachments;
+    }
 
-            fingerprints.add(&invoice);
+    pub fn attachments(&self) -> &Vec<String> {
+        &self.attachments
+    }
 
-            let sum_text = if invoice.calculate_value_added_tax() {
-                format!("total (incl. VAT) = {sum}", sum = invoice.locale().format_amount(invoice.sum_with_tax()))
-            } else {
-                format!("total = {sum}", sum = invoice.locale().format_amount(invoice.sum()))
-            };
+    /// Fail instead of warning if an invoice template contains unknown tokens or lacks required ones.
+    pub fn set_strict_template(&mut self, strict: bool) {
+        self.strict_template = strict;
+    }
 
-            println!("{:?}: {positions} positions, {sum}", 
-                tex_file,
-                positions = invoice.positions().len(),
-                sum = sum_text
-            );
+    pub fn strict_template(&self) -> bool {
+        self.strict_template
+    }
+
+    /// Fail the run after generating the other invoices if an invoice was skipped,
+    /// because its recipient has no positions or its tex file exists and is not overwritten.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// In preview mode, existing tex files are overwritten and no fingerprints are stored,
+    /// so invoices can be generated repeatedly, e.g. by `--watch`.
+    pub fn set_preview(&mut self, preview: bool) {
+        self.preview = preview;
+    }
+
+    /// Print the fingerprint of each invoice next to its number.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    /// Write a reconciliation report of worklog and billed hours to this TOML file after generating the invoices.
+    pub fn set_reconcile(&mut self, path: PathBuf) {
+        self.reconcile = Some(path);
+    }
+
+    /// Combine the PDFs of all invoices into this file after generating them.
+    pub fn set_combine(&mut self, path: PathBuf) {
+        self.combine = Some(path);
+    }
+
+    /// Generate only the invoices of the recipients with these names, all recipients if empty.
+    pub fn set_only(&mut self, names: Vec<String>) {
+        self.only = names;
+    }
+
+    /// Recipients selected with `set_only`.
+    /// Returns an error naming all selected recipients which are not loaded.
+    fn selected_recipients(&self) -> Result<Vec<&Recipient>, InvoicerError> {
+        if self.only.is_empty() {
+            return Ok(self.recipients.iter().collect());
+        }
+
+        let case_insensitive = self.config.worklog().case_insensitive_tags();
+        let selected = |recipient: &Recipient| self.only.iter().any(|name| tags_match(name, recipient.name(), case_insensitive));
+
+        let unknown = self.only.iter()
+            .filter(|name| !self.recipients.iter().any(|recipient| tags_match(name, recipient.name(), case_insensitive)))
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            return Err(InvoicerError::UnknownRecipients(unknown.join(", ")));
+        }
+
+        Ok(self.recipients.iter().filter(|recipient| selected(recipient)).collect())
+    }
+
+    fn fingerprint_file(&self) -> PathBuf {
+        self.config.directories.config_dir().join("fingerprints.toml")
+    }
+
+    fn ledger_file(&self) -> PathBuf {
+        self.config.directories.config_dir().join("ledger.toml")
+    }
+
+    /// Read the ledger, which is empty if the file does not exist yet.
+    /// Other errors are returned, so a corrupt ledger is never replaced by an empty one.
+    fn load_ledger(&self) -> Result<Ledger, InvoicerError> {
+        match Ledger::from_toml_file(self.ledger_file()) {
+            Err(InvoicerError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(Ledger::default()),
+            result => result,
+        }
+    }
+
+    /// Write the invoices of a recipient from the ledger with the running total.
+    pub fn print_ledger(&self, recipient: &str, w: &mut dyn std::io::Write) -> Result<(), InvoicerError> {
+        let ledger = self.load_ledger()?;
+        let history = ledger.history(recipient, self.config.worklog().case_insensitive_tags());
+        if history.is_empty() {
+            writeln!(w, "No invoices for {recipient} in {:?}", self.ledger_file())?;
+        }
+        for (entry, running_total) in history {
+            writeln!(w, "{}\t{}\t{:.2} {}\t{:.2} {}", entry.date, entry.number, entry.total, entry.currency, running_total, entry.currency)?;
+        }
+        Ok(())
+    }
+
+    pub fn append_worklog(&mut self, worklog: &Worklog) {
+        self.worklog.append(worklog);
+    }
+
+    pub fn append_worklog_from_csv_file(&mut self, csv: &str) -> Result<(), InvoicerError> {
+        match Worklog::from_csv_file_with_config(csv, self.config.worklog()) {
+            Ok(worklog) => {
+                self.append_worklog(&worklog);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Error loading worklog {csv}: {e}");
+                Err(e)
+            }
+        }
+    }
+
+    /// Append all `*.csv` files of a directory in the order of their names, e.g. the per-day exports of a time tracker.
+    /// The number of records of each file is printed, files which can not be read are skipped with a warning.
+    /// Returns the number of loaded files.
+    pub fn append_worklogs_from_dir(&mut self, dir: &Path) -> Result<usize, InvoicerError> {
+        let mut csvs = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("csv")))
+            .collect::<Vec<_>>();
+        csvs.sort();
+
+        let mut loaded = 0;
+        for csv in &csvs {
+            match Worklog::from_csv_file_with_config(&csv.to_string_lossy(), self.config.worklog()) {
+                Ok(worklog) => {
+                    eprintln!("{:?}: {} records", csv, worklog.len());
+                    self.append_worklog(&worklog);
+                    loaded += 1;
+                }
+                Err(e) => eprintln!("{:?}: Warning: Skipping worklog, {e}", csv),
+            }
+        }
+        Ok(loaded)
+    }
+
+    pub fn has_recipients(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    pub fn add_recipient(&mut self, recipient: Recipient) {
+        self.recipients.push(recipient);
+    }
+
+    /// Add a recipient for each tag of the worklog.
+    /// Recipients defined inline in the config are preferred over the TOML files in the tag directory.
+    pub fn add_recipients_from_worklog(&mut self) {
+        let case_insensitive = self.config.worklog().case_insensitive_tags();
+        let mut tags = self.worklog.tags().clone();
+        for recipient in self.config.recipients() {
+            let len = tags.len();
+            tags.retain(|tag| !tags_match(tag, recipient.name(), case_insensitive));
+            if tags.len() < len {
+                self.add_recipient(recipient);
+            }
+        }
+
+        let mut recipients = Recipient::from_tags(&tags, &self.tag_dir());
+        self.recipients.append(&mut recipients);
+    }
+
+    pub fn add_recipient_from_toml_file<P: FilePath>(&mut self, toml: P) -> Result<(), InvoicerError> {
+        let s = toml.to_string();
+        match Recipient::from_toml_file(toml) {
+            Ok(recipient) => {
+                self.recipients.push(recipient);
+                Ok(())
+            },
+            Err(e) => {
+                eprintln!("Could not load recipient '{}': {e}!", s);
+                Err(e)
+            },
+        }
+    }
+
+
+    pub fn generate(&self) -> Result<(), InvoicerError> {
+
+        println!("{}", self);
+        
+        self.check_invoice_dir()?;
+        self.mkdir()?;
+
+        if let Some(logo) = self.config.logo().filter(|logo| !logo.exists()) {
+            eprintln!("{:?}: Warning: The logo does not exist.", logo);
+        }
+
+
+        let mut fingerprints = InvoiceFingerprints::from_toml_file(self.fingerprint_file()).unwrap_or_default();
+        let mut ledger = self.load_ledger()?;
+
+        let mut totals: Vec<(Currency, f32)> = Vec::new();
+
+        let mut invoices = self.create_invoices(&fingerprints)?;
+        self.check_templates(&invoices)?;
+        let mut pdf_files = Vec::new();
+        let mut written = HashSet::new();
+
+        // Recipients left out by `create_invoices` have no positions
+        let mut skipped = self.selected_recipients()?.into_iter()
+            .filter(|recipient| !invoices.iter().any(|invoice| invoice.recipient().name() == recipient.name()))
+            .map(|recipient| format!("{}: no positions", recipient.name()))
+            .collect::<Vec<_>>();
+
+        for invoice in invoices.iter_mut() {
+            let tex_file = match self.resolve_tex_file(invoice, &written)? {
+                Some(tex_file) => tex_file,
+                None => {
+                    skipped.push(format!("{}: {} exists", invoice.recipient().name(), invoice.filename()));
+                    pdf_files.push(Path::new(&self.invoice_dir()).join(invoice.filename()).with_extension("pdf"));
+                    continue;
+                }
+            };
+            pdf_files.push(tex_file.with_extension("pdf"));
+            written.insert(tex_file.clone());
+            let invoice = &*invoice;
+
+            invoice.generate_tex_file(&tex_file)?;
+
+            // Empty drafts are completed manually, so there is nothing to pay or compile yet.
+            // Their fingerprint is not recorded, because it would not match the completed invoice.
+            if invoice.positions().is_empty() {
+                println!("{:?}: Empty draft", tex_file);
+                continue;
+            }
+
+            // The payment data was validated by `create_invoices`, so only the QR generator can fail here.
+            // The invoice is kept without QR code, since fingerprints and ledger have to match the written files.
+            if invoice.config().payment_qr() {
+                if let Err(e) = self.generate_payment_qr(invoice) {
+                    eprintln!("{:?}: Warning: No payment QR code generated, {e}", tex_file);
+                }
+            }
+
+            self.generate_pdf(&tex_file)?;
+
+            fingerprints.add(invoice);
+            ledger.add(invoice);
+
+            let sum_text = if invoice.calculate_value_added_tax() {
+                format!("total (incl. VAT) = {sum}", sum = invoice.locale().format_amount(invoice.sum_with_tax()))
+            } else {
+                format!("total = {sum}", sum = invoice.locale().format_amount(invoice.sum()))
+            };
+
+            println!("{:?}: {positions} positions, {hours} hours, {sum}", 
+                tex_file,
+                positions = invoice.positions().len(),
+                hours = invoice.locale().format_number(invoice.total_hours(), 2),
+                sum = sum_text
+            );
+
+            totals.push((invoice.currency(), invoice.total()));
+        }
+
+        if let Some(report_currency) = &self.report_currency {
+            self.print_report_total(&totals, report_currency);
+        }
+
+        if !self.preview {
+            self.save_fingerprints(&fingerprints)?;
+            std::fs::write(self.ledger_file(), toml::to_string(&ledger)?)?;
+        }
+
+        if let Some(path) = &self.reconcile {
+            let reconciliation = self.reconcile(&invoices);
+            print!("{reconciliation}");
+            if !reconciliation.is_balanced() {
+                eprintln!("Warning: {} worklog hours are not billed!", reconciliation.delta);
+            }
+            std::fs::write(path, toml::to_string(&reconciliation)?)?;
+        }
+
+        if let Some(combined) = &self.combine {
+            self.combine_pdfs(&pdf_files, combined)?;
+        }
+
+        match self.strict && !skipped.is_empty() {
+            true => Err(InvoicerError::SkippedInvoices(skipped.join(", "))),
+            false => Ok(()),
+        }
+    }
+
+    /// Tex file of the invoice according to the `overwrite` behaviour, `None` if the invoice is skipped.
+    /// A file written earlier in the same run is never overwritten, the invoice gets a `_rev<N>` suffix instead.
+    /// In preview mode, existing files are overwritten.
+    fn resolve_tex_file(&self, invoice: &mut Invoice, written: &HashSet<PathBuf>) -> Result<Option<PathBuf>, InvoicerError> {
+        let invoice_dir = self.invoice_dir();
+        let tex_file = invoice_dir.join(invoice.filename());
+        let collision = written.contains(&tex_file);
+        if !collision && (!tex_file.exists() || self.preview) {
+            return Ok(Some(tex_file));
+        }
+
+        let filename = invoice.filename();
+        let revision = (1..)
+            .find(|revision| {
+                let path = invoice_dir.join(with_revision(&filename, *revision));
+                !path.exists() && !written.contains(&path)
+            })
+            .unwrap();
+
+        let overwrite = if collision { OverwriteBehaviour::RenameNew } else { self.config.overwrite() };
+        match overwrite {
+            OverwriteBehaviour::Force => Ok(Some(tex_file)),
+            OverwriteBehaviour::Skip => {
+                eprintln!("{:?}: Warning: The tex file to be generated already exists.", tex_file);
+                Ok(None)
+            }
+            OverwriteBehaviour::RenameOld => {
+                for path in [tex_file.clone(), tex_file.with_extension("pdf")] {
+                    if path.exists() {
+                        let renamed = invoice_dir.join(with_revision(&Path::file_name(&path).unwrap().to_string_lossy(), revision));
+                        println!("{:?}: Renamed existing file to {:?}", path, renamed);
+                        std::fs::rename(&path, renamed)?;
+                    }
+                }
+                Ok(Some(tex_file))
+            }
+            OverwriteBehaviour::RenameNew => {
+                if collision {
+                    eprintln!("{:?}: Warning: Another invoice of this run has the same file name.", tex_file);
+                }
+                invoice.set_revision(revision);
+                Ok(Some(invoice_dir.join(invoice.filename())))
+            }
+        }
+    }
+
+    /// Concatenate the existing PDFs into one file with the configured `pdf_combiner`.
+    /// The individual files are kept.
+    pub fn combine_pdfs(&self, pdf_files: &[PathBuf], combined: &Path) -> Result<(), InvoicerError> {
+        let pdf_files = pdf_files.iter().filter(|pdf| pdf.exists()).collect::<Vec<_>>();
+        if pdf_files.is_empty() {
+            return Err(InvoicerError::CombinePdf("No PDF files found, is a pdf_generator configured?".to_string()));
+        }
+
+        let combiner = self.config.pdf_combiner();
+        let output = std::process::Command::new(&combiner)
+            .args(&pdf_files)
+            .arg(combined)
+            .output()
+            .map_err(|e| InvoicerError::CombinePdf(format!("Failed to execute {combiner:?}: {e}")))?;
+        if !output.status.success() {
+            return Err(InvoicerError::CombinePdf(format!("{combiner:?} failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+        }
+
+        println!("{:?}: {} invoices combined, {} pages", combined, pdf_files.len(), pdf_page_count(&std::fs::read(combined)?));
+        Ok(())
+    }
+
+    /// Create the invoice directory and check that files can be written into it.
+    pub fn check_invoice_dir(&self) -> Result<(), InvoicerError> {
+        let path = self.invoice_dir();
+        let probe = path.join(".invoicer_write_test");
+        std::fs::create_dir_all(&path)
+            .and_then(|_| std::fs::File::create(&probe))
+            .and_then(|_| std::fs::remove_file(&probe))
+            .map_err(|err| InvoicerError::DirectoryNotWritable {
+                setting: self.config.directories.invoices_setting(),
+                path,
+                err,
+            })
+    }
+
+    /// Check that the locale file of each selected recipient exists and can be loaded,
+    /// so a misspelled `locale` of a recipient or a broken locale file is reported before any invoice is generated.
+    pub fn check_recipient_locales(&self) -> Result<(), InvoicerError> {
+        let missing = self.selected_recipients()?.into_iter()
+            .map(|recipient| (recipient.name(), Invoice::new(self, recipient.clone()).locale_file()))
+            .filter_map(|(name, locale_file)| match Locale::from_toml_file(locale_file.as_path()) {
+                Ok(_) => None,
+                Err(_) if !locale_file.exists() => Some(format!("{name}: locale file {locale_file:?} does not exist")),
+                Err(e) => Some(format!("{name}: locale file {locale_file:?} can not be loaded: {e}")),
+            })
+            .collect::<Vec<_>>();
+
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(InvoicerError::InvalidConfig(missing.join(", "))),
+        }
+    }
+
+    /// Check that the `\\input` files of the invoice and timesheet templates can be included,
+    /// so no invoice silently lacks a section.
+    /// Missing files are printed as warning, or fail with `strict_template`.
+    pub fn check_templates(&self, invoices: &[Invoice]) -> Result<(), InvoicerError> {
+        let search_path = self.template_search_path();
+        let mut templates = BTreeSet::new();
+        for invoice in invoices {
+            templates.insert(invoice.template());
+            if invoice.generate_timesheet() {
+                templates.insert(invoice.config().timesheet_template());
+            }
+        }
+
+        let missing = templates.into_iter()
+            .filter_map(|template| {
+                let missing = missing_inputs(&template_text(&search_path, &template)?, &search_path);
+                (!missing.is_empty()).then(|| format!("{template}: missing input files {}", missing.join(", ")))
+            })
+            .collect::<Vec<_>>();
+
+        match (missing.is_empty(), self.strict_template) {
+            (true, _) => Ok(()),
+            (false, true) => Err(InvoicerError::InvalidConfig(missing.join("; "))),
+            (false, false) => {
+                missing.iter().for_each(|message| eprintln!("Warning: {message}"));
+                Ok(())
+            }
+        }
+    }
+
+    /// Create and number an invoice for each recipient.
+    /// Recipients without positions are skipped with a warning.
+    fn create_invoices(&self, fingerprints: &InvoiceFingerprints) -> Result<Vec<Invoice<'_>>, InvoicerError> {
+        // Return if no recipients are given
+        if self.recipients.is_empty() {
+            return Err(InvoicerError::NoRecipients);
+        }
+        self.check_recipient_locales()?;
+
+        // Next counter of each number scope, continuing after the last used counter unless a counter was given
+        let mut counters: HashMap<String, u32> = HashMap::new();
+
+        // Create an invoice for each selected recipient
+        let mut invoices = Vec::new();
+        for recipient in self.selected_recipients()? {
+            let mut worklog = self.worklog.from_records_matching_tag(recipient.name(), self.config.worklog().case_insensitive_tags());
+            let mut invoice = Invoice::new(self, recipient.clone());
+            worklog.set_rate(invoice.worklog_rate(&worklog)?);
+            invoice.add_worklog(&worklog);
+
+            if invoice.positions().is_empty() {
+                if !invoice.config().generate_empty() {
+                    eprintln!("{}: Warning: The generated invoice contains no positions, no invoice will be generated!", recipient.name());
+                    continue;
+                }
+                eprintln!("{}: Warning: The generated invoice contains no positions, an empty draft will be generated!", recipient.name());
+                invoice.set_period(invoice.date(), invoice.date());
+            }
+
+            let scope_key = invoice.number_scope_key();
+            let counter = counters.get(&scope_key).copied()
+                .or(self.counter)
+                .unwrap_or(fingerprints.counter(&scope_key).map_or(1, |last| last + 1));
+            counters.insert(scope_key, invoice.generate_number(counter, Some(fingerprints)));
+            if self.verbose {
+                eprintln!("{}: Invoice number {} ({}), fingerprint {}", recipient.name(), invoice.number(),
+                    if invoice.counter().is_some() { "new" } else { "reused" }, invoice.fingerprint());
+            }
+            invoices.push(invoice);
+        }
+
+        Self::check_unique_numbers(&invoices)?;
+
+        // Invalid payment data fails the run before any file is written
+        for invoice in invoices.iter().filter(|invoice| invoice.config().payment_qr() && !invoice.positions().is_empty()) {
+            Self::payment_qr_payload(invoice)?;
         }
 
-        // Save fingerprint file
+        Ok(invoices)
+    }
+
+    /// What-if totals of all billable worklog hours at the given rate, ignoring the rates of the records.
+    /// Instead of the recipients, each worklog tag is treated as a recipient, weighted tags with their share of the hours.
+    /// The total counts each record once, even if it has several tags.
+    /// Taxes are calculated like on an invoice with the `taxes` or `tax_rate` of the first payment account,
+    /// unless `calculate_value_added_tax` is disabled.
+    /// Neither files nor fingerprints are read or written.
+    pub fn estimate(&self, rate: f32) -> Estimate {
+        let case_insensitive = self.config.worklog().case_insensitive_tags();
+        let payment = self.config.payment();
+        let rounding = payment.rounding();
+        let calculate_tax = self.config.invoice().calculate_value_added_tax();
+
+        let mut tags = self.worklog.tags().iter()
+            .filter(|tag| !tag.is_empty())
+            .collect::<Vec<_>>();
+        tags.sort();
+        tags.dedup_by(|a, b| tags_match(a, b, case_insensitive));
+
+        let totals = |hours: f32| {
+            let net = rounding.round(hours * rate, 2);
+            let tax = if calculate_tax {
+                rounding.round(payment.tax_amounts(net).iter().map(|(_, amount)| amount).sum(), 2)
+            } else {
+                0.0
+            };
+            EstimateTotals { hours, net, tax, gross: net + tax }
+        };
+
+        Estimate {
+            rate,
+            currency: payment.currency(),
+            recipients: tags.into_iter()
+                .map(|tag| (tag.clone(), self.worklog.from_records_matching_tag(tag, case_insensitive).billable_hours()))
+                .filter(|(_, hours)| *hours > 0.0)
+                .map(|(tag, hours)| (tag, totals(hours)))
+                .collect(),
+            total: totals(self.worklog.billable_hours()),
+        }
+    }
+
+    /// Compare the hours of the worklog with the hours billed by the given invoices.
+    pub fn reconcile(&self, invoices: &[Invoice]) -> Reconciliation {
+        let case_insensitive_tags = self.config.worklog().case_insensitive_tags();
+        let worklog_hours = self.worklog.billable_hours();
+
+        let mut recipients = BTreeMap::new();
+        for invoice in invoices {
+            *recipients.entry(invoice.recipient().name().clone()).or_insert(0.0) += invoice.total_hours();
+        }
+        let billed_hours: f32 = recipients.values().sum();
+
+        let unmatched = self.worklog.records().iter()
+            .filter(|record| record.is_billable())
+            .filter(|record| !self.recipients.iter().any(|recipient| record.find_tag(recipient.name(), case_insensitive_tags).is_some()))
+            .collect::<Vec<_>>();
+
+        Reconciliation {
+            worklog_hours: Reconciliation::round(worklog_hours),
+            nonbillable_hours: Reconciliation::round(self.worklog.total_hours() - worklog_hours),
+            billed_hours: Reconciliation::round(billed_hours),
+            delta: Reconciliation::round(worklog_hours - billed_hours),
+            unmatched_hours: Reconciliation::round(unmatched.iter().map(|record| record.hours).sum()),
+            unmatched_records: unmatched.iter()
+                .map(|record| {
+                    let mut tags = record.tags().into_iter().filter(|tag| !tag.is_empty()).collect::<Vec<_>>();
+                    tags.sort();
+                    format!("{}: {} ({})", record.start, record.message, tags.join(", "))
+                })
+                .collect(),
+            recipients: recipients.into_iter().map(|(name, hours)| (name, Reconciliation::round(hours))).collect(),
+        }
+    }
+
+    /// Write the tex output of the first invoice to `w` instead of a file, e.g. for a preview on stdout.
+    /// The invoice gets a number, but neither files nor fingerprints are written.
+    pub fn print(&self, w: &mut dyn std::io::Write) -> Result<(), InvoicerError> {
+        let fingerprints = InvoiceFingerprints::from_toml_file(self.fingerprint_file()).unwrap_or_default();
+        let invoices = self.create_invoices(&fingerprints)?;
+
+        if invoices.len() > 1 {
+            eprintln!("Warning: {} invoices selected, only the invoice for '{}' is printed. Select a single recipient with -r or --only.", 
+                invoices.len(), invoices[0].recipient().name());
+        }
+
+        match invoices.first() {
+            Some(invoice) => Ok(invoice.generate_tex(w)?),
+            None => Err(InvoicerError::NoInvoices),
+        }
+    }
+
+    /// Check that no invoice number was assigned to several invoices.
+    pub fn check_unique_numbers(invoices: &[Invoice]) -> Result<(), InvoicerError> {
+        let mut recipients_for_number: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+        for invoice in invoices {
+            recipients_for_number.entry(invoice.number()).or_default().push(invoice.recipient().name());
+        }
+
+        let collisions = recipients_for_number.iter()
+            .filter(|(_, recipients)| recipients.len() > 1)
+            .map(|(number, recipients)| format!("{number} ({})", recipients.iter().map(|r| r.as_str()).collect::<Vec<_>>().join(", ")))
+            .collect::<Vec<_>>();
+
+        if collisions.is_empty() {
+            Ok(())
+        } else {
+            Err(InvoicerError::DuplicateInvoiceNumbers(collisions.join("; ")))
+        }
+    }
+
+    fn print_report_total(&self, totals: &[(Currency, f32)], report_currency: &Currency) {
+        let mut grand_total = 0.0_f32;
+        for (currency, total) in totals {
+            match self.config.exchange_rate(currency, report_currency) {
+                Some(rate) => {
+                    println!("{total:.2} {currency:?} = {converted:.2} {report_currency:?}", converted = total * rate);
+                    grand_total += total * rate;
+                }
+                None => eprintln!("Warning: No exchange rate for {currency:?}, {total:.2} {currency:?} is not included in the grand total!"),
+            }
+        }
+        println!("Grand total: {grand_total:.2} {report_currency:?}");
+    }
+
+    fn save_fingerprints(&self, fingerprints: &InvoiceFingerprints) -> Result<(), InvoicerError> {
         use std::io::Write;
-        let s = toml::to_string(&fingerprints).unwrap();
+        let s = toml::to_string(fingerprints)?;
         let mut f = std::fs::File::create(self.fingerprint_file())?;
         write!(f, "{}", s)?;
 
         Ok(())
     }
 
+    /// Rebuild the fingerprint file from the tex files in the invoice directory.
+    pub fn rebuild_fingerprints(&self) -> Result<(), InvoicerError> {
+        let (mut fingerprints, problems) = InvoiceFingerprints::from_tex_files(&self.invoice_dir())?;
+
+        // The counters cannot be read from the tex files, so they are kept from the existing file
+        if let Ok(existing) = InvoiceFingerprints::from_toml_file(self.fingerprint_file()) {
+            fingerprints.1 = existing.1;
+        }
+
+        for problem in &problems {
+            eprintln!("Warning: {problem}");
+        }
+
+        self.save_fingerprints(&fingerprints)?;
+        println!("{:?}: {} fingerprints written", self.fingerprint_file(), fingerprints.len());
+
+        Ok(())
+    }
+
+    /// Load all locales in the locale directory and print their settings.
+    /// With `reference`, the translation keys missing compared to this locale are listed as well.
+    pub fn check_locales(&self, reference: Option<&str>) -> Result<(), InvoicerError> {
+        let mut files = std::fs::read_dir(self.locale_dir())?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let mut locales = Vec::new();
+        let mut errors = 0;
+        for file in &files {
+            match Locale::from_toml_file(file.as_path()) {
+                Ok(locale) => locales.push(locale),
+                Err(e) => {
+                    eprintln!("{}: Error: {e}", file.display());
+                    errors += 1;
+                }
+            }
+        }
+
+        let reference = match reference {
+            Some(name) => Some(locales.iter().find(|locale| locale.name() == name)
+                .ok_or(InvoicerError::InvalidConfig(format!("Reference locale '{name}' not found in {}", self.locale_dir().display())))?),
+            None => None,
+        };
+
+        for locale in &locales {
+            println!("{}: currency {:?}, decimal '{}', separator '{}', {} translations",
+                locale.name(), locale.currency(), locale.decimal(), locale.separator(), locale.translations().len());
+            if let Some(reference) = reference {
+                let missing = locale.missing_translations(reference);
+                if !missing.is_empty() {
+                    println!("\tmissing compared to {}: {}", reference.name(), missing.iter().map(|key| key.as_str()).collect::<Vec<_>>().join(", "));
+                }
+            }
+        }
+
+        match errors {
+            0 => Ok(()),
+            _ => Err(InvoicerError::InvalidConfig(format!("{errors} locale(s) could not be loaded"))),
+        }
+    }
+
+    /// Write a commented recipient TOML `<name>.toml` into the tag directory.
+    /// An existing file is only overwritten with `force`.
+    pub fn new_recipient(&self, name: &str, force: bool) -> Result<PathBuf, InvoicerError> {
+        let file = self.tag_dir().join(format!("{name}.toml"));
+        if file.exists() && !force {
+            return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+                format!("{file:?} already exists, use --force to overwrite it")).into());
+        }
+
+        std::fs::create_dir_all(self.tag_dir())?;
+        std::fs::write(&file, Recipient::template(name))?;
+        Ok(file)
+    }
+
     pub fn config(&self) -> &Config {
         &self.config
     }
@@ -359,7 +1611,24 @@ impl Invoicer {
         self.date
     }
 
-    pub fn generate_pdf(&self, tex_file: &impl FilePath) -> Result<(), Box<dyn std::error::Error>> {
+    /// EPC payload of the payment QR code of an invoice, errors name the invoice file.
+    fn payment_qr_payload(invoice: &Invoice) -> Result<String, InvoicerError> {
+        invoice.epc_payment().payload()
+            .map_err(|e| match e {
+                InvoicerError::PaymentQr(message) => InvoicerError::PaymentQr(format!("{}: {message}", invoice.filename())),
+                e => e,
+            })
+    }
+
+    /// Render the EPC payment QR code of an invoice next to its tex file.
+    pub fn generate_payment_qr(&self, invoice: &Invoice) -> Result<(), InvoicerError> {
+        let payload = Self::payment_qr_payload(invoice)?;
+        let image_file = Path::new(&self.invoice_dir()).join(invoice.qr_filename());
+
+        crate::payment_qr::generate_qr_image(&self.config.qr_generator(), &payload, &image_file)
+    }
+
+    pub fn generate_pdf(&self, tex_file: &impl FilePath) -> Result<(), InvoicerError> {
         use std::process::Command;
         if self.config.pdf_generator.as_ref().is_none() {
             return Ok(());
@@ -385,6 +1654,19 @@ impl Invoicer {
 }
 
 
+/// Number of pages of a PDF, counted by its page objects.
+/// Pages inside compressed object streams are not found.
+pub fn pdf_page_count(pdf: &[u8]) -> usize {
+    let mut count = 0;
+    for marker in [&b"/Type /Page"[..], &b"/Type/Page"[..]] {
+        count += pdf.windows(marker.len() + 1)
+            .filter(|window| window.starts_with(marker) && !window[marker.len()].is_ascii_alphabetic())
+            .count();
+    }
+    count
+}
+
+
 impl HasDirectories for Invoicer {
     fn config_dir(&self) -> PathBuf {
         self.config().directories.config_dir()
@@ -418,9 +1700,912 @@ impl Display for Invoicer {
         writeln!(f, "\t\tTags:\t{:?}", self.tag_dir())?;
         writeln!(f, "\t\tLocales:\t{:?}", self.locale_dir())?;
 
-        println!("worklog_tags: {:?}", self.worklog.tags());
-        println!("recipients: {:?}", self.recipients.iter().map(|r| r.name().clone()).collect::<Vec<String>>());
+        writeln!(f, "\tWorklog:\t{} records, {} hours", self.worklog.len(), self.worklog.total_hours())?;
+        let mut tags = self.worklog.tags().iter().collect::<Vec<_>>();
+        tags.sort();
+        for tag in tags {
+            let hours = self.worklog.from_records_matching_tag(tag, self.config.worklog().case_insensitive_tags()).total_hours();
+            writeln!(f, "\t\t{tag}:\t{hours} hours")?;
+        }
+        writeln!(f, "\tRecipients:\t{:?}", self.recipients.iter().map(|r| r.name().clone()).collect::<Vec<String>>())?;
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, InvoiceFingerprints, Invoicer, Ledger, OverwriteBehaviour};
+    use crate::helpers::FromTomlFile;
+    use crate::error::InvoicerError;
+    use crate::invoice::{Invoice, Recipient};
+    use crate::worklog::Worklog;
+
+    const VALID_CONFIG: &str = r#"
+[directories]
+config = "${WORKING_DIR}"
+locales = "${CONFIG_DIR}/locales"
+
+[contact]
+fullname = "John Doe"
+street = "123 Fake St."
+zipcode = 1234
+email = "john@doe.com"
+city = "Berlin"
+
+[payment]
+iban = "DE123456789012345678"
+bic = "MYBANKID"
+taxid = "12345678"
+tax_rate = 19.0
+
+[invoice]
+locale = "de"
+"#;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// Config writing into a temp dir named after the test, with `extra` added to the `[invoice]` section.
+    /// Returns the temp dir, the invoices are written to its `invoices` subdirectory.
+    fn temp_config(name: &str, extra: &str) -> (std::path::PathBuf, Config) {
+        let dir = std::env::temp_dir().join(format!("invoicer_{name}_{}", std::process::id()));
+        let locales = std::env::current_dir().unwrap().join("locales");
+        let toml = VALID_CONFIG
+            .replace(r#"config = "${WORKING_DIR}""#, &format!("config = {:?}", dir.to_string_lossy()))
+            .replace(r#"locales = "${CONFIG_DIR}/locales""#, &format!("locales = {:?}", locales.to_string_lossy()))
+            .replace("[invoice]", &format!("[invoice]\n{extra}"));
+        let mut config = config(&toml);
+        config.set_invoice_dir(dir.join("invoices"));
+        (dir, config)
+    }
+
+    fn recipient(name: &str) -> Recipient {
+        let mut recipient: Recipient = toml::from_str(r#"
+[contact]
+fullname = "Jane Doe"
+street = "Musterstraße 12"
+email = "jane@doe.com"
+zipcode = 1234
+city = "Berlin"
+
+[invoice]
+
+[tags]
+"#).unwrap();
+        recipient.set_name(name);
+        recipient
+    }
+
+    fn worklog() -> Worklog {
+        Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"","10/04/2023 14:00",1,"Review"
+"#.as_bytes()).unwrap()
+    }
+
+    fn issue_fields(config: &Config) -> Vec<String> {
+        config.validate().into_iter().map(|issue| issue.field).collect()
+    }
+
+    #[test]
+    fn config_errors() {
+        match Config::from_toml_file(std::path::Path::new("missing.toml")) {
+            Err(InvoicerError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            result => panic!("Unexpected result {result:?}"),
+        }
+        assert!(matches!(Config::from_toml_file(std::path::Path::new("locales/en.toml")), Err(InvoicerError::Toml(_))));
+    }
+
+    #[test]
+    fn validate_valid_config() {
+        assert!(config(VALID_CONFIG).validate().is_empty());
+    }
+
+    #[test]
+    fn ledger() {
+        let (dir, _) = temp_config("ledger", "");
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+
+        let generate = |date: crate::helpers::DateTime, hours: u32| {
+            let (_, config) = temp_config("ledger", "");
+            let mut invoicer = Invoicer::new(config, Some(date), None);
+            invoicer.append_worklog(&Worklog::from_csv(format!(r#""Tags","Start","Hours","Message"
+"ClientA","{}",{hours},"Review"
+"#, date.format("%m/%d/%Y 10:00")).as_bytes()).unwrap());
+            invoicer.set_rate(100.0);
+            invoicer.add_recipient(recipient("ClientA"));
+            invoicer.generate().unwrap();
+            invoicer
+        };
+
+        generate(date, 2);
+        let invoicer = generate(date + chrono::Duration::days(31), 1);
+
+        let ledger = Ledger::from_toml_file(invoicer.ledger_file()).unwrap();
+        let history = ledger.history("ClientA", false);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].0.date, "2023-10-20");
+        assert_eq!(history[0].0.currency, "EUR");
+        assert_eq!(history[0].0.total, 238.0);
+        assert_eq!(history[1].0.total, 119.0);
+        assert_eq!(history[1].1, 357.0);
+        assert!(ledger.history("ClientB", false).is_empty());
+
+        let mut out = Vec::new();
+        invoicer.print_ledger("ClientA", &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+
+        // A corrupt ledger is reported instead of being replaced by an empty one
+        std::fs::write(invoicer.ledger_file(), "invoices = [").unwrap();
+        assert!(matches!(invoicer.print_ledger("ClientA", &mut Vec::new()), Err(InvoicerError::Toml(_))));
+        assert!(matches!(invoicer.generate(), Err(InvoicerError::Toml(_))));
+        assert_eq!(std::fs::read_to_string(invoicer.ledger_file()).unwrap(), "invoices = [");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strict_run() {
+        let (dir, _) = temp_config("strict", "");
+
+        let generate = |strict: bool| {
+            let (_, config) = temp_config("strict", "");
+            let mut invoicer = Invoicer::new(config, None, None);
+            invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA","10/04/2023 14:00",1,"Review"
+"ClientB,nonbillable","10/04/2023 15:00",1,"Pro-bono"
+"#.as_bytes()).unwrap());
+            invoicer.set_rate(100.0);
+            invoicer.add_recipient(recipient("ClientA"));
+            invoicer.add_recipient(recipient("ClientB"));
+            invoicer.set_preview(true);
+            invoicer.set_strict(strict);
+            invoicer.generate()
+        };
+
+        // ClientB has no positions, which is only a warning by default
+        assert!(generate(false).is_ok());
+        match generate(true) {
+            Err(InvoicerError::SkippedInvoices(message)) => {
+                assert!(message.contains("ClientB: no positions"), "{message}");
+                assert!(!message.contains("ClientA"), "{message}");
+            }
+            result => panic!("Unexpected result {result:?}"),
+        }
+
+        // The invoice of ClientA is generated anyway
+        assert_eq!(std::fs::read_dir(dir.join("invoices")).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_payment_qr_writes_nothing() {
+        let (dir, config) = temp_config("payment_qr", "payment_qr = true");
+        let mut invoicer = Invoicer::new(config, None, None);
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Rate","Message"
+"ClientA","10/04/2023 14:00",1,100,"Review"
+"ClientB","10/04/2023 15:00",1,0,"Free review"
+"#.as_bytes()).unwrap());
+        invoicer.add_recipient(recipient("ClientA"));
+        invoicer.add_recipient(recipient("ClientB"));
+
+        // The amount of ClientB can not be paid by QR code, which fails the run before the invoice of ClientA is written
+        match invoicer.generate() {
+            Err(InvoicerError::PaymentQr(message)) => assert!(message.contains("ClientB"), "{message}"),
+            result => panic!("Unexpected result {result:?}"),
+        }
+        assert!(std::fs::read_dir(dir.join("invoices")).map_or(true, |mut entries| entries.next().is_none()));
+        assert!(!dir.join("fingerprints.toml").exists());
+        assert!(!dir.join("ledger.toml").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filename_collisions() {
+        let (dir, _) = temp_config("collisions", "filename_format = \"invoice.tex\"");
+
+        let generate = |overwrite: OverwriteBehaviour| {
+            let (_, mut config) = temp_config("collisions", "filename_format = \"invoice.tex\"");
+            config.set_overwrite(overwrite);
+            let mut invoicer = Invoicer::new(config, None, None);
+            invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA,ClientB","10/04/2023 14:00",1,"Review"
+"#.as_bytes()).unwrap());
+            invoicer.set_rate(100.0);
+            invoicer.add_recipient(recipient("ClientA"));
+            invoicer.add_recipient(recipient("ClientB"));
+            invoicer.generate().unwrap();
+        };
+        let files = || {
+            let mut files = std::fs::read_dir(dir.join("invoices")).unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        let number_of = |file: &str| std::fs::read_to_string(dir.join("invoices").join(file)).unwrap()
+            .lines().find_map(|line| line.strip_prefix("\\newcommand{\\invoicenumber}").map(String::from));
+
+        // Two invoices of the same run never overwrite each other
+        generate(OverwriteBehaviour::Force);
+        assert_eq!(files(), ["invoice.tex", "invoice_rev1.tex"]);
+        assert!(number_of("invoice.tex").is_some());
+        assert_ne!(number_of("invoice.tex"), number_of("invoice_rev1.tex"));
+
+        generate(OverwriteBehaviour::Skip);
+        assert_eq!(files(), ["invoice.tex", "invoice_rev1.tex"]);
+
+        generate(OverwriteBehaviour::RenameOld);
+        assert_eq!(files(), ["invoice.tex", "invoice_rev1.tex", "invoice_rev2.tex", "invoice_rev3.tex"]);
+
+        generate(OverwriteBehaviour::RenameNew);
+        assert_eq!(files().len(), 6);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn validate_promotions() {
+        let promotion = |name: &str, kind: &str, value: f32| format!("[[promotions]]\nname = \"{name}\"\nkind = \"{kind}\"\nvalue = {value}\n\n");
+        let config = config(&VALID_CONFIG.replace("[invoice]", &format!("{}{}{}[invoice]",
+            promotion("Spring", "percent", 10.0), promotion("Huge", "percent", 150.0), promotion("Spring", "absolute", 50.0))));
+        assert_eq!(issue_fields(&config), vec!["promotions.value", "promotions.name"]);
+    }
+
+    #[test]
+    fn validate_tax_rate() {
+        let config = config(&VALID_CONFIG.replace("tax_rate = 19.0", "tax_rate = 119.0"));
+        assert_eq!(issue_fields(&config), vec!["payment.tax_rate"]);
+    }
+
+    #[test]
+    fn validate_reports_all_issues() {
+        let config = config(&VALID_CONFIG
+            .replace(r#"iban = "DE123456789012345678""#, r#"iban = """#)
+            .replace(r#"taxid = "12345678""#, r#"taxid = " ""#)
+            .replace(r#"locale = "de""#, r#"locale = "xx""#)
+            .replace("[invoice]", "[invoice]\ntemplate = \"missing.tex\""));
+        assert_eq!(issue_fields(&config), vec!["payment.iban", "payment.taxid", "invoice.locale", "invoice.template"]);
+    }
+
+    #[test]
+    fn fingerprints_from_tex_files() {
+        let dir = std::env::temp_dir().join(format!("invoicer_fingerprints_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let tex = |fingerprint: &str, number: &str| format!("%$INVOICE_FINGERPRINT\n% {fingerprint}\n%$INVOICE_DETAILS\n\\newcommand{{\\invoicenumber}}{{{number}}}\n");
+        std::fs::write(dir.join("a.tex"), tex("AAAA", "20231001")).unwrap();
+        std::fs::write(dir.join("b.tex"), tex("BBBB", "20231002")).unwrap();
+        std::fs::write(dir.join("c.tex"), tex("CCCC", "20231002")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let (fingerprints, problems) = InvoiceFingerprints::from_tex_files(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(fingerprints.len(), 2);
+        assert_eq!(fingerprints.number_for_fingerprint("AAAA".to_string()), "20231001");
+        assert_eq!(fingerprints.number_for_fingerprint("BBBB".to_string()), "20231002");
+        assert!(!fingerprints.contains_fingerprint("CCCC".to_string()));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("Duplicate invoice number 20231002"));
+    }
+
+    #[test]
+    fn layered_config_files() {
+        let dir = std::env::temp_dir().join(format!("invoicer_layered_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let home = dir.join("home.toml");
+        let cwd = dir.join("cwd.toml");
+        let explicit = dir.join("explicit.toml");
+        std::fs::write(&home, VALID_CONFIG.replace("[invoice]", "pdf_generator = \"pdflatex\"\n\n[invoice]\ndays_for_payment = 30\ndate_format = \"%d.%m.%Y\"")).unwrap();
+        std::fs::write(&cwd, "pdf_generator = \"lualatex\"\n\n[invoice]\ndays_for_payment = 21\nlocale = \"en\"\n").unwrap();
+        std::fs::write(&explicit, "[invoice]\ndays_for_payment = 7\n").unwrap();
+
+        let config = Config::from_layered_toml_files(&[home.clone(), dir.join("missing.toml"), cwd.clone(), explicit.clone()]).unwrap();
+        assert_eq!(config.invoice().days_for_payment(), 7);
+        assert_eq!(config.invoice().locale_str(), "en");
+        assert_eq!(config.invoice().date_format(), "%d.%m.%Y");
+        assert_eq!(config.pdf_generator, Some("lualatex".to_string()));
+        assert_eq!(config.contact().fullname(), "John Doe");
+
+        // A file given twice does not override the files in between
+        let config = Config::from_layered_toml_files(&[home.clone(), cwd.clone(), home.clone()]).unwrap();
+        assert_eq!(config.invoice().days_for_payment(), 21);
+
+        match Config::from_toml_files(Some(dir.join("missing.toml"))) {
+            Err(InvoicerError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn effective_config() {
+        let toml = format!(r#"{VALID_CONFIG}
+[worklog]
+delimiter = ";"
+
+[[promotions]]
+name = "Spring"
+kind = "percent"
+value = 10
+expires = 2099-12-31
+
+[[recipient]]
+name = "ClientA"
+
+[recipient.contact]
+fullname = "Jane Doe"
+street = "Musterstraße 12"
+email = "jane@doe.com"
+zipcode = 1234
+city = "Berlin"
+
+[recipient.invoice]
+
+[recipient.tags]
+dev = "[default][order=1]Development"
+"#);
+        let effective = config(&toml).effective_toml().unwrap();
+
+        let reloaded = config(&effective);
+        assert!(reloaded.directories.config.as_ref().is_some_and(|dir| std::path::Path::new(dir).is_absolute() && !dir.contains("${")));
+        assert_eq!(reloaded.directories.locales, Some(format!("{}/locales", reloaded.directories.config.clone().unwrap())));
+        assert_eq!(reloaded.invoice().locale_str(), "de");
+        assert!(effective.contains("date_format = \"%Y/%m/%d\""));
+        assert!(effective.contains("number_scope = \"monthly\""));
+        assert_eq!(reloaded.payment[0].fallback_rate(), 100.0);
+        assert_eq!(reloaded.worklog().delimiter(), ';');
+        assert_eq!(reloaded.qr_generator, Some("qrencode".to_string()));
+        assert_eq!(reloaded.promotion("Spring").map(|promotion| promotion.value()), Some(10.0));
+
+        let recipients = reloaded.recipients();
+        assert!(recipients[0].tags()["dev"].is_default());
+        assert_eq!(recipients[0].tags()["dev"].order(), Some(1));
+        // Recipient settings are not filled in, since they would override the config
+        assert_eq!(effective.matches("date_format").count(), 1);
+
+        assert_eq!(reloaded.effective_toml().unwrap(), effective);
+    }
+
+    #[test]
+    fn inline_recipients() {
+        let inline = |name: &str| format!(r#"
+[[recipient]]
+name = "{name}"
+default_rate = 80
+
+[recipient.contact]
+fullname = "Jane Doe"
+street = "Musterstraße 12"
+email = "jane@doe.com"
+zipcode = 1234
+city = "Berlin"
+
+[recipient.invoice]
+
+[recipient.tags]
+"#);
+        let config = config(&format!("{VALID_CONFIG}{}{}", inline("ClientA"), inline("ClientB")));
+        let recipients = config.recipients();
+        assert_eq!(recipients.iter().map(|r| r.name().as_str()).collect::<Vec<_>>(), ["ClientA", "ClientB"]);
+        assert_eq!(recipients[1].default_rate(), Some(80.0));
+
+        let mut invoicer = Invoicer::new(config, None, None);
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientB","10/04/2023 14:00",1,"Review"
+"#.as_bytes()).unwrap());
+        invoicer.add_recipients_from_worklog();
+        assert_eq!(invoicer.recipients.iter().map(|r| r.name().as_str()).collect::<Vec<_>>(), ["ClientB"]);
+    }
+
+    #[test]
+    fn new_recipient() {
+        let dir = std::env::temp_dir().join(format!("invoicer_new_recipient_{}", std::process::id()));
+        let mut config = config(VALID_CONFIG);
+        config.set_tag_dir(dir.clone());
+        let invoicer = Invoicer::new(config, None, None);
+
+        let file = invoicer.new_recipient("ClientA", false).unwrap();
+        assert_eq!(file, dir.join("ClientA.toml"));
+        let recipient = Recipient::from_tag(&"ClientA".to_string(), &dir).unwrap();
+        assert_eq!(recipient.name(), "ClientA");
+        assert!(recipient.tags().contains_key("ClientA"));
+
+        // Existing files are only overwritten with force
+        std::fs::write(&file, "# edited").unwrap();
+        assert!(invoicer.new_recipient("ClientA", false).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "# edited");
+        assert!(invoicer.new_recipient("ClientA", true).is_ok());
+        assert!(Recipient::from_tag(&"ClientA".to_string(), &dir).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invoice_dir_not_writable() {
+        let file = std::env::temp_dir().join(format!("invoicer_not_a_dir_{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+
+        let mut not_a_dir = config(VALID_CONFIG);
+        not_a_dir.set_invoice_dir(file.join("${YEAR}"));
+        let invoicer = Invoicer::new(not_a_dir, None, None);
+        let result = invoicer.check_invoice_dir();
+        std::fs::remove_file(&file).unwrap();
+
+        match result {
+            Err(InvoicerError::DirectoryNotWritable { setting, path, .. }) => {
+                assert!(setting.ends_with("${YEAR}"));
+                assert_eq!(path, file.join(invoicer.date().format("%Y").to_string()));
+            }
+            result => panic!("Unexpected result {result:?}"),
+        }
+
+        let dir = std::env::temp_dir().join(format!("invoicer_writable_{}", std::process::id()));
+        let mut writable = config(VALID_CONFIG);
+        writable.set_invoice_dir(dir.clone());
+        assert!(Invoicer::new(writable, None, None).check_invoice_dir().is_ok());
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn logo() {
+        assert!(config(VALID_CONFIG).logo().is_none());
+
+        let config = config(&format!("logo = \"${{CONFIG_DIR}}/logo.pdf\"\n{VALID_CONFIG}"));
+        assert_eq!(config.logo().unwrap(), std::env::current_dir().unwrap().join("logo.pdf"));
+    }
+
+    #[test]
+    fn page_count() {
+        let pdf = b"%PDF-1.4\n1 0 obj << /Type /Pages /Kids [2 0 R 3 0 R] /Count 2 >> endobj\n2 0 obj << /Type /Page /Parent 1 0 R >> endobj\n3 0 obj <</Type/Page/Parent 1 0 R>> endobj\n";
+        assert_eq!(super::pdf_page_count(pdf), 2);
+        assert_eq!(super::pdf_page_count(b""), 0);
+    }
+
+    #[test]
+    fn combine_without_pdfs() {
+        let invoicer = Invoicer::new(config(VALID_CONFIG), None, None);
+        let missing = [std::path::PathBuf::from("does_not_exist.pdf")];
+        assert!(matches!(invoicer.combine_pdfs(&missing, std::path::Path::new("combined.pdf")), Err(InvoicerError::CombinePdf(_))));
+    }
+
+    #[test]
+    fn exchange_rate() {
+        let config = config(&format!("{VALID_CONFIG}\n[exchange_rates]\nUSD = 0.92\n"));
+        let eur = crate::locale::Currency::from_str("EUR".to_string());
+        let usd = crate::locale::Currency::from_str("USD".to_string());
+        let chf = crate::locale::Currency::from_str("CHF".to_string());
+
+        assert_eq!(config.exchange_rate(&eur, &eur), Some(1.0));
+        assert_eq!(config.exchange_rate(&usd, &eur), Some(0.92));
+        assert_eq!(config.exchange_rate(&chf, &eur), None);
+    }
+
+    #[test]
+    fn number_collision() {
+        let config = config(&VALID_CONFIG.replace("[invoice]", "[invoice]\nnumber_format = \"%Y%m\"\ntimesheet = false"));
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+        let invoicer = Invoicer::new(config, Some(date), None);
+        let worklog = worklog();
+
+        let mut invoices = Vec::new();
+        let mut counter = 1;
+        for name in ["ClientA", "ClientB"] {
+            let mut invoice = Invoice::new(&invoicer, recipient(name));
+            invoice.add_worklog(&worklog);
+            counter = invoice.generate_number(counter, None);
+            invoices.push(invoice);
+        }
+
+        assert_eq!(invoices[0].number(), "202310");
+        match Invoicer::check_unique_numbers(&invoices) {
+            Err(InvoicerError::DuplicateInvoiceNumbers(numbers)) => assert_eq!(numbers, "202310 (ClientA, ClientB)"),
+            result => panic!("Unexpected result {result:?}"),
+        }
+
+        invoices.pop();
+        assert!(Invoicer::check_unique_numbers(&invoices).is_ok());
+    }
+
+    #[test]
+    fn print_first_invoice() {
+        let config = config(&VALID_CONFIG.replace("[invoice]", "[invoice]\nnumber_format = \"%Y%m${COUNTER}\"\ntimesheet = false"));
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+        let mut invoicer = Invoicer::new(config, Some(date), Some(7));
+
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA,ClientB","10/04/2023 14:00",1,"Review"
+"#.as_bytes()).unwrap());
+        invoicer.add_recipient(recipient("ClientA"));
+
+        let mut output = Vec::new();
+        invoicer.print(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("\\newcommand{\\invoicenumber}{20231007}"), "{output}");
+        assert!(output.contains("Review"));
+
+        invoicer.add_recipient(recipient("ClientB"));
+        let mut output = Vec::new();
+        invoicer.print(&mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("\\newcommand{\\invoicenumber}{20231007}"));
+    }
+
+    #[test]
+    fn print_invoice_with_timesheet() {
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), Some(date), Some(7));
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA","10/04/2023 14:00",1,"Review"
+"#.as_bytes()).unwrap());
+        invoicer.add_recipient(recipient("ClientA"));
+
+        // Only the document is written, diagnostics like the timesheet size go to stderr
+        let mut output = Vec::new();
+        invoicer.print(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Review"), "{output}");
+        assert!(!output.contains("Generated timesheet"), "{output}");
+    }
+
+    #[test]
+    fn only_selected_recipients() {
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), None, None);
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA,ClientB,ClientC","10/04/2023 14:00",1,"Review"
+"#.as_bytes()).unwrap());
+        for name in ["ClientA", "ClientB", "ClientC"] {
+            invoicer.add_recipient(recipient(name));
+        }
+
+        invoicer.set_only(vec!["ClientC".to_string(), "ClientA".to_string()]);
+        let invoices = invoicer.create_invoices(&InvoiceFingerprints::default()).unwrap();
+        assert_eq!(invoices.iter().map(|invoice| invoice.recipient().name().as_str()).collect::<Vec<_>>(), ["ClientA", "ClientC"]);
+
+        invoicer.set_only(vec!["ClientA".to_string(), "ClientD".to_string()]);
+        match invoicer.create_invoices(&InvoiceFingerprints::default()) {
+            Err(InvoicerError::UnknownRecipients(names)) => assert_eq!(names, "ClientD"),
+            result => panic!("Unexpected result {:?}", result.map(|invoices| invoices.len())),
+        }
+    }
+
+    #[test]
+    fn missing_recipient_locale() {
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), None, None);
+        invoicer.append_worklog(&worklog());
+        invoicer.add_recipient(recipient("ClientA"));
+        let mut misspelled: Recipient = toml::from_str(r#"
+[contact]
+fullname = "Jane Doe"
+street = "Musterstraße 12"
+email = "jane@doe.com"
+zipcode = 1234
+city = "Berlin"
+
+[invoice]
+locale = "xx"
+
+[tags]
+"#).unwrap();
+        misspelled.set_name("ClientB");
+        invoicer.add_recipient(misspelled);
+        assert!(invoicer.check_recipient_locales().is_err());
+
+        match invoicer.create_invoices(&InvoiceFingerprints::default()) {
+            Err(InvoicerError::InvalidConfig(message)) => {
+                assert!(message.contains("ClientB") && message.contains("xx.toml"), "{message}");
+                assert!(!message.contains("ClientA"), "{message}");
+            }
+            result => panic!("Unexpected result {:?}", result.map(|invoices| invoices.len())),
+        }
+
+        invoicer.set_only(vec!["ClientA".to_string()]);
+        assert!(invoicer.check_recipient_locales().is_ok());
+    }
+
+    #[test]
+    fn unloadable_recipient_locale() {
+        let dir = std::env::temp_dir().join(format!("invoicer_unloadable_locale_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.toml"), "currency = ").unwrap();
+        std::fs::write(dir.join("xx.toml"), "fallback = \"yy\"").unwrap();
+        std::fs::write(dir.join("yy.toml"), "fallback = \"xx\"").unwrap();
+        std::fs::write(dir.join("zz.toml"), "fallback = 1").unwrap();
+
+        let toml = VALID_CONFIG.replace(r#"locales = "${CONFIG_DIR}/locales""#, &format!("locales = {:?}", dir.to_string_lossy()));
+        let mut invoicer = Invoicer::new(config(&toml), None, None);
+        invoicer.append_worklog(&worklog());
+        let mut broken: Recipient = toml::from_str(r#"
+[contact]
+fullname = "Jane Doe"
+street = "Musterstraße 12"
+email = "jane@doe.com"
+zipcode = 1234
+city = "Berlin"
+
+[invoice]
+locale = "broken"
+
+[tags]
+"#).unwrap();
+        broken.set_name("ClientA");
+        invoicer.add_recipient(broken);
+
+        // The invalid TOML is reported instead of panicking while generating
+        match invoicer.create_invoices(&InvoiceFingerprints::default()) {
+            Err(InvoicerError::InvalidConfig(message)) => assert!(message.contains("ClientA") && message.contains("can not be loaded"), "{message}"),
+            result => panic!("Unexpected result {:?}", result.map(|invoices| invoices.len())),
+        }
+
+        // So are a cyclic fallback chain and a fallback which is no string
+        for (locale, error) in [("xx", "Cyclic locale fallback"), ("zz", "must be a string")] {
+            let mut invoicer = Invoicer::new(config(&toml.replace(r#"locale = "de""#, &format!("locale = {locale:?}"))), None, None);
+            invoicer.append_worklog(&worklog());
+            invoicer.add_recipient(recipient("ClientA"));
+            match invoicer.create_invoices(&InvoiceFingerprints::default()) {
+                Err(InvoicerError::InvalidConfig(message)) => assert!(message.contains(error), "{message}"),
+                result => panic!("Unexpected result {:?}", result.map(|invoices| invoices.len())),
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn worklogs_from_dir() {
+        let dir = std::env::temp_dir().join(format!("invoicer_worklog_dir_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("2023-10-05.csv"), "\"Tags\",\"Start\",\"Hours\",\"Message\"\n\"ClientA\",\"10/05/2023 09:00\",3,\"Setup\"\n").unwrap();
+        std::fs::write(dir.join("2023-10-04.csv"), "\"Tags\",\"Start\",\"Hours\",\"Message\"\n\"ClientA\",\"10/04/2023 09:00\",2,\"Review\"\n\"ClientB\",\"10/04/2023 14:00\",1,\"Call\"\n").unwrap();
+        std::fs::write(dir.join("2023-10-06.csv"), "\"Tags\",\"Start\",\"Hours\",\"Message\"\n\"ClientA\",\"tomorrow\",\"two\",\"Broken\"\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a worklog").unwrap();
+
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), None, None);
+        assert_eq!(invoicer.append_worklogs_from_dir(&dir).unwrap(), 2);
+        let messages = invoicer.worklog.records().iter().map(|record| record.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(messages, ["Review", "Call", "Setup"]);
+        assert!(invoicer.worklog.tags().contains("ClientB"));
+
+        assert!(invoicer.append_worklogs_from_dir(&dir.join("missing")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn estimate() {
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), None, None);
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA","10/04/2023 14:00",2,"Review"
+"ClientA:0.5,ClientB:0.5","10/05/2023 09:00",3,"Meeting"
+"ClientB,nonbillable","10/07/2023 10:00",1,"Pro-bono"
+"Internal,nonbillable","10/07/2023 11:00",1,"Admin"
+"ClientB,Support","10/08/2023 10:00",1,"Hotline"
+"#.as_bytes()).unwrap());
+
+        // No recipients are needed, each tag is a pseudo-recipient
+        let estimate = invoicer.estimate(100.0);
+        assert_eq!(estimate.recipients.keys().collect::<Vec<_>>(), ["ClientA", "ClientB", "Support"]);
+        assert_eq!(estimate.recipients["ClientA"], super::EstimateTotals { hours: 3.5, net: 350.0, tax: 66.5, gross: 416.5 });
+        assert_eq!(estimate.recipients["ClientB"], super::EstimateTotals { hours: 2.5, net: 250.0, tax: 47.5, gross: 297.5 });
+
+        // Records with several tags count once in the total
+        assert_eq!(estimate.total, super::EstimateTotals { hours: 6.0, net: 600.0, tax: 114.0, gross: 714.0 });
+
+        let output = estimate.to_string();
+        assert!(output.contains("ClientA: 3.5 hours, net 350.00"), "{output}");
+        assert!(output.contains("Total: 6 hours, net 600.00"), "{output}");
+        assert!(output.contains("gross 714.00"), "{output}");
+
+        // Several taxes are each rounded like on an invoice, 5.00 + 9.98 instead of 14.975% of 100.10
+        let toml = VALID_CONFIG.replace("tax_rate = 19.0", r#"taxes = [{ name = "GST", rate = 5.0 }, { name = "QST", rate = 9.975 }]"#);
+        let mut invoicer = Invoicer::new(config(&toml), None, None);
+        invoicer.append_worklog(&Worklog::from_csv("Tags,Start,Hours,Message\nClientA,10/04/2023 14:00,1,Review\n".as_bytes()).unwrap());
+        let estimate = invoicer.estimate(100.1);
+        assert_eq!(estimate.total.tax, 14.98);
+        assert_eq!(estimate.total.gross, 115.08);
+    }
+
+    #[test]
+    fn missing_template_inputs() {
+        let dir = std::env::temp_dir().join(format!("invoicer_template_inputs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("custom.tex"), "\\input{common}\n\\input{nonexistent}\n%$INVOICE_POSITIONS\n").unwrap();
+
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), None, None);
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA","10/04/2023 14:00",2,"Review"
+"#.as_bytes()).unwrap());
+        invoicer.add_recipient(recipient("ClientA"));
+        invoicer.set_template_path(vec![dir.clone()]);
+        invoicer.set_strict_template(true);
+
+        // The embedded templates include only existing files
+        let invoices = invoicer.create_invoices(&InvoiceFingerprints::default()).unwrap();
+        assert!(invoicer.check_templates(&invoices).is_ok());
+        drop(invoices);
+
+        invoicer.set_template("custom.tex");
+        let invoices = invoicer.create_invoices(&InvoiceFingerprints::default()).unwrap();
+        match invoicer.check_templates(&invoices) {
+            Err(InvoicerError::InvalidConfig(message)) => {
+                assert!(message.contains("custom.tex") && message.contains("nonexistent.tex"), "{message}");
+                assert!(!message.contains("common.tex"), "{message}");
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn output_dir() {
+        use super::HasDirectories;
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+
+        let mut config = config(&VALID_CONFIG.replace("[directories]", "[directories]\ninvoices = \"${HOME}/invoices/${YEAR}\""));
+        config.set_invoice_dir(std::path::PathBuf::from("out"));
+        assert_eq!(Invoicer::new(config.clone(), Some(date), None).invoice_dir(), std::path::PathBuf::from("out"));
+
+        config.set_invoice_dir(std::path::PathBuf::from("out/${YEAR}"));
+        assert_eq!(Invoicer::new(config, Some(date), None).invoice_dir(), std::path::PathBuf::from("out/2023"));
+    }
+
+    #[test]
+    fn locale_override() {
+        let mut invoicer = Invoicer::new(config(&VALID_CONFIG.replace("locale = \"de\"", "locale = \"en\"")), None, None);
+        let mut recipient: Recipient = toml::from_str(r#"
+[contact]
+fullname = "Jane Doe"
+street = "Musterstraße 12"
+email = "jane@doe.com"
+zipcode = 1234
+city = "Berlin"
+
+[invoice]
+locale = "de"
+
+[tags]
+"#).unwrap();
+        recipient.set_name("ClientA");
+        assert_eq!(Invoice::new(&invoicer, recipient.clone()).locale().name(), "de");
+
+        // --locale takes precedence over the recipient and the config
+        invoicer.set_locale("en").unwrap();
+        assert_eq!(Invoice::new(&invoicer, recipient).locale().name(), "en");
+
+        match invoicer.set_locale("xx") {
+            Err(InvoicerError::InvalidConfig(message)) => assert!(message.contains("xx.toml")),
+            result => panic!("Unexpected result {result:?}"),
+        }
+        assert_eq!(invoicer.locale(), Some(&"en".to_string()));
+    }
+
+    #[test]
+    fn number_scope() {
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+        let numbers = |scope: &str, fingerprints: &InvoiceFingerprints| {
+            let number_format = if scope == "per_recipient" { "${RECIPIENT}-%Y-${COUNTER}" } else { "%Y%m${COUNTER}" };
+            let config = config(&VALID_CONFIG.replace("[invoice]", &format!("[invoice]\nnumber_scope = \"{scope}\"\nnumber_format = \"{number_format}\"\ntimesheet = false")));
+            assert!(config.validate().is_empty());
+            let mut invoicer = Invoicer::new(config, Some(date), None);
+            invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA,ClientB","10/04/2023 14:00",1,"Review"
+"#.as_bytes()).unwrap());
+            invoicer.add_recipient(recipient("ClientA"));
+            invoicer.add_recipient(recipient("ClientB"));
+
+            let invoices = invoicer.create_invoices(fingerprints).unwrap();
+            let mut fingerprints = InvoiceFingerprints::default();
+            invoices.iter().for_each(|invoice| fingerprints.add(invoice));
+            (invoices.iter().map(|invoice| invoice.number()).collect::<Vec<_>>(), fingerprints)
+        };
+
+        let mut fingerprints = InvoiceFingerprints::default();
+        fingerprints.set_counter("2023-10", 3);
+        fingerprints.set_counter("2023-09", 7);
+        assert_eq!(numbers("monthly", &fingerprints).0, ["20231004", "20231005"]);
+
+        fingerprints.set_counter("global", 41);
+        let (global, added) = numbers("global", &fingerprints);
+        assert_eq!(global, ["20231042", "20231043"]);
+        assert_eq!(added.counter("global"), Some(43));
+
+        fingerprints.set_counter("recipient ClientB", 2);
+        assert_eq!(numbers("per_recipient", &fingerprints).0, ["ClientA-2023-01", "ClientB-2023-03"]);
+        assert_eq!(numbers("yearly", &fingerprints).0, ["20231001", "20231002"]);
+
+        // Counters are stored in a table next to the fingerprints
+        let toml = toml::to_string(&added).unwrap();
+        assert!(toml.contains("[counters]\nglobal = 43"));
+        let read: InvoiceFingerprints = toml::from_str(&toml).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.counter("global"), Some(43));
+
+        let config = config(&VALID_CONFIG.replace("[invoice]", "[invoice]\nnumber_scope = \"per_recipient\""));
+        assert_eq!(issue_fields(&config), ["invoice.number_format"]);
+    }
+
+    #[test]
+    fn numbers_in_same_month() {
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+        let invoicer = Invoicer::new(config(&VALID_CONFIG.replace("[invoice]", "[invoice]\ntimesheet = false")), Some(date), None);
+
+        // A fingerprint file of an older version without counters
+        let mut fingerprints = InvoiceFingerprints::default();
+        fingerprints.insert("ABC".to_string(), "20231001".to_string());
+        fingerprints.insert("DEF".to_string(), "20231002".to_string());
+
+        let mut first = Invoice::new(&invoicer, recipient("ClientA"));
+        first.add_worklog(&worklog());
+        assert_eq!(first.generate_number(1, Some(&fingerprints)), 4);
+        assert_eq!(first.number(), "20231003");
+        fingerprints.add(&first);
+
+        // Second invoice for the same recipient in the same month, e.g. for another worklog
+        let mut second = Invoice::new(&invoicer, recipient("ClientA"));
+        second.add_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"","10/18/2023 09:00",2,"Meeting"
+"#.as_bytes()).unwrap());
+        assert_eq!(second.generate_number(fingerprints.counter("2023-10").unwrap() + 1, Some(&fingerprints)), 5);
+        assert_eq!(second.number(), "20231004");
+        fingerprints.add(&second);
+
+        // An explicit counter like `--counter 1` is skipped as well if its number is used
+        let mut third = Invoice::new(&invoicer, recipient("ClientB"));
+        third.add_worklog(&worklog());
+        third.generate_number(1, Some(&fingerprints));
+        assert_eq!(third.number(), "20231005");
+    }
+
+    #[test]
+    fn reconcile() {
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), None, None);
+        invoicer.append_worklog(&Worklog::from_csv(r#""Tags","Start","Hours","Message"
+"ClientA","10/04/2023 14:00",2,"Review"
+"ClientA:0.5,ClientB:0.5","10/05/2023 09:00",3,"Meeting"
+"Internal","10/06/2023 10:00",1.5,"Admin"
+"ClientA,nonbillable","10/07/2023 10:00",1,"Pro-bono"
+"#.as_bytes()).unwrap());
+        invoicer.add_recipient(recipient("ClientA"));
+        invoicer.add_recipient(recipient("ClientB"));
+
+        let invoices = invoicer.create_invoices(&InvoiceFingerprints::default()).unwrap();
+        let reconciliation = invoicer.reconcile(&invoices);
+        assert_eq!(reconciliation.worklog_hours, 6.5);
+        assert_eq!(reconciliation.nonbillable_hours, 1.0);
+        assert_eq!(reconciliation.billed_hours, 5.0);
+        assert_eq!(reconciliation.delta, 1.5);
+        assert_eq!(reconciliation.unmatched_hours, 1.5);
+        assert_eq!(reconciliation.unmatched_records, ["10/06/2023 10:00: Admin (Internal)"]);
+        assert_eq!(reconciliation.recipients.get("ClientA"), Some(&3.5));
+        assert_eq!(reconciliation.recipients.get("ClientB"), Some(&1.5));
+        assert!(!reconciliation.is_balanced());
+
+        let report = toml::to_string(&reconciliation).unwrap();
+        assert!(report.contains("delta = 1.5"));
+        assert!(report.contains("[recipients]"));
+    }
+
+    #[test]
+    fn generate_empty() {
+        let date = crate::helpers::DateTime::parse_from_str("2023-10-20 00:00", "%Y-%m-%d %H:%M").unwrap();
+
+        let mut invoicer = Invoicer::new(config(VALID_CONFIG), Some(date), None);
+        invoicer.append_worklog(&worklog());
+        invoicer.add_recipient(recipient("ClientA"));
+        assert!(invoicer.create_invoices(&InvoiceFingerprints::default()).unwrap().is_empty());
+
+        let mut invoicer = Invoicer::new(config(&VALID_CONFIG.replace("[invoice]", "[invoice]\ngenerate_empty = true")), Some(date), None);
+        invoicer.append_worklog(&worklog());
+        invoicer.add_recipient(recipient("ClientA"));
+        let invoices = invoicer.create_invoices(&InvoiceFingerprints::default()).unwrap();
+        assert_eq!(invoices.len(), 1);
+        assert!(invoices[0].positions().is_empty());
+        assert_eq!(invoices[0].begin_date(), date);
+        assert_eq!(invoices[0].end_date(), date);
+    }
+}
@@ -1,6 +1,9 @@
+pub mod error;
 pub mod generate_tex;
 pub mod helpers;
 pub mod invoice;
 pub mod invoicer;
 pub mod locale;
+pub mod payment_qr;
+pub mod schema;
 pub mod worklog;
\ No newline at end of file
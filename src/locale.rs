@@ -1,9 +1,10 @@
 
+use chrono::Datelike;
 use common_macros::hash_map;
 use lazy_static::lazy_static;
 
 use std::collections::HashMap;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use struct_iterable::Iterable;
 
 lazy_static! {
@@ -15,8 +16,11 @@ lazy_static! {
     };
 }
 
+/// Translation keys of the month names.
+const MONTHS: [&str; 12] = ["january", "february", "march", "april", "may", "june", "july", "august", "september", "october", "november", "december"];
 
-#[derive(Clone, Deserialize)]
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Currency(String);
 
 
@@ -29,8 +33,14 @@ impl Currency {
         &self.0
     }
     
+    /// Symbol of a known currency like `€`, otherwise the currency code, e.g. `CZK`.
     pub fn symbol(&self) -> String {
-        CURRENCIES.get(self.0.as_str()).unwrap_or(&"€").to_string()
+        CURRENCIES.get(self.0.as_str()).map(|symbol| symbol.to_string()).unwrap_or(self.0.clone())
+    }
+
+    /// Symbol given by the user, e.g. `Kč`, otherwise the symbol of the currency.
+    pub fn symbol_or(&self, symbol_override: Option<&String>) -> String {
+        symbol_override.cloned().unwrap_or(self.symbol())
     }
 }
 
@@ -59,6 +69,86 @@ impl Default for Currency {
 }
 
 
+/// Rounding of amounts to the displayed precision.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rounding {
+    /// Round half away from zero, usual in accounting
+    HalfUp,
+    /// Round half to even, also called banker's rounding.
+    /// Ties are those of the printed number, e.g. `2.675` becomes `2.68`,
+    /// while Rust's `{:.2}` rounds the binary value slightly below it to `2.67`.
+    #[default]
+    HalfEven,
+    /// Round towards negative infinity
+    Floor,
+    /// Round towards positive infinity
+    Ceil,
+}
+
+impl Rounding {
+    /// Round a decimal number string like `-2.125` to `precision` fraction digits.
+    /// The result always has `precision` fraction digits.
+    /// Strings which are not decimal numbers are returned unchanged.
+    pub fn round_str(&self, s: &str, precision: usize) -> String {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+        if int_part.is_empty() || !int_part.chars().chain(frac_part.chars()).all(|c| c.is_ascii_digit()) {
+            return s.to_string();
+        }
+
+        let (kept, rest) = frac_part.split_at(precision.min(frac_part.len()));
+        let mut rest = rest.bytes();
+        let first = rest.next().unwrap_or(b'0');
+        let rest_nonzero = rest.any(|b| b != b'0');
+        let is_odd = (kept.bytes().last().or(int_part.bytes().last()).unwrap() - b'0') % 2 == 1;
+
+        let round_away = match self {
+            Rounding::HalfUp => first >= b'5',
+            Rounding::HalfEven => first > b'5' || (first == b'5' && (rest_nonzero || is_odd)),
+            Rounding::Floor => negative && (first != b'0' || rest_nonzero),
+            Rounding::Ceil => !negative && (first != b'0' || rest_nonzero),
+        };
+
+        let mut number: Vec<u8> = int_part.bytes().chain(kept.bytes()).chain(std::iter::repeat_n(b'0', precision - kept.len())).collect();
+        if round_away {
+            let mut i = number.len();
+            loop {
+                if i == 0 {
+                    number.insert(0, b'1');
+                    break;
+                }
+                i -= 1;
+                if number[i] == b'9' {
+                    number[i] = b'0';
+                } else {
+                    number[i] += 1;
+                    break;
+                }
+            }
+        }
+
+        let (int_digits, frac_digits) = number.split_at(number.len() - precision);
+        let mut result = String::from_utf8_lossy(int_digits).to_string();
+        if precision > 0 {
+            result = result + "." + &String::from_utf8_lossy(frac_digits);
+        }
+        if negative && number.iter().any(|&b| b != b'0') {
+            result.insert(0, '-');
+        }
+        result
+    }
+
+    /// Round a number to `precision` fraction digits, based on its shortest decimal representation.
+    pub fn round(&self, value: f32, precision: usize) -> f32 {
+        self.round_str(&value.to_string(), precision).parse().unwrap_or(value)
+    }
+}
+
+
 #[derive(Debug, Clone, Deserialize, Iterable)]
 
 pub struct Locale {
@@ -68,7 +158,37 @@ pub struct Locale {
     separator: String,
     pattern: String,
     currency: Currency,
-    translations: HashMap<String, String>
+    /// Symbol of the currency, overrides the symbols known to invoicer
+    currency_symbol: Option<String>,
+    translations: HashMap<String, String>,
+    #[serde(default)]
+    units: HashMap<String, String>,
+    #[serde(skip)]
+    rounding: Rounding,
+    /// Name of a locale consulted for missing keys, loaded from the same directory
+    #[serde(default)]
+    fallback: Option<String>,
+    /// Lines of an address with contact fields like `${ZIPCODE} ${CITY}`
+    #[serde(default = "default_address_format")]
+    address_format: Vec<String>,
+    /// Separator between the lines of an address
+    #[serde(default = "default_address_separator")]
+    address_separator: String,
+    /// Separator between the begin and end date of a period
+    #[serde(default = "default_period_separator")]
+    period_separator: String,
+}
+
+fn default_address_format() -> Vec<String> {
+    ["${COMPANYNAME}", "${FULLNAME}", "${STREET}", "${ZIPCODE} ${CITY}", "${COUNTRY}"].map(String::from).to_vec()
+}
+
+fn default_address_separator() -> String {
+    "\\\\".to_string()
+}
+
+fn default_period_separator() -> String {
+    " -- ".to_string()
 }
 
 impl Default for Locale {
@@ -79,7 +199,14 @@ impl Default for Locale {
             separator: ",".to_string(),
             pattern: "#!".to_string(),
             currency: Currency::default(),
-            translations: HashMap::new()
+            currency_symbol: None,
+            translations: HashMap::new(),
+            units: HashMap::new(),
+            rounding: Rounding::default(),
+            fallback: None,
+            address_format: default_address_format(),
+            address_separator: default_address_separator(),
+            period_separator: default_period_separator(),
         }
     }
 }
@@ -93,20 +220,149 @@ impl Locale {
         &self.currency
     }
 
+    /// Show amounts in another currency than the one of the locale file, e.g. the currency of an invoice.
+    pub fn set_currency(&mut self, currency: Currency, currency_symbol: Option<String>) {
+        self.currency = currency;
+        self.currency_symbol = currency_symbol;
+    }
+
+    /// Configured `currency_symbol` or the symbol of the currency.
+    pub fn currency_symbol(&self) -> String {
+        self.currency.symbol_or(self.currency_symbol.as_ref())
+    }
+
+    /// Display label of a unit code like `h`, the code itself if the locale has no label for it.
+    pub fn unit<'a>(&'a self, unit: &'a str) -> &'a str {
+        self.units.get(unit).map(|label| label.as_str()).unwrap_or(unit)
+    }
+
+    pub fn decimal(&self) -> &String {
+        &self.decimal
+    }
+
+    pub fn separator(&self) -> &String {
+        &self.separator
+    }
+
+    pub fn translations(&self) -> &HashMap<String, String> {
+        &self.translations
+    }
+
+    /// Sorted translation keys of `reference` which this locale lacks.
+    pub fn missing_translations<'a>(&self, reference: &'a Locale) -> Vec<&'a String> {
+        let mut missing = reference.translations.keys()
+            .filter(|key| !self.translations.contains_key(*key))
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing
+    }
+
+    /// Lines of an address, see `Contact::address_block`.
+    pub fn address_format(&self) -> &[String] {
+        &self.address_format
+    }
+
+    pub fn address_separator(&self) -> &str {
+        &self.address_separator
+    }
+
+    pub fn period_separator(&self) -> &str {
+        &self.period_separator
+    }
+
+    pub fn fallback(&self) -> Option<&String> {
+        self.fallback.as_ref()
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+
+    /// Set the rounding used when formatting numbers.
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
     pub fn tr(&self, s: String) -> &String {
         self.translations.get(&s).unwrap()
     } 
 
+    /// Translation for a key, `None` if the locale does not define it.
+    pub fn translation(&self, key: &str) -> Option<&String> {
+        self.translations.get(key)
+    }
+
+    /// Plural form of a translation for `count`, with `${COUNT}` replaced by the count.
+    /// The form `<key>.one` is used for a count of 1 and `<key>.other` otherwise,
+    /// falling back to `<key>.other` and then to `<key>` if a form is not defined.
+    pub fn tr_plural(&self, key: &str, count: u32) -> Option<String> {
+        let one = (count == 1).then(|| self.translation(&format!("{key}.one"))).flatten();
+        one.or(self.translation(&format!("{key}.other")))
+            .or(self.translation(key))
+            .map(|translation| translation.replace("${COUNT}", &count.to_string()))
+    }
+
+    /// Translated name of a month from 1 to 12, e.g. the `march` translation for 3.
+    pub fn month_name(&self, month: u32) -> Option<&String> {
+        MONTHS.get(month.wrapping_sub(1) as usize).and_then(|key| self.translation(key))
+    }
+
+    /// Replace the month name tokens `%B` (full name) and `%b` (first three letters) of a date format with the translated month name.
+    /// Without a translation, the tokens are kept for chrono's English names.
+    pub fn translate_month(&self, format_str: &str, month: u32) -> String {
+        let name = match self.month_name(month) {
+            Some(name) => name,
+            None => return format_str.to_string(),
+        };
+
+        let mut result = String::new();
+        let mut chars = format_str.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('B') => result += name,
+                Some('b') => result.extend(name.chars().take(3)),
+                Some(next) => {
+                    result.push(c);
+                    result.push(next);
+                }
+                None => result.push(c),
+            }
+        }
+        result
+    }
+
+    /// Format a date with a chrono format string, using translated month names.
+    pub fn format_date(&self, date: DateTime, format_str: &str) -> String {
+        helpers::date_to_str(date, &self.translate_month(format_str, date.month()))
+    }
+
     pub fn format_number<T: std::fmt::Display>(&self, number: T, precision: usize) -> String {
-        let s = format!("{number:.precision$}")
-            .replace(".", &self.decimal);
-        
-        let mut fs = String::new();
-        for (i, c) in s.chars().rev().enumerate() {
-            if i % 3 == 0 && (i > 2 + self.decimal.len()) {
-                fs = self.separator.clone() + &fs;
+        let plain = format!("{number}");
+        // Integer types ignore the precision and are neither rounded nor grouped
+        if format!("{number:.1}") == format!("{number:.2}") {
+            return plain;
+        }
+
+        let s = self.rounding.round_str(&plain, precision);
+        let (sign, s) = match s.strip_prefix('-') {
+            Some(s) => ("-", s),
+            None => ("", s.as_str()),
+        };
+        let (int_part, frac_part) = s.split_once('.').unwrap_or((s, ""));
+
+        let mut fs = String::from(sign);
+        for (i, c) in int_part.chars().enumerate() {
+            if i > 0 && (int_part.len() - i) % 3 == 0 {
+                fs += &self.separator;
             }
-            fs = c.to_string() + &fs;
+            fs.push(c);
+        }
+        if !frac_part.is_empty() {
+            fs = fs + &self.decimal + frac_part;
         }
         fs
     }
@@ -114,27 +370,80 @@ impl Locale {
     pub fn format_amount<T: std::fmt::Display>(&self, number: T) -> String {
         self.pattern
             .replace('#', self.format_number(number, 2).as_str())
-            .replace('!', self.currency.symbol().as_str())
+            .replace('!', self.currency_symbol().as_str())
     }
 }
 
 
-use crate::{generate_tex::{GenerateTex, generate_tex_command}, helpers::{FromTomlFile, self, FilePath}};
+use crate::{generate_tex::{GenerateTex, generate_tex_command}, helpers::{FromTomlFile, self, FilePath, DateTime}, error::InvoicerError};
 
 impl GenerateTex for Locale {
     fn generate_tex<'a>(&self, w: &'a mut dyn std::io::Write) -> std::io::Result<()> {
+        // Plural forms like `days.one` become `\trdaysone`, since TeX command names consist of letters only
         for (name, translation) in &self.translations {
-            generate_tex_command(w, format!("tr{}", name).as_str(), translation)?;
+            generate_tex_command(w, format!("tr{}", name.replace('.', "")).as_str(), translation)?;
         }
         Ok(())
     }
 }
 
+/// Decimal mark, thousands separator and amount pattern of common locale identifiers like `de` or `de-CH`,
+/// used for the fields a locale file omits. Unknown identifiers get the English defaults.
+pub fn standard_number_format(identifier: &str) -> (&'static str, &'static str, &'static str) {
+    let identifier = identifier.replace('_', "-").to_lowercase();
+    let language = identifier.split('-').next().unwrap_or_default();
+    match (identifier.as_str(), language) {
+        ("de-ch", _) | ("fr-ch", _) | ("it-ch", _) => (".", "'", "! #"),
+        ("en-in", _) => (".", ",", "!#"),
+        (_, "de") | (_, "it") | (_, "es") | (_, "pt") | (_, "da") | (_, "id") | (_, "tr") => (",", ".", "# !"),
+        (_, "nl") => (",", ".", "! #"),
+        (_, "fr") | (_, "sv") | (_, "fi") | (_, "nb") | (_, "no") | (_, "pl") | (_, "cs") | (_, "ru") => (",", " ", "# !"),
+        (_, "en") | (_, "ja") | (_, "zh") | (_, "ko") => (".", ",", "!#"),
+        _ => (".", ",", "#!"),
+    }
+}
+
+/// Read a locale file as TOML table, merged with its fallback chain.
+/// Keys of the primary locale win, `translations` and `units` are merged key by key.
+fn locale_table(path: &std::path::Path, visited: &mut Vec<std::path::PathBuf>) -> Result<toml::Table, InvoicerError> {
+    if visited.iter().any(|p| p == path) {
+        return Err(InvoicerError::InvalidConfig(format!("Cyclic locale fallback: {}", path.display())));
+    }
+    visited.push(path.to_path_buf());
+
+    let mut table: toml::Table = helpers::from_toml_file(path)?;
+    let fallback = match table.get("fallback") {
+        Some(toml::Value::String(fallback)) => fallback.clone(),
+        Some(_) => return Err(InvoicerError::InvalidConfig(format!("Locale fallback in {} must be a string", path.display()))),
+        None => return Ok(table),
+    };
+
+    let mut fallback_table = locale_table(&path.with_file_name(format!("{fallback}.toml")), visited)?;
+    for section in ["translations", "units"] {
+        if let (Some(toml::Value::Table(primary)), Some(toml::Value::Table(secondary))) = (table.get(section), fallback_table.get(section)) {
+            let mut merged = secondary.clone();
+            merged.extend(primary.clone());
+            table.insert(section.to_string(), toml::Value::Table(merged));
+        }
+    }
+    fallback_table.extend(table);
+    Ok(fallback_table)
+}
+
 impl FromTomlFile for Locale {
-    fn from_toml_file<P: FilePath>(filename: P)  -> Result<Self, Box<dyn std::error::Error>> {
+    fn from_toml_file<P: FilePath>(filename: P)  -> Result<Self, InvoicerError> {
         let name = filename.to_string();
-        let mut locale: Locale = helpers::from_toml_file(filename)?;
-        locale.name = helpers::name_from_file::<std::path::PathBuf>(name.into());
+        let mut table = locale_table(std::path::Path::new(&filename), &mut Vec::new())?;
+        let identifier = helpers::name_from_file::<std::path::PathBuf>(name.into());
+
+        // Explicit fields override the defaults of the locale identifier
+        let (decimal, separator, pattern) = standard_number_format(&identifier);
+        for (key, value) in [("decimal", decimal), ("separator", separator), ("pattern", pattern)] {
+            table.entry(key).or_insert(toml::Value::String(value.to_string()));
+        }
+
+        let mut locale: Locale = table.try_into()?;
+        locale.name = identifier;
         
         Ok(locale)
     }
@@ -144,7 +453,7 @@ impl FromTomlFile for Locale {
 #[cfg(test)]
 mod tests {
     use crate::{helpers::FromTomlFile, generate_tex::GenerateTex};
-    use super::Locale;
+    use super::{Locale, Rounding};
 
     #[test]
     fn load_toml_and_generate_tex() {
@@ -158,6 +467,32 @@ mod tests {
         assert!(locale.generate_tex(&mut std::io::sink()).is_ok());
     }
 
+    #[test]
+    fn tr_plural() {
+        let locale: Locale = toml::from_str(r##"
+decimal = "."
+separator = ","
+pattern = "#!"
+currency = "EUR"
+
+[translations]
+"days.one" = "${COUNT} day"
+"days.other" = "${COUNT} days"
+"hours.other" = "hours"
+weeks = "weeks"
+"##).unwrap();
+        assert_eq!(locale.tr_plural("days", 1), Some("1 day".to_string()));
+        assert_eq!(locale.tr_plural("days", 2), Some("2 days".to_string()));
+        assert_eq!(locale.tr_plural("days", 0), Some("0 days".to_string()));
+        assert_eq!(locale.tr_plural("hours", 1), Some("hours".to_string()));
+        assert_eq!(locale.tr_plural("weeks", 1), Some("weeks".to_string()));
+        assert_eq!(locale.tr_plural("months", 2), None);
+
+        let mut tex = Vec::new();
+        locale.generate_tex(&mut tex).unwrap();
+        assert!(String::from_utf8(tex).unwrap().contains("\\newcommand{\\trdaysone}{${COUNT} day}"));
+    }
+
     #[test]
     fn format() {
         let locale = Locale::from_toml_file(std::path::Path::new("locales/en.toml")).unwrap();
@@ -165,5 +500,141 @@ mod tests {
         assert_eq!(locale.format_amount(1234.943_f32), "1,234.94€");
         assert_eq!(locale.format_amount(1234.00_f32), "1,234.00€");
         assert_eq!(locale.format_amount(1234_i32), "1234€"); // TODO: Handle int types differently?
+        assert_eq!(locale.format_number(1234567.5_f64, 0), "1,234,568");
+        assert_eq!(locale.format_number(-1234.5_f32, 1), "-1,234.5");
+        assert_eq!(locale.format_number(123.456_f32, 3), "123.456");
+    }
+
+    #[test]
+    fn currency_symbol() {
+        use super::Currency;
+        assert_eq!(Currency::from_str("USD".to_string()).symbol(), "$");
+        assert_eq!(Currency::from_str("CZK".to_string()).symbol(), "CZK");
+
+        let mut locale: Locale = toml::from_str(r##"
+decimal = ","
+separator = " "
+pattern = "# !"
+currency = "CZK"
+
+[translations]
+"##).unwrap();
+        assert_eq!(locale.format_amount(1234.5_f32), "1 234,50 CZK");
+
+        locale.currency_symbol = Some("Kč".to_string());
+        assert_eq!(locale.currency_symbol(), "Kč");
+        assert_eq!(locale.format_amount(1234.5_f32), "1 234,50 Kč");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn units() {
+        let locale = Locale::from_toml_file(std::path::Path::new("locales/de.toml")).unwrap();
+        assert_eq!(locale.unit("h"), "Std.");
+        assert_eq!(locale.unit("km"), "km");
+        assert_eq!(Locale::default().unit("h"), "h");
+    }
+
+    #[test]
+    fn rounding() {
+        let mut locale = Locale::from_toml_file(std::path::Path::new("locales/en.toml")).unwrap();
+        assert_eq!(locale.format_number(2.125_f32, 2), "2.12");
+
+        locale.set_rounding(Rounding::HalfUp);
+        assert_eq!(locale.format_number(2.125_f32, 2), "2.13");
+        assert_eq!(locale.format_number(-2.125_f32, 2), "-2.13");
+        assert_eq!(locale.format_number(1.005_f32, 2), "1.01");
+        assert_eq!(locale.format_amount(999.995_f32), "1,000.00€");
+
+        assert_eq!(Rounding::HalfEven.round_str("2.135", 2), "2.14");
+
+        // Ties of the printed number are rounded to even, unlike `{:.2}` rounding the binary value
+        locale.set_rounding(Rounding::HalfEven);
+        assert_eq!(format!("{:.2}", 2.675_f32), "2.67");
+        assert_eq!(locale.format_number(2.675_f32, 2), "2.68");
+        assert_eq!(locale.format_number(2.665_f32, 2), "2.66");
+        assert_eq!(Rounding::HalfEven.round_str("2.665", 2), "2.66");
+        assert_eq!(Rounding::HalfEven.round_str("2.6651", 2), "2.67");
+        assert_eq!(Rounding::Floor.round_str("2.129", 2), "2.12");
+        assert_eq!(Rounding::Floor.round_str("-2.121", 2), "-2.13");
+        assert_eq!(Rounding::Ceil.round_str("2.121", 2), "2.13");
+        assert_eq!(Rounding::Ceil.round_str("-0.001", 2), "0.00");
+        assert_eq!(Rounding::HalfUp.round_str("7", 2), "7.00");
+        assert_eq!(Rounding::HalfUp.round(2.125, 2), 2.13);
+    }
+
+    #[test]
+    fn standard_number_format() {
+        let dir = std::env::temp_dir().join(format!("invoicer_locale_defaults_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("de.toml"), "currency = \"EUR\"\n\n[translations]\n").unwrap();
+        std::fs::write(dir.join("de-CH.toml"), "currency = \"CHF\"\npattern = \"#!\"\n\n[translations]\n").unwrap();
+        std::fs::write(dir.join("xx.toml"), "currency = \"EUR\"\n\n[translations]\n").unwrap();
+
+        let de = Locale::from_toml_file(dir.join("de.toml"));
+        let de_ch = Locale::from_toml_file(dir.join("de-CH.toml"));
+        let unknown = Locale::from_toml_file(dir.join("xx.toml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let de = de.unwrap();
+        assert_eq!(de.decimal(), ",");
+        assert_eq!(de.separator(), ".");
+        assert_eq!(de.format_amount(1234.5), "1.234,50 €");
+
+        // Explicit fields override the defaults
+        let de_ch = de_ch.unwrap();
+        assert_eq!(de_ch.separator(), "'");
+        assert_eq!(de_ch.format_amount(1234.5), "1'234.50CHF");
+
+        let unknown = unknown.unwrap();
+        assert_eq!(unknown.decimal(), ".");
+        assert_eq!(super::standard_number_format("en_US"), (".", ",", "!#"));
+    }
+
+    #[test]
+    fn fallback() {
+        let dir = std::env::temp_dir().join(format!("invoicer_locale_fallback_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("locales/en.toml", dir.join("en.toml")).unwrap();
+        std::fs::write(dir.join("partial.toml"), "fallback = \"en\"\n\n[translations]\ninvoice = \"Facture\"\n").unwrap();
+        std::fs::write(dir.join("cyclic.toml"), "fallback = \"cyclic\"\n").unwrap();
+
+        let locale = Locale::from_toml_file(dir.join("partial.toml"));
+        let cyclic = Locale::from_toml_file(dir.join("cyclic.toml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let locale = locale.unwrap();
+        assert_eq!(locale.name(), "partial");
+        assert_eq!(locale.fallback().unwrap(), "en");
+        assert_eq!(locale.tr("invoice".to_string()), "Facture");
+        assert_eq!(locale.tr("subtotal".to_string()), "Sub total");
+        assert_eq!(locale.format_amount(1234.5_f32), "1,234.50€");
+
+        let mut tex = Vec::new();
+        locale.generate_tex(&mut tex).unwrap();
+        let tex = String::from_utf8(tex).unwrap();
+        assert!(tex.contains("\\trinvoice}{Facture}"));
+        assert!(tex.contains("\\trsubtotal}"));
+
+        assert!(cyclic.is_err());
+    }
+
+    #[test]
+    fn month_names() {
+        let de = Locale::from_toml_file(std::path::Path::new("locales/de.toml")).unwrap();
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 3, 3).unwrap().and_hms_opt(0, 0, 0).unwrap();
+
+        assert_eq!(de.month_name(3).unwrap(), "März");
+        assert_eq!(de.format_date(date, "%d. %B %Y"), "03. März 2024");
+        assert_eq!(de.format_date(date, "%Y-%b-%d"), "2024-Mär-03");
+        assert_eq!(de.format_date(date, "100%%B"), "100%B");
+        assert_eq!(Locale::default().format_date(date, "%B"), "March");
+    }
+
+    #[test]
+    fn missing_translations() {
+        let en = Locale::from_toml_file(std::path::Path::new("locales/en.toml")).unwrap();
+        let de = Locale::from_toml_file(std::path::Path::new("locales/de.toml")).unwrap();
+        assert!(de.missing_translations(&en).is_empty());
+        assert_eq!(Locale::default().missing_translations(&en).len(), en.translations().len());
+    }
+}
@@ -0,0 +1,129 @@
+//! SEPA payment QR codes according to EPC069-12 ("GiroCode").
+
+use std::path::Path;
+
+use crate::error::InvoicerError;
+
+/// Maximum length of the beneficiary name.
+const MAX_NAME_LENGTH: usize = 70;
+
+/// Maximum length of the unstructured remittance information.
+const MAX_REFERENCE_LENGTH: usize = 140;
+
+/// Format an amount per SEPA rules: dot as decimal separator, two decimals, no grouping.
+pub fn sepa_amount(amount: f32) -> String {
+    format!("{amount:.2}")
+}
+
+fn invalid_input(message: String) -> InvoicerError {
+    InvoicerError::PaymentQr(message)
+}
+
+/// Data of a SEPA credit transfer encoded in an EPC QR code.
+#[derive(Debug, Clone)]
+pub struct EpcPayment {
+    pub bic: String,
+    pub name: String,
+    pub iban: String,
+    pub currency: String,
+    pub amount: f32,
+    pub reference: String,
+}
+
+impl EpcPayment {
+    /// Generate the EPC QR payload, version 002 with UTF-8 encoding.
+    pub fn payload(&self) -> Result<String, InvoicerError> {
+        if self.currency != "EUR" {
+            return Err(invalid_input(format!("EPC QR codes only support EUR, not {}", self.currency)));
+        }
+        if !(0.01..=999_999_999.99).contains(&self.amount) {
+            return Err(invalid_input(format!("Amount {} is out of range for an EPC QR code", sepa_amount(self.amount))));
+        }
+        if self.name.is_empty() || self.name.chars().count() > MAX_NAME_LENGTH {
+            return Err(invalid_input(format!("Beneficiary name '{}' must have 1 to {MAX_NAME_LENGTH} characters", self.name)));
+        }
+        if self.reference.chars().count() > MAX_REFERENCE_LENGTH {
+            return Err(invalid_input(format!("Reference '{}' must not exceed {MAX_REFERENCE_LENGTH} characters", self.reference)));
+        }
+
+        let iban = self.iban.replace(' ', "");
+        if iban.is_empty() {
+            return Err(invalid_input("IBAN is missing".to_string()));
+        }
+
+        Ok([
+            "BCD",
+            "002",
+            "1",
+            "SCT",
+            self.bic.trim(),
+            self.name.trim(),
+            &iban,
+            &format!("EUR{}", sepa_amount(self.amount)),
+            "",
+            "",
+            self.reference.trim(),
+        ].join("\n"))
+    }
+}
+
+/// Render a QR code for the payload into a PNG image with an external command, e.g. `qrencode`.
+pub fn generate_qr_image(generator: &str, payload: &str, image_file: &Path) -> Result<(), InvoicerError> {
+    use std::process::Command;
+
+    let output = Command::new(generator)
+        .args(["-t", "PNG", "-o"])
+        .arg(image_file)
+        .arg(payload)
+        .output()
+        .map_err(|e| invalid_input(format!("Failed to execute QR generator {generator:?}: {e}")))?;
+
+    if !output.status.success() {
+        return Err(invalid_input(format!("QR generator {generator:?} failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{EpcPayment, sepa_amount};
+
+    fn payment(amount: f32) -> EpcPayment {
+        EpcPayment {
+            bic: "MYBANKID".to_string(),
+            name: "John Doe".to_string(),
+            iban: "DE12 3456 7890 1234 5678".to_string(),
+            currency: "EUR".to_string(),
+            amount,
+            reference: "20231001".to_string(),
+        }
+    }
+
+    #[test]
+    fn amount_format() {
+        assert_eq!(sepa_amount(1234.5), "1234.50");
+        assert_eq!(sepa_amount(19.999), "20.00");
+        assert_eq!(sepa_amount(100000.0), "100000.00");
+    }
+
+    #[test]
+    fn epc_payload() {
+        assert_eq!(payment(1190.0).payload().unwrap(),
+            "BCD\n002\n1\nSCT\nMYBANKID\nJohn Doe\nDE123456789012345678\nEUR1190.00\n\n\n20231001");
+    }
+
+    #[test]
+    fn epc_payload_invalid() {
+        assert!(payment(0.0).payload().is_err());
+
+        let mut usd = payment(100.0);
+        usd.currency = "USD".to_string();
+        assert!(usd.payload().is_err());
+
+        let mut long_name = payment(100.0);
+        long_name.name = "x".repeat(71);
+        assert!(long_name.payload().is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//! Fully populated example TOML files, printed by `invoicer schema`.
+//!
+//! Each field is listed with a comment describing it and its default, followed by an example value,
+//! so the output can be used as a starting point and parses as the file it describes.
+
+/// Kind of TOML file an example is printed for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaKind {
+    /// The main `invoicer.toml`
+    Config,
+    /// A recipient TOML in the tag directory
+    Recipient,
+    /// A locale TOML in the locale directory
+    Locale,
+}
+
+/// Field of a table: key, example value and description including the default.
+type Field = (&'static str, &'static str, &'static str);
+
+/// Tables of a TOML file, the header being empty for the top-level keys.
+type Table = (&'static str, &'static [Field]);
+
+const CONFIG: &[Field] = &[
+    ("pdf_generator", "\"pdflatex\"", "Command generating the PDF from a tex file, no PDFs are generated if unset"),
+    ("qr_generator", "\"qrencode\"", "Command generating the payment QR code image, default: qrencode"),
+    ("pdf_combiner", "\"pdfunite\"", "Command combining the PDFs of a run with --combine, default: pdfunite"),
+    ("logo", "\"${CONFIG_DIR}/logo.png\"", "Logo image shown on the invoice, no logo if unset"),
+    ("overwrite", "\"RenameOld\"", "What happens with an existing tex file: Force, RenameOld, RenameNew or Skip, default: RenameOld"),
+];
+
+const DIRECTORIES: &[Field] = &[
+    ("config", "\"${HOME}/.invoicer\"", "Config directory, default: ${HOME}/.invoicer"),
+    ("tags", "\"${CONFIG_DIR}/tags\"", "Directory of the recipient TOMLs, default: ${CONFIG_DIR}/tags"),
+    ("templates", "\"${CONFIG_DIR}/templates\"", "Template directory, default: ${CONFIG_DIR}/templates"),
+    ("template_path", "[\"${CONFIG_DIR}/shared\"]", "Directories searched for templates before the template directory, default: []"),
+    ("invoices", "\"${HOME}/invoices\"", "Directory the invoices are written to, default: ${HOME}/Documents/invoices/${YEAR}"),
+    ("locales", "\"${CONFIG_DIR}/locales\"", "Directory of the locale TOMLs, default: ${CONFIG_DIR}/locales"),
+];
+
+const CONTACT: &[Field] = &[
+    ("companyname", "\"ACME Ltd.\"", "Company name, optional"),
+    ("fullname", "\"Jane Doe\"", "Full name, required"),
+    ("street", "\"Main Street 1\"", "Street and house number, required"),
+    ("zipcode", "12345", "Zip code, required"),
+    ("city", "\"Berlin\"", "City, required"),
+    ("country", "\"Germany\"", "Country, optional"),
+    ("phone", "\"+49 30 1234567\"", "Phone number, optional"),
+    ("fax", "\"+49 30 1234568\"", "Fax number, optional"),
+    ("email", "\"jane@example.com\"", "Email address, required"),
+    ("website", "\"https://example.com\"", "Website, optional"),
+];
+
+const PAYMENT: &[Field] = &[
+    ("accountholder", "\"Jane Doe\"", "Account holder, default: the full name of the contact"),
+    ("iban", "\"DE02120300000000202051\"", "IBAN, required"),
+    ("bic", "\"BYLADEM1001\"", "BIC, required"),
+    ("taxid", "\"DE123456789\"", "Tax ID, required"),
+    ("currency", "\"EUR\"", "Currency of the account, default: EUR"),
+    ("currency_symbol", "\"€\"", "Symbol of the currency, default: the symbol known to invoicer"),
+    ("tax_rate", "19.0", "Value-added tax rate in percent, used if no taxes are given"),
+    ("taxes", "[{ name = \"VAT\", rate = 19.0 }]", "Taxes applied to the net total, each with an optional name and a rate in percent"),
+    ("default_rate", "100.0", "Hourly rate of recipients without a rate"),
+    ("fallback_rate", "80.0", "Hourly rate used if neither the record, the recipient nor the default rate is set"),
+    ("require_explicit_rate", "false", "Fail instead of using the fallback rate, default: false"),
+    ("rounding", "\"half_even\"", "Rounding of amounts: half_up, half_even, floor or ceil, default: half_even"),
+    ("round_positions", "true", "Round each position before summing up, default: true"),
+];
+
+const INVOICE: &[Field] = &[
+    ("locale", "\"en\"", "Locale of the invoice, default: en"),
+    ("template", "\"invoice.tex\"", "Invoice template, default: invoice.tex"),
+    ("date_format", "\"%Y/%m/%d\"", "Format of dates, default: %Y/%m/%d"),
+    ("number_format", "\"%Y%m${COUNTER}\"", "Format of the invoice number, default: %Y%m${COUNTER}"),
+    ("number_scope", "\"monthly\"", "Scope of the counter: global, yearly, monthly or per_recipient, default: monthly"),
+    ("filename_format", "\"${INVOICENUMBER}_${INVOICE}_${RECIPIENT}.tex\"", "Format of the tex file name, default: ${INVOICENUMBER}_${INVOICE}_${RECIPIENT}.tex"),
+    ("ascii_filenames", "false", "Transliterate file names to ASCII, default: false"),
+    ("days_for_payment", "14", "Days until the invoice is due, default: 14"),
+    ("calculate_value_added_tax", "true", "Add taxes to the invoice, default: true"),
+    ("timesheet", "true", "Generate a timesheet with the invoice, default: true"),
+    ("timesheet_template", "\"timesheet.tex\"", "Separate timesheet template, default: the timesheet is part of the invoice"),
+    ("timesheet_columns", "[\"start\", \"hours\", \"message\"]", "Columns of the timesheet: start, end, hours, message, tags or total, default: [\"start\", \"hours\", \"message\"]"),
+    ("timesheet_nonbillable", "false", "List non-billable records in the timesheet, default: false"),
+    ("payment_qr", "false", "Add a payment QR code, default: false"),
+    ("generate_empty", "false", "Generate invoices without positions as drafts, default: false"),
+    ("rate_precision", "2", "Decimal digits of rates, default: 2"),
+    ("amount_precision", "2", "Decimal digits of amounts, default: 2"),
+    ("position_text_max_len", "80", "Maximum length of position texts before they are shortened, default: unlimited"),
+    ("position_hour_rounding", "0.25", "Round the hours of each position up to this increment, default: no rounding"),
+    ("subject", "\"${PERIOD}\"", "Subject of the invoice, default: ${PERIOD}"),
+    ("group_untagged_by", "\"message\"", "Grouping of records without a tag: message, day or none, default: message"),
+    ("position_sort", "\"tag\"", "Order of positions: tag, alphabetical, amount_desc or amount_asc, default: tag"),
+    ("positions_from", "\"tags\"", "Positions per tag or one per record: tags or chronological, default: tags"),
+];
+
+const WORKLOG: &[Field] = &[
+    ("delimiter", "\",\"", "Field delimiter of CSV worklogs, default: ,"),
+    ("quote", "'\"'", "Quote character of CSV worklogs, default: \""),
+    ("comment", "\"#\"", "Lines starting with this character are skipped, empty to turn comments off, default: #"),
+    ("flexible", "false", "Allow records with a varying number of fields, default: false"),
+    ("case_insensitive_tags", "false", "Match tags regardless of their case, default: false"),
+    ("decimal", "\".\"", "Decimal separator of the Hours and Rate columns, default: guessed for each number"),
+];
+
+const COLUMNS: &[Field] = &[
+    ("tags", "\"Tags\"", "Header of the tag column, default: Tags"),
+    ("start", "\"Start\"", "Header of the start column, default: Start"),
+    ("hours", "\"Hours\"", "Header of the hours column, default: Hours"),
+    ("rate", "\"Rate\"", "Header of the rate column, default: Rate"),
+    ("message", "\"Message\"", "Header of the message column, default: Message"),
+];
+
+const LEGAL: &[Field] = &[
+    ("register", "\"Amtsgericht Berlin HRB 12345\"", "Free keys, each available as \\legal<key> in templates, letters only"),
+];
+
+const EXCHANGE_RATES: &[Field] = &[
+    ("USD", "1.08", "Units of a currency per unit of the report currency"),
+];
+
+const PROMOTIONS: &[Field] = &[
+    ("name", "\"Early bird\"", "Text of the discount position, required"),
+    ("kind", "\"percent\"", "Kind of discount: percent or absolute, required"),
+    ("value", "10.0", "Discount in percent or as absolute amount, required"),
+    ("expires", "2026-12-31", "Last day the promotion applies, default: never expires"),
+];
+
+const RECIPIENT: &[Field] = &[
+    ("default_rate", "100.0", "Hourly rate of the recipient, default: the default rate of the payment account"),
+    ("deposit", "500.0", "Deposit subtracted from the total, default: none"),
+    ("cap", "5000.0", "Maximum net total, default: no cap"),
+    ("currency", "\"EUR\"", "Currency of the invoices, default: the currency of the payment account"),
+    ("payment_terms", "\"Payable immediately\"", "Payment terms replacing the translated terms, default: none"),
+    ("tax_exempt", "\"Reverse charge\"", "Reason of a tax exemption, no taxes are added if set"),
+    ("po_number", "\"4500012345\"", "Purchase order number shown on the invoices and in the payment QR reference, default: none"),
+    ("attachments", "[\"terms.pdf\"]", "Files attached to the combined invoice PDF, default: []"),
+];
+
+const RATE_TIERS: &[Field] = &[
+    ("from_hours", "40.0", "Hours from which the rate applies, required"),
+    ("rate", "90.0", "Hourly rate of the tier, required"),
+];
+
+const FEES: &[Field] = &[
+    ("text", "\"Management fee\"", "Text of the fee position, required"),
+    ("percent", "5.0", "Fee in percent of the positions above, required"),
+];
+
+const CUSTOM: &[Field] = &[
+    ("project", "\"Website\"", "Free keys, each available as \\recipient<key> in templates, letters only"),
+];
+
+const TAGS: &[Field] = &[
+    ("Development", "\"[default][order=1]Software development\"", "Tag of worklog records and the text of its position, optionally annotated with [default], [template=<file>] and [order=<n>]"),
+];
+
+const LOCALE: &[Field] = &[
+    ("currency", "\"EUR\"", "Currency of the locale, required"),
+    ("currency_symbol", "\"€\"", "Symbol of the currency, default: the symbol known to invoicer"),
+    ("decimal", "\".\"", "Decimal separator, default: inferred from the locale name, e.g. , for de"),
+    ("separator", "\",\"", "Thousands separator, default: inferred from the locale name, e.g. . for de"),
+    ("pattern", "\"#!\"", "Position of the number (#) and currency symbol (!), default: inferred from the locale name, e.g. # ! for de"),
+    ("fallback", "\"en\"", "Locale consulted for missing translations, default: none"),
+    ("address_format", "[\"${COMPANYNAME}\", \"${FULLNAME}\", \"${STREET}\", \"${ZIPCODE} ${CITY}\", \"${COUNTRY}\"]", "Lines of an address, default: company name, full name, street, zip code and city, country"),
+    ("address_separator", "\"\\\\\\\\\"", "Separator between the lines of an address, default: \\\\"),
+    ("period_separator", "\" -- \"", "Separator between the begin and end date of a period, default: \" -- \""),
+];
+
+const CONFIG_TABLES: &[Table] = &[
+    ("", CONFIG),
+    ("[directories]", DIRECTORIES),
+    ("[contact]", CONTACT),
+    ("[[payment]]", PAYMENT),
+    ("[invoice]", INVOICE),
+    ("[worklog]", WORKLOG),
+    ("[worklog.columns]", COLUMNS),
+    ("[legal]", LEGAL),
+    ("[exchange_rates]", EXCHANGE_RATES),
+    ("[[promotions]]", PROMOTIONS),
+];
+
+const RECIPIENT_TABLES: &[Table] = &[
+    ("", RECIPIENT),
+    ("[[rate_tiers]]", RATE_TIERS),
+    ("[[fees]]", FEES),
+    ("[contact]", CONTACT),
+    ("[invoice]", INVOICE),
+    ("[custom]", CUSTOM),
+    ("[tags]", TAGS),
+];
+
+const LOCALE_TABLES: &[Table] = &[
+    ("", LOCALE),
+];
+
+/// Translations and units of the English locale, appended to the locale example.
+const EN_LOCALE: &str = include_str!("../locales/en.toml");
+
+impl SchemaKind {
+    fn tables(&self) -> &'static [Table] {
+        match self {
+            SchemaKind::Config => CONFIG_TABLES,
+            SchemaKind::Recipient => RECIPIENT_TABLES,
+            SchemaKind::Locale => LOCALE_TABLES,
+        }
+    }
+
+    fn header(&self) -> &'static str {
+        match self {
+            SchemaKind::Config => "# Example invoicer.toml\n\
+                # Recipients can also be given inline as [[recipient]] tables with a name key and the fields of `invoicer schema recipient`.\n",
+            SchemaKind::Recipient => "# Example recipient TOML, named <TAG>.toml in the tag directory\n",
+            SchemaKind::Locale => "# Example locale TOML, named <LOCALE>.toml in the locale directory\n",
+        }
+    }
+
+    /// Commented example TOML with all fields set.
+    pub fn example_toml(&self) -> String {
+        let mut toml = format!("{}\n", self.header());
+        for (header, fields) in self.tables() {
+            if !header.is_empty() {
+                toml += &format!("\n{header}\n");
+            }
+            for (key, example, doc) in fields.iter() {
+                toml += &format!("# {doc}\n{key} = {example}\n");
+            }
+        }
+        if *self == SchemaKind::Locale {
+            toml += "\n# Translated texts, each available as \\tr<key> in templates, without dots\n";
+            toml += &EN_LOCALE[EN_LOCALE.find("[translations]").unwrap_or(EN_LOCALE.len())..];
+        }
+        toml
+    }
+}
+
+/// Recipient TOML for the tag `name`, written by `invoicer new-recipient <name>`.
+/// Only required fields and the tag are set, all others are commented out, as are arrays of tables like `[[fees]]`.
+pub fn recipient_template(name: &str) -> String {
+    let mut toml = format!("# Recipient '{}', worklog records with one of the tags below are invoiced to it.\n", name.replace(['\n', '\r'], " "));
+    let tag = toml_key(name);
+    for (header, fields) in RECIPIENT_TABLES {
+        let array = header.starts_with("[[");
+        if !header.is_empty() {
+            toml += &format!("\n{}{header}\n", if array { "# " } else { "" });
+        }
+        for (key, example, doc) in fields.iter() {
+            let (key, set) = match *header {
+                "[tags]" => (tag.as_str(), true),
+                _ => (*key, !array && doc.ends_with("required")),
+            };
+            toml += &format!("# {doc}\n{}{key} = {example}\n", if set { "" } else { "# " });
+        }
+    }
+    toml
+}
+
+/// A TOML key, quoted and escaped unless it is a bare key like `ClientA`.
+fn toml_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recipient_template, SchemaKind};
+    use crate::invoice::Recipient;
+    use crate::invoicer::Config;
+    use crate::locale::Locale;
+    use struct_iterable::Iterable;
+
+    /// Key paths of the leaves of `value`, descending into the given tables and arrays of tables.
+    fn key_paths(value: &toml::Value, prefix: &str, tables: &[String], paths: &mut Vec<String>) {
+        let Some(table) = value.as_table() else { return };
+        for (key, value) in table {
+            let path = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+            match value {
+                toml::Value::Table(_) if tables.contains(&path) => key_paths(value, &path, tables, paths),
+                toml::Value::Array(values) if tables.contains(&path) => values.iter().for_each(|value| key_paths(value, &path, tables, paths)),
+                _ => paths.push(path),
+            }
+        }
+    }
+
+    /// Every serialized field of `value` is documented and every documented field is read back.
+    fn assert_documented<T: serde::Serialize>(kind: SchemaKind, value: &T, free_tables: &[&str]) {
+        let tables: Vec<String> = kind.tables().iter()
+            .map(|(header, _)| header.trim_matches(|c| c == '[' || c == ']').to_string())
+            .collect();
+        let mut documented: Vec<String> = kind.tables().iter()
+            .filter(|(header, _)| !free_tables.iter().any(|free| header.contains(free)))
+            .flat_map(|(header, fields)| {
+                let table = header.trim_matches(|c| c == '[' || c == ']').to_string();
+                fields.iter().map(move |(key, _, _)| if table.is_empty() { key.to_string() } else { format!("{table}.{key}") })
+            })
+            .collect();
+        let mut paths = Vec::new();
+        key_paths(&toml::Value::try_from(value).unwrap(), "", &tables, &mut paths);
+        paths.retain(|path| !free_tables.iter().any(|free| path == free || path.starts_with(&format!("{free}."))));
+        paths.sort();
+        paths.dedup();
+        documented.sort();
+        assert_eq!(paths, documented);
+    }
+
+    #[test]
+    fn config_example() {
+        let toml = SchemaKind::Config.example_toml();
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert_documented(SchemaKind::Config, &config, &["legal", "exchange_rates", "recipient"]);
+        assert!(toml.contains("# Hourly rate of recipients without a rate\ndefault_rate = 100.0\n"));
+    }
+
+    #[test]
+    fn recipient_example() {
+        let toml = SchemaKind::Recipient.example_toml();
+        let recipient: Recipient = toml::from_str(&toml).unwrap();
+        assert_documented(SchemaKind::Recipient, &recipient, &["custom", "tags"]);
+
+        // The template of new recipients lists the same fields, only the required ones are set
+        let template = recipient_template("ClientA");
+        let recipient: Recipient = toml::from_str(&template).unwrap();
+        assert_eq!(recipient.contact().fullname(), "Jane Doe");
+        assert!(recipient.default_rate().is_none());
+        assert!(recipient.tags().contains_key("ClientA"));
+        // Tags which are no bare keys are quoted
+        for name in ["ACME Ltd.", "Müller & Söhne", "say \"hi\""] {
+            let recipient: Recipient = toml::from_str(&recipient_template(name)).unwrap();
+            assert!(recipient.tags().contains_key(name), "{name}");
+        }
+
+        for line in toml.lines().skip_while(|line| !line.is_empty()).filter(|line| line.starts_with('#')) {
+            assert!(template.contains(line), "{line} is missing in the template");
+        }
+    }
+
+    #[test]
+    fn locale_example() {
+        let toml = SchemaKind::Locale.example_toml();
+        let locale: Locale = toml::from_str(&toml).unwrap();
+        assert_eq!(locale.translation("invoice").unwrap(), "Invoice");
+        for (name, _) in locale.iter() {
+            assert!(["name", "rounding", "translations", "units"].contains(&name) || toml.contains(&format!("\n{name} = ")), "{name} is not documented");
+        }
+    }
+}
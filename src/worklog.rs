@@ -1,23 +1,88 @@
 
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap};
 
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use crate::helpers::DateTime;
+use crate::locale::Currency;
+use crate::error::InvoicerError;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct WorklogRecord {
-    #[serde(rename = "Tags", deserialize_with = "deserialize_tags")]
+    #[serde(rename = "Tags", default, deserialize_with = "deserialize_tags")]
     pub tags: Option<HashSet<String>>,
     #[serde(rename = "Start")]
     pub start: String,
-    #[serde(rename = "Hours")]
+    #[serde(rename = "Hours", deserialize_with = "deserialize_hours")]
     pub hours: f32,
-    #[serde(rename = "Rate")]
-    pub rate: Option<f32>,
+    #[serde(rename = "Rate", default)]
+    pub rate: Option<Rate>,
     #[serde(rename = "Message")]
-    pub message: String
+    pub message: String,
+    /// Unit of `hours`, e.g. `pcs` for a deliverable, hours if empty
+    #[serde(rename = "Unit", default)]
+    pub unit: Option<String>,
+    #[serde(rename = "Billable", default = "billable_default", deserialize_with = "deserialize_billable")]
+    pub billable: bool,
+    #[serde(skip)]
+    pub weights: HashMap<String, f32>,
 }
 
+/// Format of the `Start` column, e.g. `10/04/2023 09:00`.
+pub const START_FORMAT: &str = "%m/%d/%Y %H:%M";
+
+/// Unit of records without `Unit` column, their amount is in hours.
+pub const HOURS_UNIT: &str = "h";
+
+/// Tag marking a record as non-billable, like `no` in the `Billable` column.
+pub const NONBILLABLE_TAG: &str = "nonbillable";
+
+fn billable_default() -> bool {
+    true
+}
+
+/// Read the optional `Billable` column: `yes`, `true`, `1` or empty are billable, `no`, `false` or `0` are not.
+fn deserialize_billable<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where D: Deserializer<'de> {
+    struct BillableVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for BillableVisitor {
+        type Value = bool;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a boolean like yes, no, true, false, 1 or 0")
+        }
+
+        fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<bool, E> {
+            Ok(v)
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<bool, E> {
+            Ok(v != 0)
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<bool, E> {
+            Ok(v != 0)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<bool, E> {
+            match v.trim().to_lowercase().as_str() {
+                "" | "yes" | "y" | "true" | "1" => Ok(true),
+                "no" | "n" | "false" | "0" => Ok(false),
+                _ => Err(E::custom(format!("Invalid billable value '{v}', expected yes or no"))),
+            }
+        }
+
+        fn visit_unit<E: serde::de::Error>(self) -> Result<bool, E> {
+            Ok(true)
+        }
+    }
+
+    deserializer.deserialize_any(BillableVisitor)
+}
+
+/// Read comma-separated tags, trimming whitespace and skipping empty tags, e.g. of `a,,b,`.
+/// The case is kept, `case_insensitive_tags` in the config decides how tags are compared.
+/// A cell without tags is `None`.
 fn deserialize_tags<'de, D>(deserializer: D) -> Result<Option<HashSet<String>>, D::Error>
 where D: Deserializer<'de> {
     let buf = String::deserialize(deserializer);
@@ -26,23 +91,453 @@ where D: Deserializer<'de> {
     }
     let buf = buf.unwrap();
 
-    let s = buf.split(",").map(|ss| ss.trim().to_string() ).collect::<HashSet<String>>();
-    Ok(Some(s))
+    let s = buf.split(",")
+        .map(|ss| ss.trim().to_string())
+        .filter(|ss| !ss.is_empty())
+        .collect::<HashSet<String>>();
+    Ok(if s.is_empty() { None } else { Some(s) })
+}
+
+/// Parse a formatted number like `$120`, `1,200.50`, `1.200,50` or `120,5`.
+/// Whitespace and currency symbols around the number are removed, as well as spaces and `'` used for grouping.
+/// If both `.` and `,` occur, the last one is the decimal separator.
+/// Otherwise a separator occurring once is the decimal separator and one occurring several times groups digits.
+/// A formatted number with a single separator followed by exactly three digits, like `1,200` or `1.200 EUR`,
+/// is ambiguous and rejected, the decimal separator has to be set with `decimal` in the `[worklog]` section then.
+/// Unformatted numbers like `1.125` are decimals.
+pub fn parse_number(s: &str) -> Result<f32, String> {
+    if is_ambiguous_number(s) {
+        return Err(format!("Ambiguous number '{}', set `decimal` in the [worklog] section", s.trim()));
+    }
+    let number = number_part(s);
+    let invalid = || format!("Invalid number '{}'", s.trim());
+    let decimal = match (number.rfind('.'), number.rfind(',')) {
+        (Some(dot), Some(comma)) => if dot > comma { '.' } else { ',' },
+        (Some(_), None) => '.',
+        (None, Some(_)) => ',',
+        (None, None) => return number.parse().map_err(|_| invalid()),
+    };
+    let number = if number.matches(decimal).count() > 1 {
+        number.replace(['.', ','], "")
+    } else {
+        number.replace(|c| (c == '.' || c == ',') && c != decimal, "").replace(decimal, ".")
+    };
+    number.parse().map_err(|_| invalid())
+}
+
+/// The number in a formatted string, without surrounding currency symbols and without grouping spaces or `'`.
+fn number_part(s: &str) -> String {
+    s.trim_matches(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '+')))
+        .replace([' ', '\u{a0}', '\u{202f}', '\''], "")
+}
+
+/// A single `.` or `,` followed by exactly three digits, like `1,200`, is either a decimal or a grouping separator.
+/// Unformatted numbers like `1.125` are not ambiguous, neither are numbers like `0,125`,
+/// since grouped digits never follow a leading zero.
+fn is_ambiguous_number(s: &str) -> bool {
+    if s.trim().parse::<f32>().is_ok() {
+        return false;
+    }
+    let number = number_part(s);
+    let mut parts = number.split(['.', ',']);
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(integer), Some(fraction), None) => {
+            let integer = integer.trim_start_matches(['-', '+']);
+            !integer.is_empty() && integer != "0" && fraction.len() == 3 && fraction.chars().all(|c| c.is_ascii_digit())
+        }
+        _ => false,
+    }
+}
+
+/// Normalize a number with the given decimal separator to a plain number like `1200.5`,
+/// removing `.`, `,`, spaces and `'` used for grouping. Other characters, e.g. a currency, are kept.
+/// A zero is appended to the decimals, so `1,125 EUR` becomes `1.1250 EUR` and is not taken as ambiguous by `parse_number`.
+fn normalize_number(s: &str, decimal: char) -> String {
+    let mut number = s.chars()
+        .filter(|c| *c == decimal || !matches!(c, '.' | ',' | ' ' | '\u{a0}' | '\u{202f}' | '\''))
+        .map(|c| if c == decimal { '.' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string();
+    if let Some(point) = number.find('.') {
+        let end = number[point + 1..].find(|c: char| !c.is_ascii_digit()).map_or(number.len(), |i| point + 1 + i);
+        number.insert(end, '0');
+    }
+    number
+}
+
+/// Read the `Hours` column, numbers can be formatted, e.g. `1,5`, see `parse_number`.
+fn deserialize_hours<'de, D>(deserializer: D) -> Result<f32, D::Error>
+where D: Deserializer<'de> {
+    struct HoursVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for HoursVisitor {
+        type Value = f32;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a number of hours, e.g. 1.5 or 1,5")
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<f32, E> {
+            Ok(v as f32)
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<f32, E> {
+            Ok(v as f32)
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<f32, E> {
+            Ok(v as f32)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<f32, E> {
+            parse_number(v).map_err(|e| E::custom(format!("Invalid hours: {e}")))
+        }
+    }
+
+    deserializer.deserialize_any(HoursVisitor)
+}
+
+/// Hourly rate of a worklog record, e.g. `120` or `120EUR`.
+/// Formatted numbers like `$120` or `1.200,00 EUR` are read with `parse_number`, a currency symbol is ignored.
+/// A rate without currency suffix is in the invoice currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub value: f32,
+    pub currency: Option<Currency>,
+}
+
+impl std::str::FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let number = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let currency = s[number.len()..].to_uppercase();
+
+        let value = parse_number(number).map_err(|e| format!("Invalid rate: {e}"))?;
+        Ok(Self {
+            value,
+            currency: if currency.is_empty() { None } else { Some(Currency::from_str(currency)) }
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de> {
+        struct RateVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for RateVisitor {
+            type Value = Rate;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a number with an optional currency suffix, e.g. 120 or 120EUR")
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Rate, E> {
+                Ok(Rate { value: v as f32, currency: None })
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Rate, E> {
+                self.visit_f64(v as f64)
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Rate, E> {
+                self.visit_f64(v as f64)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Rate, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(RateVisitor)
+    }
+}
+
+/// Column names of a worklog CSV which differ from the default names `Tags`, `Start`, `Hours`, `Rate` and `Message`,
+/// `[worklog.columns]` section in the config.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ColumnMapping {
+    tags: Option<String>,
+    start: Option<String>,
+    hours: Option<String>,
+    rate: Option<String>,
+    message: Option<String>,
 }
 
+impl ColumnMapping {
+    pub fn new(tags: Option<&str>, start: Option<&str>, hours: Option<&str>, rate: Option<&str>, message: Option<&str>) -> Self {
+        let name = |name: Option<&str>| name.map(|name| name.to_string());
+        Self {
+            tags: name(tags),
+            start: name(start),
+            hours: name(hours),
+            rate: name(rate),
+            message: name(message),
+        }
+    }
+
+    /// Replace the mapped column names of a header row by the default names.
+    /// Other columns are kept as they are.
+    pub fn map_headers(&self, headers: &csv::StringRecord) -> csv::StringRecord {
+        let columns = [
+            (&self.tags, "Tags"),
+            (&self.start, "Start"),
+            (&self.hours, "Hours"),
+            (&self.rate, "Rate"),
+            (&self.message, "Message"),
+        ];
+        headers.iter()
+            .map(|header| columns.iter()
+                .find(|(mapped, _)| mapped.as_deref() == Some(header))
+                .map_or(header, |(_, default)| default))
+            .collect()
+    }
+}
+
+/// Settings for reading worklogs, `[worklog]` section in the config.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WorklogConfig {
+    delimiter: Option<char>,
+    quote: Option<char>,
+    comment: Option<String>,
+    flexible: Option<bool>,
+    case_insensitive_tags: Option<bool>,
+    decimal: Option<char>,
+    #[serde(default)]
+    columns: ColumnMapping,
+}
+
+impl WorklogConfig {
+    /// CSV field delimiter, `,` by default.
+    pub fn delimiter(&self) -> char {
+        self.delimiter.unwrap_or(',')
+    }
+
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = Some(delimiter);
+    }
+
+    /// CSV quote character, `"` by default.
+    pub fn quote(&self) -> char {
+        self.quote.unwrap_or('"')
+    }
+
+    /// Lines starting with this character are comments and skipped, `#` by default.
+    /// An empty `comment` turns comments off, so lines starting with `#` are records.
+    pub fn comment(&self) -> Option<char> {
+        match &self.comment {
+            Some(comment) => comment.chars().next(),
+            None => Some('#'),
+        }
+    }
+
+    /// Allow records with a varying number of fields.
+    pub fn flexible(&self) -> bool {
+        self.flexible.unwrap_or(false)
+    }
+
+    /// Match worklog tags and recipient tags regardless of their case.
+    pub fn case_insensitive_tags(&self) -> bool {
+        self.case_insensitive_tags.unwrap_or(false)
+    }
+
+    pub fn set_case_insensitive_tags(&mut self, case_insensitive_tags: bool) {
+        self.case_insensitive_tags = Some(case_insensitive_tags);
+    }
+
+    /// Decimal separator of the `Hours` and `Rate` columns, guessed for each number if unset.
+    pub fn decimal(&self) -> Option<char> {
+        self.decimal
+    }
+
+    pub fn set_decimal(&mut self, decimal: char) {
+        self.decimal = Some(decimal);
+    }
+
+    /// Copy with all settings that have a default set to their effective value.
+    pub fn with_defaults(&self) -> Self {
+        Self {
+            delimiter: Some(self.delimiter()),
+            quote: Some(self.quote()),
+            comment: Some(self.comment().map(String::from).unwrap_or_default()),
+            flexible: Some(self.flexible()),
+            case_insensitive_tags: Some(self.case_insensitive_tags()),
+            decimal: self.decimal,
+            columns: self.columns.clone(),
+        }
+    }
+
+    /// Column names of the worklog CSVs.
+    pub fn columns(&self) -> &ColumnMapping {
+        &self.columns
+    }
+
+    pub fn set_columns(&mut self, columns: ColumnMapping) {
+        self.columns = columns;
+    }
+
+    fn csv_reader_builder(&self) -> Result<csv::ReaderBuilder, InvoicerError> {
+        fn ascii(c: char, name: &str) -> Result<u8, InvoicerError> {
+            if c.is_ascii() {
+                Ok(c as u8)
+            } else {
+                Err(InvoicerError::InvalidConfig(format!("CSV {name} '{c}' must be an ASCII character")))
+            }
+        }
+
+        let comment = match self.comment.as_deref() {
+            Some(comment) if comment.chars().count() > 1 =>
+                return Err(InvoicerError::InvalidConfig(format!("CSV comment '{comment}' must be a single character or empty"))),
+            _ => self.comment().map(|c| ascii(c, "comment")).transpose()?,
+        };
+
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .delimiter(ascii(self.delimiter(), "delimiter")?)
+            .quote(ascii(self.quote(), "quote")?)
+            .comment(comment)
+            .flexible(self.flexible())
+            .trim(csv::Trim::Headers);
+        Ok(builder)
+    }
+}
+
+/// Compare two tags, optionally ignoring their case.
+pub fn tags_match(a: &str, b: &str, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        a.to_lowercase() == b.to_lowercase()
+    } else {
+        a == b
+    }
+}
+
+/// UTF-8 byte order mark, written by Excel on Windows.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Skip a leading UTF-8 byte order mark, which would otherwise become part of the first header name.
+fn skip_bom(reader: impl std::io::Read) -> std::io::Result<impl std::io::Read> {
+    use std::io::BufRead;
+
+    let mut reader = std::io::BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(BOM) {
+        reader.consume(BOM.len());
+    }
+    Ok(reader)
+}
+
+/// Tolerance for the sum of tag weights of a record.
+const WEIGHT_EPSILON: f32 = 1.0e-3;
+
 impl WorklogRecord {
+    /// Create a billable record starting at `start`.
+    ///
+    /// ```
+    /// use invoicer::worklog::{Worklog, WorklogRecord};
+    ///
+    /// let start = chrono::NaiveDate::from_ymd_opt(2023, 10, 4).unwrap().and_hms_opt(9, 0, 0).unwrap();
+    /// let record = WorklogRecord::new(start, 2.5, "Development", &["ClientA"], None);
+    /// assert_eq!(record.start, "10/04/2023 09:00");
+    ///
+    /// let worklog = Worklog::from_records(vec![record]);
+    /// assert_eq!(worklog.total_hours(), 2.5);
+    /// assert!(worklog.tags().contains("ClientA"));
+    /// ```
+    pub fn new(start: DateTime, hours: f32, message: &str, tags: &[&str], rate: Option<Rate>) -> Self {
+        let tags: HashSet<String> = tags.iter()
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect();
+
+        Self {
+            tags: (!tags.is_empty()).then_some(tags),
+            start: start.format(START_FORMAT).to_string(),
+            hours,
+            rate,
+            message: message.to_string(),
+            unit: None,
+            billable: true,
+            weights: HashMap::new(),
+        }
+    }
+
+    /// Extract weighted tags like `ClientA:0.6` into the `weights` map.
+    /// The weight suffix is removed from the tag, so `ClientA:0.6` becomes `ClientA`.
+    /// Returns an error if the weights of a record do not sum up to 1.0.
+    pub fn parse_tag_weights(&mut self) -> Result<(), InvoicerError> {
+        let tags = match &self.tags {
+            Some(tags) => tags.clone(),
+            None => return Ok(())
+        };
+
+        let mut plain_tags = HashSet::new();
+        for tag in tags {
+            match tag.rsplit_once(':').map(|(name, weight)| (name.trim(), weight.trim().parse::<f32>())) {
+                Some((name, Ok(weight))) => {
+                    self.weights.insert(name.to_string(), weight);
+                    plain_tags.insert(name.to_string());
+                }
+                _ => {
+                    plain_tags.insert(tag);
+                }
+            }
+        }
+        self.tags = Some(plain_tags);
+
+        if !self.weights.is_empty() {
+            let sum: f32 = self.weights.values().sum();
+            if (sum - 1.0).abs() > WEIGHT_EPSILON {
+                return Err(InvoicerError::InvalidWorklog(
+                    format!("Tag weights of record '{} {}' sum up to {sum}, expected 1.0", self.start, self.message)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Weight of a tag, 1.0 for unweighted tags.
+    pub fn weight(&self, tag: &str) -> f32 {
+        self.weights.get(tag).copied().unwrap_or(1.0)
+    }
+
     pub fn begin_date(&self) -> DateTime {
-        DateTime::parse_from_str(&self.start, "%m/%d/%Y %H:%M").unwrap()
+        DateTime::parse_from_str(&self.start, START_FORMAT).unwrap()
     }
 
+    /// End of the record, the begin date for records which are not in hours.
     pub fn end_date(&self) -> DateTime {
         let mut date = self.begin_date();
-        date += chrono::Duration::seconds((60.0 * 60.0 * self.hours) as i64);
+        if self.is_hours() {
+            date += chrono::Duration::seconds((60.0 * 60.0 * self.hours) as i64);
+        }
         date
     }
 
+    /// Unit of the record, `h` if the `Unit` column is missing or empty.
+    pub fn unit(&self) -> &str {
+        self.unit.as_deref().map(str::trim).filter(|unit| !unit.is_empty()).unwrap_or(HOURS_UNIT)
+    }
+
+    /// Whether the amount of the record is in hours and not a quantity of another unit.
+    pub fn is_hours(&self) -> bool {
+        self.unit() == HOURS_UNIT
+    }
+
     pub fn net(&self) -> f32 {
-        self.hours * self.rate.unwrap_or_default()
+        self.hours * self.rate_value().unwrap_or_default()
+    }
+
+    /// Rate of the record without currency.
+    pub fn rate_value(&self) -> Option<f32> {
+        self.rate.as_ref().map(|rate| rate.value)
+    }
+
+    /// Currency of the record's rate, `None` if it is in the invoice currency.
+    pub fn currency(&self) -> Option<&Currency> {
+        self.rate.as_ref().and_then(|rate| rate.currency.as_ref())
     }
 
     pub fn tags(&self) -> HashSet<String> {
@@ -58,6 +553,17 @@ impl WorklogRecord {
             None => false
         }
     }
+
+    /// Whether the record is invoiced.
+    /// Records with `no` in the `Billable` column or the tag `nonbillable` are not.
+    pub fn is_billable(&self) -> bool {
+        self.billable && self.find_tag(NONBILLABLE_TAG, true).is_none()
+    }
+
+    /// Tag of the record matching `tag`, optionally ignoring the case.
+    pub fn find_tag(&self, tag: &str, case_insensitive: bool) -> Option<&String> {
+        self.tags.as_ref()?.iter().find(|t| tags_match(t, tag, case_insensitive))
+    }
 }
 
 
@@ -80,34 +586,97 @@ impl Worklog {
         }
     }
 
-    pub fn from_csv(reader: impl std::io::Read) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut rdr = csv::ReaderBuilder::new()
-            .from_reader(reader);
+    /// Create a worklog from records constructed in code, e.g. with [`WorklogRecord::new`].
+    pub fn from_records(records: Vec<WorklogRecord>) -> Self {
+        let mut worklog = Self::new();
+        for record in records {
+            worklog.add_record(record);
+        }
+        worklog
+    }
+
+    pub fn from_csv(reader: impl std::io::Read) -> Result<Self, InvoicerError> {
+        Self::from_csv_with_config(reader, &WorklogConfig::default())
+    }
+
+    pub fn from_csv_with_config(reader: impl std::io::Read, config: &WorklogConfig) -> Result<Self, InvoicerError> {
+        let mut rdr = config.csv_reader_builder()?
+            .from_reader(skip_bom(reader)?);
+        let headers = config.columns().map_headers(rdr.headers()?);
+        rdr.set_headers(headers.clone());
+        let numbers: Vec<usize> = headers.iter().enumerate()
+            .filter(|(_, header)| *header == "Hours" || *header == "Rate")
+            .map(|(index, _)| index)
+            .collect();
         let mut worklog = Self::new();
 
-        for result in rdr.deserialize() {
-            // Notice that we need to provide a type hint for automatic
-            // deserialization.
-            let record: WorklogRecord = result?;
+        for result in rdr.records() {
+            let row = result?;
+            let mut record: WorklogRecord = match config.decimal() {
+                Some(decimal) => {
+                    let mut normalized: csv::StringRecord = row.iter().enumerate()
+                        .map(|(index, field)| if numbers.contains(&index) { normalize_number(field, decimal) } else { field.to_string() })
+                        .collect();
+                    normalized.set_position(row.position().cloned());
+                    normalized.deserialize(Some(&headers))?
+                }
+                None => row.deserialize(Some(&headers))?,
+            };
+            record.parse_tag_weights()?;
             worklog.add_record(record);
         }
 
         Ok(worklog)
     }
 
-    pub fn from_csv_file(filename: &str)  -> Result<Self, Box<dyn std::error::Error>> {
+    /// Read a worklog CSV whose column names differ from the default names.
+    pub fn from_csv_with_mapping(reader: impl std::io::Read, columns: &ColumnMapping) -> Result<Self, InvoicerError> {
+        let mut config = WorklogConfig::default();
+        config.set_columns(columns.clone());
+        Self::from_csv_with_config(reader, &config)
+    }
+
+    /// Read a worklog from a JSON array of records.
+    /// The records use the same field names as the CSV columns.
+    pub fn from_json(reader: impl std::io::Read) -> Result<Self, InvoicerError> {
+        let records: Vec<WorklogRecord> = serde_json::from_reader(reader)?;
+        let mut worklog = Self::new();
+
+        for mut record in records {
+            record.parse_tag_weights()?;
+            worklog.add_record(record);
+        }
+
+        Ok(worklog)
+    }
+
+    pub fn from_csv_file(filename: &str)  -> Result<Self, InvoicerError> {
+        Self::from_csv_file_with_config(filename, &WorklogConfig::default())
+    }
+
+    pub fn from_csv_file_with_config(filename: &str, config: &WorklogConfig)  -> Result<Self, InvoicerError> {
         use std::io::BufReader;
-        let file = std::fs::File::open(&filename)?;
+        let file = std::fs::File::open(filename)?;
         let buf_reader = BufReader::new(file);
-        Self::from_csv(buf_reader)
+        Self::from_csv_with_config(buf_reader, config)
     }
 
+    /// Create a worklog from all records with the given tag.
+    /// Hours of weighted tags are allocated proportionally.
     pub fn from_records_with_tag(&self, tag: &str) -> Self {
+        self.from_records_matching_tag(tag, false)
+    }
+
+    /// Create a worklog from all records with the given tag, optionally ignoring the case of the tags.
+    pub fn from_records_matching_tag(&self, tag: &str, case_insensitive: bool) -> Self {
         let mut worklog = Worklog::new();
 
         for record in self.records() {
-            if record.has_tag(tag) {
-                worklog.add_record(record.clone());
+            if let Some(record_tag) = record.find_tag(tag, case_insensitive) {
+                let weight = record.weight(record_tag);
+                let mut record = record.clone();
+                record.hours *= weight;
+                worklog.add_record(record);
             }
         }
 
@@ -122,6 +691,7 @@ impl Worklog {
         self.rate = rate;
     }
 
+    /// Tags of all records, without the `nonbillable` marker tag.
     pub fn tags(&self) -> &HashSet<String> {
         &self.tags
     }
@@ -129,7 +699,7 @@ impl Worklog {
     pub fn add_record(&mut self, record: WorklogRecord) {
         self.begin_date = record.begin_date().min(self.begin_date);
         self.end_date = record.end_date().max(self.end_date);
-        self.tags.extend(record.tags());
+        self.tags.extend(record.tags().into_iter().filter(|tag| !tags_match(tag, NONBILLABLE_TAG, true)));
 
         self.records.push(record);
     }
@@ -148,6 +718,16 @@ impl Worklog {
         sum
     }
 
+    /// Sum of the hours of all records, records in other units are not included.
+    pub fn total_hours(&self) -> f32 {
+        self.records.iter().filter(|record| record.is_hours()).map(|record| record.hours).sum()
+    }
+
+    /// Sum of the hours of all billable records, records in other units are not included.
+    pub fn billable_hours(&self) -> f32 {
+        self.records.iter().filter(|record| record.is_billable() && record.is_hours()).map(|record| record.hours).sum()
+    }
+
     pub fn sort(&mut self) {
         self.records.sort_by_key(|r| r.begin_date());
     }
@@ -174,4 +754,285 @@ impl Default for Worklog {
     fn default() -> Self {
         Worklog::new()
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Worklog, WorklogConfig, WorklogRecord, tags_match};
+    use crate::error::InvoicerError;
+    use crate::locale::Currency;
+
+    #[test]
+    fn split_weighted_tags() {
+        let csv = r#""Tags","Start","Hours","Message"
+"ClientA:0.6,ClientB:0.4,dev","10/04/2023 14:00",10,"Shared work"
+"ClientA,dev","10/05/2023 14:00",2,"Own work"
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        assert!(worklog.tags().contains("ClientA"));
+        assert!(!worklog.tags().contains("ClientA:0.6"));
+
+        let a = worklog.from_records_with_tag("ClientA");
+        assert_eq!(a.len(), 2);
+        assert!((a.records()[0].hours - 6.0).abs() < 1.0e-4);
+        assert!((a.records()[1].hours - 2.0).abs() < 1.0e-4);
+
+        let b = worklog.from_records_with_tag("ClientB");
+        assert_eq!(b.len(), 1);
+        assert!((b.records()[0].hours - 4.0).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn tags_whitespace_and_empty() {
+        let csv = r#"Tags,Start,Hours,Message
+"a,,b,",10/04/2023 14:00,2,Review
+" A , b ",10/05/2023 09:00,1,Setup
+"",10/06/2023 09:00,1,Admin
+" , ",10/07/2023 09:00,1,Admin
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        let records = worklog.records();
+        assert_eq!(records[0].tags, Some(std::collections::HashSet::from(["a".to_string(), "b".to_string()])));
+        assert_eq!(records[1].tags, Some(std::collections::HashSet::from(["A".to_string(), "b".to_string()])));
+        assert_eq!(records[2].tags, None);
+        assert_eq!(records[3].tags, None);
+        assert!(!worklog.tags().contains(""));
+
+        assert!(records[1].find_tag("a", false).is_none());
+        assert_eq!(records[1].find_tag("a", true), Some(&"A".to_string()));
+    }
+
+    #[test]
+    fn from_records() {
+        let csv = r#"Tags,Start,Hours,Rate,Message
+ClientA,10/04/2023 14:00,2,120EUR,Review
+"#;
+        let parsed = Worklog::from_csv(csv.as_bytes()).unwrap();
+        let start = chrono::NaiveDate::from_ymd_opt(2023, 10, 4).unwrap().and_hms_opt(14, 0, 0).unwrap();
+        let rate = "120EUR".parse().unwrap();
+        let worklog = Worklog::from_records(vec![WorklogRecord::new(start, 2.0, "Review", &["ClientA", " "], Some(rate))]);
+
+        let (a, b) = (&parsed.records()[0], &worklog.records()[0]);
+        assert_eq!(a.start, b.start);
+        assert_eq!(a.tags, b.tags);
+        assert_eq!(a.rate, b.rate);
+        assert_eq!(worklog.begin_date(), start);
+        assert_eq!(worklog.sum(), parsed.sum());
+    }
+
+    #[test]
+    fn billable() {
+        let csv = r#"Tags,Start,Hours,Message,Billable
+ClientA,10/04/2023 14:00,2,Review,yes
+ClientA,10/05/2023 09:00,1,Internal meeting,no
+ClientA,10/06/2023 09:00,1.5,Setup,
+"ClientA,nonbillable",10/07/2023 09:00,0.5,Pro-bono,
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        let billable = worklog.records().iter().map(|record| record.is_billable()).collect::<Vec<_>>();
+        assert_eq!(billable, vec![true, false, true, false]);
+        assert_eq!(worklog.tags().iter().collect::<Vec<_>>(), ["ClientA"]);
+        assert!((worklog.billable_hours() - 3.5).abs() < 1.0e-4);
+        assert!((worklog.total_hours() - 5.0).abs() < 1.0e-4);
+
+        let invalid = "Tags,Start,Hours,Message,Billable\nClientA,10/04/2023 14:00,2,Review,maybe\n";
+        assert!(Worklog::from_csv(invalid.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn column_mapping() {
+        use super::ColumnMapping;
+        let csv = r#"Project,Date,Duration,Description,Billable
+ClientA,10/04/2023 14:00,2,Review,yes
+ClientA,10/05/2023 09:00,1.5,Setup,yes
+"#;
+        let columns = ColumnMapping::new(Some("Project"), Some("Date"), Some("Duration"), None, Some("Description"));
+        let worklog = Worklog::from_csv_with_mapping(csv.as_bytes(), &columns).unwrap();
+        assert_eq!(worklog.len(), 2);
+        assert!(worklog.tags().contains("ClientA"));
+        assert_eq!(worklog.records()[1].start, "10/05/2023 09:00");
+        assert_eq!(worklog.records()[1].message, "Setup");
+        assert!((worklog.total_hours() - 3.5).abs() < 1.0e-4);
+
+        let config: WorklogConfig = toml::from_str(r#"
+[columns]
+start = "Date"
+hours = "Duration"
+message = "Description"
+"#).unwrap();
+        let worklog = Worklog::from_csv_with_config(csv.as_bytes(), &config).unwrap();
+        assert!(worklog.records().iter().all(|record| record.tags.is_none()));
+
+        // Without mapping, the default names are required
+        assert!(Worklog::from_csv(csv.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn without_tags_column() {
+        let csv = r#"Start,Hours,Rate,Message
+10/04/2023 14:00,2,120,Review
+10/05/2023 09:00,1.5,,Setup
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(worklog.len(), 2);
+        assert!(worklog.records().iter().all(|record| record.tags.is_none()));
+        assert!((worklog.total_hours() - 3.5).abs() < 1.0e-4);
+        assert_eq!(worklog.records()[0].rate_value(), Some(120.0));
+    }
+
+    #[test]
+    fn invalid_tag_weights() {
+        let csv = r#""Tags","Start","Hours","Message"
+"ClientA:0.6,ClientB:0.6","10/04/2023 14:00",10,"Shared work"
+"#;
+        assert!(matches!(Worklog::from_csv(csv.as_bytes()), Err(InvoicerError::InvalidWorklog(_))));
+    }
+
+    #[test]
+    fn semicolon_delimiter() {
+        let csv = r#""Tags";"Start";"Hours";"Rate";"Message"
+"ClientA,dev";"10/04/2023 14:00";3;100;"Review; Discussion"
+"#;
+        let mut config = WorklogConfig::default();
+        config.set_delimiter(';');
+
+        let worklog = Worklog::from_csv_with_config(csv.as_bytes(), &config).unwrap();
+        assert_eq!(worklog.len(), 1);
+        assert!(worklog.records()[0].has_tag("dev"));
+        assert_eq!(worklog.records()[0].message, "Review; Discussion");
+
+        assert!(matches!(Worklog::from_csv(csv.as_bytes()), Err(InvoicerError::Csv(_))));
+    }
+
+    #[test]
+    fn comment_lines() {
+        let csv = r#""Tags","Start","Hours","Message"
+# monthly export
+"ClientA","10/04/2023 14:00",2,"Review"
+# Changes requested by ClientA
+"ClientA","10/05/2023 09:00",1.5,"Setup #2"
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(worklog.len(), 2);
+        assert_eq!(worklog.records()[1].message, "Setup #2");
+
+        let config: WorklogConfig = toml::from_str("comment = \"%\"").unwrap();
+        assert!(Worklog::from_csv_with_config(csv.as_bytes(), &config).is_err());
+        let worklog = Worklog::from_csv_with_config(csv.replace('#', "%").as_bytes(), &config).unwrap();
+        assert_eq!(worklog.len(), 2);
+
+        // An empty comment character turns comments off
+        let config: WorklogConfig = toml::from_str("comment = \"\"").unwrap();
+        let csv = "Tags,Start,Hours,Message\n#urgent,10/04/2023 14:00,2,Review\n";
+        let worklog = Worklog::from_csv_with_config(csv.as_bytes(), &config).unwrap();
+        assert!(worklog.records()[0].has_tag("#urgent"));
+        assert_eq!(Worklog::from_csv(csv.as_bytes()).unwrap().len(), 0);
+
+        let config: WorklogConfig = toml::from_str("comment = \"//\"").unwrap();
+        assert!(matches!(Worklog::from_csv_with_config(csv.as_bytes(), &config), Err(InvoicerError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn rate_with_currency() {
+        let csv = r#""Tags","Start","Hours","Rate","Message"
+"ClientA","10/04/2023 14:00",3,120,"Plain rate"
+"ClientA","10/05/2023 14:00",2,120EUR,"Rate with currency"
+"ClientA","10/06/2023 14:00",1,,"No rate"
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        let records = worklog.records();
+
+        assert_eq!(records[0].rate_value(), Some(120.0));
+        assert_eq!(records[0].currency(), None);
+
+        assert_eq!(records[1].rate_value(), Some(120.0));
+        assert_eq!(records[1].currency(), Some(&Currency::from_str("EUR".to_string())));
+
+        assert_eq!(records[2].rate_value(), None);
+
+        assert!("120 USD".parse::<super::Rate>().unwrap().currency == Some(Currency::from_str("USD".to_string())));
+        assert!("EUR".parse::<super::Rate>().is_err());
+    }
+
+    #[test]
+    fn formatted_numbers() {
+        use super::{parse_number, Rate};
+
+        assert_eq!("$120".parse::<Rate>().unwrap(), Rate { value: 120.0, currency: None });
+        assert_eq!("120,50".parse::<Rate>().unwrap().value, 120.5);
+        assert_eq!("120.5".parse::<Rate>().unwrap().value, 120.5);
+        assert_eq!("1.200,00 EUR".parse::<Rate>().unwrap(), Rate { value: 1200.0, currency: Some(Currency::from_str("EUR".to_string())) });
+        assert_eq!(parse_number("1,200.50"), Ok(1200.5));
+        assert_eq!(parse_number("1 234 567"), Ok(1234567.0));
+        assert_eq!(parse_number("1,234,567"), Ok(1234567.0));
+        assert_eq!(parse_number(" 120 € "), Ok(120.0));
+        assert_eq!(parse_number("0,125"), Ok(0.125));
+        assert!(parse_number("abc").is_err());
+        assert!(parse_number("1,200").is_err());
+        assert_eq!(parse_number("1.200"), Ok(1.2));
+        assert!(parse_number("$1.200").is_err());
+        assert_eq!("1.200EUR".parse::<Rate>().unwrap().value, 1.2);
+
+        let csv = r#"Tags,Start,Hours,Rate,Message
+ClientA,10/04/2023 14:00,"1,5","$120",Review
+ClientA,10/05/2023 14:00,2.25,"1.200,00 EUR",Development
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(worklog.records()[0].hours, 1.5);
+        assert_eq!(worklog.records()[0].rate_value(), Some(120.0));
+        assert_eq!(worklog.records()[1].hours, 2.25);
+        assert_eq!(worklog.records()[1].rate_value(), Some(1200.0));
+
+        // Ambiguous numbers like 1,200 need a configured decimal separator
+        let csv = "Tags,Start,Hours,Rate,Message\nClientA,10/04/2023 14:00,2,\"1,200\",Review\n";
+        assert!(Worklog::from_csv(csv.as_bytes()).err().unwrap().to_string().contains("Ambiguous number '1,200'"));
+        // Unformatted numbers are decimals as before
+        let plain = "Tags,Start,Hours,Rate,Message\nClientA,10/04/2023 14:00,1.125,120.000,Review\n";
+        let worklog = Worklog::from_csv(plain.as_bytes()).unwrap();
+        assert_eq!(worklog.records()[0].hours, 1.125);
+        assert_eq!(worklog.records()[0].rate_value(), Some(120.0));
+        let mut config = WorklogConfig::default();
+        config.set_decimal('.');
+        assert_eq!(Worklog::from_csv_with_config(csv.as_bytes(), &config).unwrap().records()[0].rate_value(), Some(1200.0));
+        assert_eq!(Worklog::from_csv_with_config(plain.as_bytes(), &config).unwrap().records()[0].hours, 1.125);
+        config.set_decimal(',');
+        assert_eq!(Worklog::from_csv_with_config(csv.as_bytes(), &config).unwrap().records()[0].rate_value(), Some(1.2));
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let csv = "\u{feff}\"Tags\",\" Start \",\"Hours\",\"Message\"\n\"ClientA,dev\",\"10/04/2023 14:00\",3,\"Review\"\n";
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        assert!(worklog.records()[0].has_tag("ClientA"));
+        assert!(worklog.records()[0].has_tag("dev"));
+    }
+
+    #[test]
+    fn case_insensitive_tags() {
+        let csv = r#""Tags","Start","Hours","Message"
+"clienta:0.5,ClientB:0.5","10/04/2023 14:00",4,"Shared work"
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(worklog.from_records_with_tag("ClientA").len(), 0);
+
+        let a = worklog.from_records_matching_tag("ClientA", true);
+        assert_eq!(a.len(), 1);
+        assert!((a.records()[0].hours - 2.0).abs() < 1.0e-4);
+
+        assert!(tags_match("ClientA", "clienta", true));
+        assert!(!tags_match("ClientA", "clienta", false));
+    }
+
+    #[test]
+    fn total_hours() {
+        let csv = r#""Tags","Start","Hours","Message"
+"ClientA:0.25,ClientB:0.75","10/04/2023 14:00",4,"Shared work"
+"ClientA","10/05/2023 14:00",1.5,"Review"
+"#;
+        let worklog = Worklog::from_csv(csv.as_bytes()).unwrap();
+        assert!((worklog.total_hours() - 5.5).abs() < 1.0e-4);
+        assert!((worklog.from_records_with_tag("ClientA").total_hours() - 2.5).abs() < 1.0e-4);
+        assert_eq!(Worklog::new().total_hours(), 0.0);
+    }
+}